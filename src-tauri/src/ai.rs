@@ -0,0 +1,1479 @@
+// بسم الله الرحمن الرحيم
+// Chat model providers: Gemini, OpenAI-compatible, Anthropic and Ollama behind one trait
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tauri::{self, AppHandle, Manager};
+use tokio::sync::oneshot;
+
+use crate::http::{self, AiError, HttpSettings};
+use crate::secrets::{SecretStore, DEFAULT_KEY_NAME};
+use crate::usage::{MeteredProvider, UsageLedger};
+
+pub type ProviderFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String, // "system", "user", "assistant"
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatResponse {
+    pub text: String,
+    pub usage: Option<TokenUsage>,
+}
+
+// Receives each piece of text as it arrives
+pub type DeltaSink<'a> = &'a (dyn Fn(&str) + Send + Sync);
+
+pub trait AiProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn model(&self) -> &str;
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>>;
+
+    // Providers without a streaming endpoint deliver the whole answer as one delta
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.chat(request).await?;
+            on_delta(&response.text);
+            Ok(response)
+        })
+    }
+}
+
+// How to reach a model; stored by profile name in ai_providers.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    // api_key is only read from older config files and is moved to the secret store on load;
+    // key_name picks one of several stored keys for the provider kind
+    Gemini {
+        model: String,
+        #[serde(default)]
+        base_url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key: Option<String>,
+        #[serde(default)]
+        key_name: Option<String>,
+    },
+    // Also covers llama.cpp's server and anything else speaking /v1/chat/completions
+    OpenAi {
+        model: String,
+        base_url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key: Option<String>,
+        #[serde(default)]
+        key_name: Option<String>,
+    },
+    Anthropic {
+        model: String,
+        #[serde(default)]
+        base_url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key: Option<String>,
+        #[serde(default)]
+        key_name: Option<String>,
+    },
+    Ollama {
+        model: String,
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+}
+
+impl ProviderConfig {
+    pub fn default_gemini() -> Self {
+        ProviderConfig::Gemini {
+            model: "gemini-2.0-flash".to_string(),
+            base_url: None,
+            api_key: None,
+            key_name: None,
+        }
+    }
+
+    // Secret store provider name for this kind of profile
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderConfig::Gemini { .. } => "gemini",
+            ProviderConfig::OpenAi { .. } => "openai",
+            ProviderConfig::Anthropic { .. } => "anthropic",
+            ProviderConfig::Ollama { .. } => "ollama",
+        }
+    }
+
+    fn key_slot(&mut self) -> Option<(&mut Option<String>, &mut Option<String>)> {
+        match self {
+            ProviderConfig::Gemini { api_key, key_name, .. }
+            | ProviderConfig::OpenAi { api_key, key_name, .. }
+            | ProviderConfig::Anthropic { api_key, key_name, .. } => Some((api_key, key_name)),
+            ProviderConfig::Ollama { .. } => None,
+        }
+    }
+
+    pub fn build(&self, fallback_key: &str) -> Result<Box<dyn AiProvider>, String> {
+        let provider: Box<dyn AiProvider> = match self {
+            ProviderConfig::Gemini { model, base_url, api_key, .. } => {
+                let api_key = api_key.clone().unwrap_or_else(|| fallback_key.to_string());
+                if api_key.is_empty() {
+                    return Err("No API key provided".to_string());
+                }
+                Box::new(GeminiProvider {
+                    base_url: base_url
+                        .clone()
+                        .unwrap_or_else(|| "https://generativelanguage.googleapis.com/v1beta".to_string()),
+                    model: model.clone(),
+                    api_key,
+                })
+            }
+            ProviderConfig::OpenAi { model, base_url, api_key, .. } => Box::new(OpenAiProvider {
+                base_url: base_url.clone(),
+                model: model.clone(),
+                api_key: api_key.clone().or_else(|| Some(fallback_key.to_string())),
+            }),
+            ProviderConfig::Anthropic { model, base_url, api_key, .. } => {
+                let api_key = api_key.clone().unwrap_or_else(|| fallback_key.to_string());
+                if api_key.is_empty() {
+                    return Err("No Anthropic API key configured".to_string());
+                }
+                Box::new(AnthropicProvider {
+                    base_url: base_url
+                        .clone()
+                        .unwrap_or_else(|| "https://api.anthropic.com/v1".to_string()),
+                    model: model.clone(),
+                    api_key,
+                })
+            }
+            ProviderConfig::Ollama { model, base_url } => Box::new(OllamaProvider {
+                base_url: base_url
+                    .clone()
+                    .unwrap_or_else(|| "http://localhost:11434".to_string()),
+                model: model.clone(),
+            }),
+        };
+        Ok(provider)
+    }
+}
+
+pub struct GeminiProvider {
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+impl GeminiProvider {
+    fn payload(&self, request: &ChatRequest) -> Result<serde_json::Value, String> {
+        let mut contents = Vec::new();
+        let mut system = Vec::new();
+
+        for message in &request.messages {
+            let role = match message.role.as_str() {
+                "user" => "user",
+                "assistant" => "model",
+                "system" => {
+                    system.push(serde_json::json!({ "text": message.content }));
+                    continue;
+                }
+                role => return Err(format!("Invalid role: {}", role)),
+            };
+            contents.push(serde_json::json!({
+                "role": role,
+                "parts": [{ "text": message.content }]
+            }));
+        }
+
+        let mut payload = serde_json::json!({
+            "contents": contents,
+            "generationConfig": {
+                "temperature": request.temperature.unwrap_or(0.7),
+                "topP": 0.95,
+                "topK": 40,
+                "maxOutputTokens": request.max_tokens.unwrap_or(4096)
+            }
+        });
+        if !system.is_empty() {
+            payload["systemInstruction"] = serde_json::json!({ "parts": system });
+        }
+        Ok(payload)
+    }
+
+    async fn post(&self, method: &str, query: &str, request: &ChatRequest, stream: bool) -> Result<reqwest::Response, AiError> {
+        let url = format!("{}/models/{}:{}{}", self.base_url, self.model, method, query);
+        let payload = self.payload(request).map_err(AiError::Config)?;
+        // The key goes in a header so it stays out of proxy and server logs
+        http::send(
+            |client| {
+                client
+                    .post(&url)
+                    .header("x-goog-api-key", &self.api_key)
+                    .json(&payload)
+            },
+            stream,
+        )
+        .await
+    }
+}
+
+fn gemini_text(json: &serde_json::Value) -> String {
+    json.pointer("/candidates/0/content/parts")
+        .and_then(|parts| parts.as_array())
+        .map(|parts| {
+            parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+                .collect::<String>()
+        })
+        .unwrap_or_default()
+}
+
+// Prompts can be blocked outright, or the answer cut off by a safety finish reason
+fn gemini_blocked(json: &serde_json::Value) -> Option<AiError> {
+    if let Some(reason) = json.pointer("/promptFeedback/blockReason").and_then(|r| r.as_str()) {
+        return Some(AiError::SafetyBlocked(format!("prompt blocked ({})", reason)));
+    }
+    match json.pointer("/candidates/0/finishReason").and_then(|r| r.as_str()) {
+        Some(reason @ ("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII")) => {
+            Some(AiError::SafetyBlocked(format!("response stopped ({})", reason)))
+        }
+        _ => None,
+    }
+}
+
+fn gemini_usage(json: &serde_json::Value) -> Option<TokenUsage> {
+    json.get("usageMetadata").map(|usage| TokenUsage {
+        input_tokens: usage.get("promptTokenCount").and_then(|n| n.as_u64()).unwrap_or(0),
+        output_tokens: usage.get("candidatesTokenCount").and_then(|n| n.as_u64()).unwrap_or(0),
+    })
+}
+
+impl AiProvider for GeminiProvider {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.post("generateContent", "", request, false).await?;
+            let json = http::read_json(response).await?;
+            if let Some(error) = gemini_blocked(&json) {
+                return Err(error);
+            }
+
+            let mut text = gemini_text(&json);
+            if text.is_empty() {
+                text = "Sorry, I couldn't generate a response.".to_string();
+            }
+
+            Ok(ChatResponse {
+                text,
+                usage: gemini_usage(&json),
+            })
+        })
+    }
+
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.post("streamGenerateContent", "?alt=sse", request, true).await?;
+
+            let mut text = String::new();
+            let mut usage = None;
+            for_each_line(response, |line| {
+                if let Some(json) = sse_json(line)? {
+                    if let Some(error) = gemini_blocked(&json) {
+                        return Err(error);
+                    }
+                    let delta = gemini_text(&json);
+                    if !delta.is_empty() {
+                        on_delta(&delta);
+                        text.push_str(&delta);
+                    }
+                    // Each chunk carries running totals; the last one wins
+                    if let Some(chunk_usage) = gemini_usage(&json) {
+                        usage = Some(chunk_usage);
+                    }
+                }
+                Ok(true)
+            })
+            .await?;
+
+            Ok(ChatResponse { text, usage })
+        })
+    }
+}
+
+pub struct OpenAiProvider {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiProvider {
+    async fn post(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::Response, AiError> {
+        let mut payload = serde_json::json!({
+            "model": self.model,
+            "messages": request.messages,
+        });
+        if let Some(temperature) = request.temperature {
+            payload["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            payload["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if stream {
+            payload["stream"] = serde_json::json!(true);
+            payload["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        http::send(
+            |client| {
+                let builder = client.post(&url).json(&payload);
+                // Local servers usually run without a key
+                match self.api_key.as_ref().filter(|k| !k.is_empty()) {
+                    Some(api_key) => builder.bearer_auth(api_key),
+                    None => builder,
+                }
+            },
+            stream,
+        )
+        .await
+    }
+}
+
+fn openai_usage(json: &serde_json::Value) -> Option<TokenUsage> {
+    json.get("usage").filter(|u| !u.is_null()).map(|usage| TokenUsage {
+        input_tokens: usage.get("prompt_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+        output_tokens: usage.get("completion_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+    })
+}
+
+fn openai_filtered(json: &serde_json::Value) -> Option<AiError> {
+    (json.pointer("/choices/0/finish_reason").and_then(|r| r.as_str()) == Some("content_filter"))
+        .then(|| AiError::SafetyBlocked("response stopped by the content filter".to_string()))
+}
+
+impl AiProvider for OpenAiProvider {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let json = http::read_json(self.post(request, false).await?).await?;
+            if let Some(error) = openai_filtered(&json) {
+                return Err(error);
+            }
+
+            let text = json
+                .pointer("/choices/0/message/content")
+                .and_then(|c| c.as_str())
+                .unwrap_or_default()
+                .to_string();
+
+            Ok(ChatResponse {
+                text,
+                usage: openai_usage(&json),
+            })
+        })
+    }
+
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.post(request, true).await?;
+
+            let mut text = String::new();
+            let mut usage = None;
+            for_each_line(response, |line| {
+                if sse_data(line) == Some("[DONE]") {
+                    return Ok(false);
+                }
+                if let Some(json) = sse_json(line)? {
+                    if let Some(error) = openai_filtered(&json) {
+                        return Err(error);
+                    }
+                    if let Some(delta) = json.pointer("/choices/0/delta/content").and_then(|c| c.as_str()) {
+                        if !delta.is_empty() {
+                            on_delta(delta);
+                            text.push_str(delta);
+                        }
+                    }
+                    if let Some(chunk_usage) = openai_usage(&json) {
+                        usage = Some(chunk_usage);
+                    }
+                }
+                Ok(true)
+            })
+            .await?;
+
+            Ok(ChatResponse { text, usage })
+        })
+    }
+}
+
+pub struct AnthropicProvider {
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+impl AnthropicProvider {
+    async fn post(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::Response, AiError> {
+        // System prompts go in their own field rather than the message list
+        let system = request
+            .messages
+            .iter()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let messages: Vec<&ChatMessage> = request.messages.iter().filter(|m| m.role != "system").collect();
+
+        let mut payload = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens.unwrap_or(4096),
+        });
+        if !system.is_empty() {
+            payload["system"] = serde_json::json!(system);
+        }
+        if let Some(temperature) = request.temperature {
+            payload["temperature"] = serde_json::json!(temperature);
+        }
+        if stream {
+            payload["stream"] = serde_json::json!(true);
+        }
+
+        let url = format!("{}/messages", self.base_url.trim_end_matches('/'));
+        http::send(
+            |client| {
+                client
+                    .post(&url)
+                    .header("x-api-key", &self.api_key)
+                    .header("anthropic-version", "2023-06-01")
+                    .json(&payload)
+            },
+            stream,
+        )
+        .await
+    }
+}
+
+impl AiProvider for AnthropicProvider {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let json = http::read_json(self.post(request, false).await?).await?;
+            if json.get("stop_reason").and_then(|r| r.as_str()) == Some("refusal") {
+                return Err(AiError::SafetyBlocked("the model declined to answer".to_string()));
+            }
+
+            let text = json
+                .get("content")
+                .and_then(|c| c.as_array())
+                .map(|blocks| {
+                    blocks
+                        .iter()
+                        .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"))
+                        .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                        .collect::<String>()
+                })
+                .unwrap_or_default();
+            let usage = json.get("usage").map(|usage| TokenUsage {
+                input_tokens: usage.get("input_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+                output_tokens: usage.get("output_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+            });
+
+            Ok(ChatResponse { text, usage })
+        })
+    }
+
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.post(request, true).await?;
+
+            let mut text = String::new();
+            let mut usage = TokenUsage::default();
+            for_each_line(response, |line| {
+                let Some(json) = sse_json(line)? else {
+                    return Ok(true);
+                };
+                match json.get("type").and_then(|t| t.as_str()) {
+                    Some("message_start") => {
+                        usage.input_tokens = json
+                            .pointer("/message/usage/input_tokens")
+                            .and_then(|n| n.as_u64())
+                            .unwrap_or(0);
+                    }
+                    Some("content_block_delta") => {
+                        if let Some(delta) = json.pointer("/delta/text").and_then(|t| t.as_str()) {
+                            on_delta(delta);
+                            text.push_str(delta);
+                        }
+                    }
+                    Some("message_delta") => {
+                        if json.pointer("/delta/stop_reason").and_then(|r| r.as_str()) == Some("refusal") {
+                            return Err(AiError::SafetyBlocked("the model declined to answer".to_string()));
+                        }
+                        if let Some(output) = json.pointer("/usage/output_tokens").and_then(|n| n.as_u64()) {
+                            usage.output_tokens = output;
+                        }
+                    }
+                    Some("message_stop") => return Ok(false),
+                    Some("error") => {
+                        let message = json
+                            .pointer("/error/message")
+                            .and_then(|m| m.as_str())
+                            .unwrap_or("Unknown error");
+                        return Err(AiError::Api(message.to_string()));
+                    }
+                    _ => {}
+                }
+                Ok(true)
+            })
+            .await?;
+
+            Ok(ChatResponse {
+                text,
+                usage: Some(usage),
+            })
+        })
+    }
+}
+
+pub struct OllamaProvider {
+    base_url: String,
+    model: String,
+}
+
+impl OllamaProvider {
+    async fn post(&self, request: &ChatRequest, stream: bool) -> Result<reqwest::Response, AiError> {
+        let mut options = serde_json::Map::new();
+        if let Some(temperature) = request.temperature {
+            options.insert("temperature".to_string(), serde_json::json!(temperature));
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            options.insert("num_predict".to_string(), serde_json::json!(max_tokens));
+        }
+
+        let payload = serde_json::json!({
+            "model": self.model,
+            "messages": request.messages,
+            "stream": stream,
+            "options": options,
+        });
+
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        http::send(|client| client.post(&url).json(&payload), stream).await
+    }
+}
+
+fn ollama_usage(json: &serde_json::Value) -> TokenUsage {
+    TokenUsage {
+        input_tokens: json.get("prompt_eval_count").and_then(|n| n.as_u64()).unwrap_or(0),
+        output_tokens: json.get("eval_count").and_then(|n| n.as_u64()).unwrap_or(0),
+    }
+}
+
+impl AiProvider for OllamaProvider {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let json = http::read_json(self.post(request, false).await?).await?;
+
+            let text = json
+                .pointer("/message/content")
+                .and_then(|c| c.as_str())
+                .unwrap_or_default()
+                .to_string();
+
+            Ok(ChatResponse {
+                text,
+                usage: Some(ollama_usage(&json)),
+            })
+        })
+    }
+
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            let response = self.post(request, true).await?;
+
+            // Ollama streams newline-delimited JSON rather than SSE
+            let mut text = String::new();
+            let mut usage = None;
+            for_each_line(response, |line| {
+                if line.trim().is_empty() {
+                    return Ok(true);
+                }
+                let json: serde_json::Value = serde_json::from_str(line)
+                    .map_err(|e| AiError::Malformed(format!("Invalid stream chunk: {}", e)))?;
+                if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+                    return Err(AiError::Api(error.to_string()));
+                }
+                if let Some(delta) = json.pointer("/message/content").and_then(|c| c.as_str()) {
+                    if !delta.is_empty() {
+                        on_delta(delta);
+                        text.push_str(delta);
+                    }
+                }
+                if json.get("done").and_then(|d| d.as_bool()) == Some(true) {
+                    usage = Some(ollama_usage(&json));
+                    return Ok(false);
+                }
+                Ok(true)
+            })
+            .await?;
+
+            Ok(ChatResponse { text, usage })
+        })
+    }
+}
+
+// Feed each complete line of a streamed body to `handle` until it returns false
+pub(crate) async fn for_each_line<F>(mut response: reqwest::Response, mut handle: F) -> Result<(), AiError>
+where
+    F: FnMut(&str) -> Result<bool, AiError> + Send,
+{
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while http::read_chunk(&mut response, &mut buffer).await? {
+        // Split on bytes so multi-byte characters cut across chunks stay intact
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !handle(line.trim_end_matches(['\r', '\n']))? {
+                return Ok(());
+            }
+        }
+    }
+
+    if !buffer.is_empty() {
+        let line = String::from_utf8_lossy(&buffer).to_string();
+        handle(line.trim_end())?;
+    }
+    Ok(())
+}
+
+fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(|data| data.trim_start())
+}
+
+// The JSON payload of an SSE data line; comments, event names and blank lines give None
+pub(crate) fn sse_json(line: &str) -> Result<Option<serde_json::Value>, AiError> {
+    match sse_data(line) {
+        Some(data) if !data.is_empty() && data != "[DONE]" => serde_json::from_str(data)
+            .map(Some)
+            .map_err(|e| AiError::Malformed(format!("Invalid stream chunk: {}", e))),
+        _ => Ok(None),
+    }
+}
+
+// Payload of "ai_stream"; the last event for a request has `done` set
+#[derive(Debug, Clone, Serialize)]
+pub struct AiStreamEvent {
+    pub request_id: String,
+    pub delta: String,
+    pub done: bool,
+    pub error: Option<String>,
+    // One of the AiError kinds, so the UI can tell a bad key from a dropped connection
+    pub error_kind: Option<String>,
+}
+
+// Streams in flight, keyed by the caller's request id, so they can be cancelled
+pub struct AiStreamManager {
+    active: Mutex<HashMap<String, oneshot::Sender<()>>>,
+}
+
+impl AiStreamManager {
+    pub fn new() -> Self {
+        Self {
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Stream a reply to the window as "ai_stream" events until it finishes or is cancelled
+    pub async fn run(
+        &self,
+        app_handle: &AppHandle,
+        request_id: &str,
+        provider: &dyn AiProvider,
+        request: &ChatRequest,
+    ) -> Result<ChatResponse, AiError> {
+        let emit_delta = |delta: &str| {
+            let _ = app_handle.emit_all(
+                "ai_stream",
+                AiStreamEvent {
+                    request_id: request_id.to_string(),
+                    delta: delta.to_string(),
+                    done: false,
+                    error: None,
+                    error_kind: None,
+                },
+            );
+        };
+
+        let result = self
+            .cancellable(request_id, provider.stream_chat(request, &emit_delta))
+            .await;
+
+        let _ = app_handle.emit_all(
+            "ai_stream",
+            AiStreamEvent {
+                request_id: request_id.to_string(),
+                delta: String::new(),
+                done: true,
+                error: result.as_ref().err().map(|e| e.to_string()),
+                error_kind: result.as_ref().err().map(|e| e.kind().to_string()),
+            },
+        );
+
+        result
+    }
+
+    // Run `future` under `request_id` so ai_cancel_stream can stop it.
+    // Dropping the future drops the connection, which stops generation upstream.
+    pub async fn cancellable<T, F>(&self, request_id: &str, future: F) -> Result<T, AiError>
+    where
+        F: Future<Output = Result<T, AiError>>,
+    {
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        if self
+            .active
+            .lock()
+            .insert(request_id.to_string(), cancel_tx)
+            .is_some()
+        {
+            log::warn!("AI request id {} reused while still streaming", request_id);
+        }
+
+        let result = tokio::select! {
+            result = future => result,
+            Ok(()) = cancel_rx => Err(AiError::Cancelled),
+        };
+
+        self.active.lock().remove(request_id);
+        result
+    }
+
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.active.lock().remove(request_id) {
+            Some(cancel) => cancel.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderSettings {
+    // Profile used when a command doesn't name one
+    pub default: String,
+    pub providers: HashMap<String, ProviderConfig>,
+    // Timeouts and retries shared by every provider
+    #[serde(default)]
+    pub http: HttpSettings,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        let mut providers = HashMap::new();
+        providers.insert("gemini".to_string(), ProviderConfig::default_gemini());
+        Self {
+            default: "gemini".to_string(),
+            providers,
+            http: HttpSettings::default(),
+        }
+    }
+}
+
+pub struct AiProviderStore {
+    settings: RwLock<ProviderSettings>,
+    secrets: Arc<SecretStore>,
+    usage: Arc<UsageLedger>,
+    config_path: PathBuf,
+}
+
+impl AiProviderStore {
+    pub fn new(app_handle: &tauri::AppHandle, secrets: Arc<SecretStore>, usage: Arc<UsageLedger>) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
+
+        let config_path = app_dir.join("ai_providers.json");
+
+        let settings: ProviderSettings = fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        http::configure(&settings.http);
+
+        let store = Self {
+            settings: RwLock::new(settings),
+            secrets,
+            usage,
+            config_path,
+        };
+        store.migrate_inline_keys();
+        store
+    }
+
+    // Keys written straight into ai_providers.json move to the secret store under the profile name
+    fn migrate_inline_keys(&self) {
+        let needs_migration = self
+            .settings
+            .read()
+            .providers
+            .values()
+            .any(|config| matches!(config.clone().key_slot(), Some((Some(_), _))));
+        if !needs_migration {
+            return;
+        }
+
+        let secrets = self.secrets.clone();
+        let result = self.update(|settings| {
+            for (name, config) in settings.providers.iter_mut() {
+                let kind = config.kind();
+                if let Some((api_key, key_name)) = config.key_slot() {
+                    if let Some(key) = api_key.take() {
+                        secrets.set(kind, name, &key)?;
+                        *key_name = Some(name.clone());
+                    }
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::warn!("Failed to move API keys out of ai_providers.json: {}", e);
+        }
+    }
+
+    // What the webview sees: a key still inline, because moving it failed, stays in the backend
+    pub fn get_settings(&self) -> ProviderSettings {
+        let mut settings = self.settings.read().clone();
+        for config in settings.providers.values_mut() {
+            if let Some((api_key, _)) = config.key_slot() {
+                *api_key = None;
+            }
+        }
+        settings
+    }
+
+    // A key entered with the profile goes to the secret store, as at startup, so it's never written
+    // to ai_providers.json
+    pub fn set_provider(&self, name: String, mut config: ProviderConfig) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err("Provider name is empty".to_string());
+        }
+        let kind = config.kind();
+        if let Some((api_key, key_name)) = config.key_slot() {
+            if let Some(key) = api_key.take().filter(|key| !key.is_empty()) {
+                self.secrets.set(kind, &name, &key)?;
+                *key_name = Some(name.clone());
+            }
+        }
+        self.update(|settings| {
+            settings.providers.insert(name, config);
+            Ok(())
+        })
+    }
+
+    pub fn update<F>(&self, change: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut ProviderSettings) -> Result<(), String>,
+    {
+        let mut settings = self.settings.write();
+        let mut updated = settings.clone();
+        change(&mut updated)?;
+
+        let content = serde_json::to_string_pretty(&updated)
+            .map_err(|e| format!("Failed to serialize provider settings: {}", e))?;
+        fs::write(&self.config_path, content)
+            .map_err(|e| format!("Failed to save provider settings: {}", e))?;
+
+        http::configure(&updated.http);
+        *settings = updated;
+        Ok(())
+    }
+
+    pub fn resolve(&self, name: Option<&str>) -> Result<ProviderConfig, String> {
+        let settings = self.settings.read();
+        let name = name.unwrap_or(&settings.default);
+        settings
+            .providers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown AI provider: {}", name))
+    }
+
+    pub fn provider(&self, name: Option<&str>) -> Result<Box<dyn AiProvider>, String> {
+        self.provider_with_key(name, None)
+    }
+
+    // `gemini_key` is the key older frontends pass with each request; it only applies to Gemini profiles
+    pub fn provider_with_key(&self, name: Option<&str>, gemini_key: Option<&str>) -> Result<Box<dyn AiProvider>, String> {
+        let mut config = self.resolve(name)?;
+        let kind = config.kind();
+
+        let key = match (kind, gemini_key.filter(|k| !k.is_empty())) {
+            ("gemini", Some(key)) => key.to_string(),
+            _ => config
+                .key_slot()
+                .and_then(|(_, key_name)| {
+                    self.secrets
+                        .get(kind, key_name.as_deref().unwrap_or(DEFAULT_KEY_NAME))
+                })
+                .unwrap_or_default(),
+        };
+
+        let provider = config.build(&key)?;
+        // Local models cost nothing, so they stay out of the ledger and budgets
+        if kind == "ollama" {
+            return Ok(provider);
+        }
+        Ok(Box::new(MeteredProvider::new(provider, self.usage.clone())))
+    }
+}
+
+#[tauri::command]
+pub fn get_ai_providers(store: tauri::State<'_, AiProviderStore>) -> ProviderSettings {
+    store.get_settings()
+}
+
+#[tauri::command]
+pub fn set_ai_provider(
+    store: tauri::State<'_, AiProviderStore>,
+    name: String,
+    config: ProviderConfig,
+) -> Result<(), String> {
+    store.set_provider(name, config)
+}
+
+#[tauri::command]
+pub fn remove_ai_provider(store: tauri::State<'_, AiProviderStore>, name: String) -> Result<(), String> {
+    store.update(|settings| {
+        if settings.default == name {
+            return Err("Can't remove the default provider".to_string());
+        }
+        settings
+            .providers
+            .remove(&name)
+            .map(|_| ())
+            .ok_or_else(|| format!("Unknown AI provider: {}", name))
+    })
+}
+
+#[tauri::command]
+pub fn set_default_ai_provider(store: tauri::State<'_, AiProviderStore>, name: String) -> Result<(), String> {
+    store.update(|settings| {
+        if !settings.providers.contains_key(&name) {
+            return Err(format!("Unknown AI provider: {}", name));
+        }
+        settings.default = name;
+        Ok(())
+    })
+}
+
+// Timeouts and retries for every AI and search request, not just one provider's
+#[tauri::command]
+pub fn set_ai_http_settings(store: tauri::State<'_, AiProviderStore>, http: HttpSettings) -> Result<(), String> {
+    store.update(|settings| {
+        settings.http = http;
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub async fn ai_chat(
+    request: ChatRequest,
+    provider: Option<String>,
+    store: tauri::State<'_, AiProviderStore>,
+) -> Result<ChatResponse, AiError> {
+    if request.messages.is_empty() {
+        return Err(AiError::Config("No messages provided".into()));
+    }
+
+    let provider = store.provider(provider.as_deref()).map_err(AiError::Config)?;
+    provider.chat(&request).await
+}
+
+#[tauri::command]
+pub async fn ai_chat_stream(
+    request_id: String,
+    request: ChatRequest,
+    provider: Option<String>,
+    app_handle: AppHandle,
+    store: tauri::State<'_, AiProviderStore>,
+    streams: tauri::State<'_, AiStreamManager>,
+) -> Result<ChatResponse, AiError> {
+    if request.messages.is_empty() {
+        return Err(AiError::Config("No messages provided".into()));
+    }
+
+    let provider = store.provider(provider.as_deref()).map_err(AiError::Config)?;
+    streams
+        .run(&app_handle, &request_id, provider.as_ref(), &request)
+        .await
+}
+
+#[tauri::command]
+pub fn ai_cancel_stream(request_id: String, streams: tauri::State<'_, AiStreamManager>) -> bool {
+    streams.cancel(&request_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::{json_reply, mock_server, stream_reply, Reply};
+
+    fn request() -> ChatRequest {
+        ChatRequest {
+            messages: vec![
+                ChatMessage {
+                    role: "system".to_string(),
+                    content: "Be brief.".to_string(),
+                },
+                ChatMessage {
+                    role: "user".to_string(),
+                    content: "Hello".to_string(),
+                },
+            ],
+            temperature: Some(0.5),
+            max_tokens: Some(64),
+        }
+    }
+
+    async fn stream(provider: &dyn AiProvider) -> (Result<ChatResponse, AiError>, Vec<String>) {
+        let deltas = Mutex::new(Vec::new());
+        let on_delta = |delta: &str| deltas.lock().push(delta.to_string());
+        let request = request();
+        let result = provider.stream_chat(&request, &on_delta).await;
+        (result, deltas.into_inner())
+    }
+
+    fn usage(response: &ChatResponse) -> (u64, u64) {
+        let usage = response.usage.as_ref().expect("usage reported");
+        (usage.input_tokens, usage.output_tokens)
+    }
+
+    fn gemini(base_url: &str) -> Box<dyn AiProvider> {
+        ProviderConfig::Gemini {
+            model: "gemini-test".to_string(),
+            base_url: Some(base_url.to_string()),
+            api_key: None,
+            key_name: None,
+        }
+        .build("test-key")
+        .unwrap()
+    }
+
+    fn openai(base_url: &str, api_key: &str) -> Box<dyn AiProvider> {
+        ProviderConfig::OpenAi {
+            model: "gpt-test".to_string(),
+            base_url: format!("{}/v1/", base_url),
+            api_key: None,
+            key_name: None,
+        }
+        .build(api_key)
+        .unwrap()
+    }
+
+    fn anthropic(base_url: &str) -> Box<dyn AiProvider> {
+        ProviderConfig::Anthropic {
+            model: "claude-test".to_string(),
+            base_url: Some(base_url.to_string()),
+            api_key: Some("test-key".to_string()),
+            key_name: None,
+        }
+        .build("")
+        .unwrap()
+    }
+
+    fn ollama(base_url: &str) -> Box<dyn AiProvider> {
+        ProviderConfig::Ollama {
+            model: "llama-test".to_string(),
+            base_url: Some(base_url.to_string()),
+        }
+        .build("")
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn gemini_chat() {
+        let (base_url, server) = mock_server(vec![json_reply(serde_json::json!({
+            "candidates": [{ "content": { "parts": [{ "text": "Hi " }, { "text": "there" }] } }],
+            "usageMetadata": { "promptTokenCount": 5, "candidatesTokenCount": 2 },
+        }))])
+        .await;
+
+        let response = gemini(&base_url).chat(&request()).await.unwrap();
+        assert_eq!(response.text, "Hi there");
+        assert_eq!(usage(&response), (5, 2));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].path, "/models/gemini-test:generateContent");
+        assert_eq!(recorded[0].headers["x-goog-api-key"], "test-key");
+        let body = &recorded[0].body;
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+        assert_eq!(body["contents"][0]["role"], "user");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 64);
+        assert_eq!(body["generationConfig"]["temperature"], 0.5);
+    }
+
+    #[tokio::test]
+    async fn gemini_stream() {
+        let body = [
+            r#"data: {"candidates":[{"content":{"parts":[{"text":"Hel"}]}}]}"#,
+            "",
+            r#"data: {"candidates":[{"content":{"parts":[{"text":"lo ☕"}]}}],"usageMetadata":{"promptTokenCount":5,"candidatesTokenCount":3}}"#,
+            "",
+            "",
+        ]
+        .join("\r\n");
+        let (base_url, server) = mock_server(vec![stream_reply("text/event-stream", &body)]).await;
+
+        let (response, deltas) = stream(gemini(&base_url).as_ref()).await;
+        let response = response.unwrap();
+        assert_eq!(deltas, vec!["Hel", "lo ☕"]);
+        assert_eq!(response.text, "Hello ☕");
+        assert_eq!(usage(&response), (5, 3));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].path, "/models/gemini-test:streamGenerateContent?alt=sse");
+    }
+
+    #[tokio::test]
+    async fn gemini_blocked_prompt() {
+        let (base_url, _server) = mock_server(vec![json_reply(serde_json::json!({
+            "promptFeedback": { "blockReason": "SAFETY" },
+        }))])
+        .await;
+
+        let error = gemini(&base_url).chat(&request()).await.unwrap_err();
+        assert!(matches!(error, AiError::SafetyBlocked(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn openai_chat() {
+        let (base_url, server) = mock_server(vec![json_reply(serde_json::json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Hi" }, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 7, "completion_tokens": 1 },
+        }))])
+        .await;
+
+        let response = openai(&base_url, "test-key").chat(&request()).await.unwrap();
+        assert_eq!(response.text, "Hi");
+        assert_eq!(usage(&response), (7, 1));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].path, "/v1/chat/completions");
+        assert_eq!(recorded[0].headers["authorization"], "Bearer test-key");
+        let body = &recorded[0].body;
+        assert_eq!(body["model"], "gpt-test");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["max_tokens"], 64);
+        assert!(body.get("stream").is_none());
+    }
+
+    #[tokio::test]
+    async fn openai_stream() {
+        let body = [
+            r#"data: {"choices":[{"delta":{"role":"assistant","content":""}}]}"#,
+            "",
+            r#"data: {"choices":[{"delta":{"content":"Hi"}}]}"#,
+            "",
+            r#"data: {"choices":[{"delta":{"content":" there"},"finish_reason":"stop"}]}"#,
+            "",
+            r#"data: {"choices":[],"usage":{"prompt_tokens":7,"completion_tokens":2}}"#,
+            "",
+            "data: [DONE]",
+            "",
+            "",
+        ]
+        .join("\n");
+        let (base_url, server) = mock_server(vec![stream_reply("text/event-stream", &body)]).await;
+
+        // A local server without a key gets no Authorization header at all
+        let (response, deltas) = stream(openai(&base_url, "").as_ref()).await;
+        let response = response.unwrap();
+        assert_eq!(deltas, vec!["Hi", " there"]);
+        assert_eq!(response.text, "Hi there");
+        assert_eq!(usage(&response), (7, 2));
+
+        let recorded = server.await.unwrap();
+        assert!(!recorded[0].headers.contains_key("authorization"));
+        assert_eq!(recorded[0].body["stream"], true);
+        assert_eq!(recorded[0].body["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn anthropic_chat() {
+        let (base_url, server) = mock_server(vec![json_reply(serde_json::json!({
+            "content": [{ "type": "text", "text": "Hi" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 9, "output_tokens": 1 },
+        }))])
+        .await;
+
+        let response = anthropic(&base_url).chat(&request()).await.unwrap();
+        assert_eq!(response.text, "Hi");
+        assert_eq!(usage(&response), (9, 1));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].path, "/messages");
+        assert_eq!(recorded[0].headers["x-api-key"], "test-key");
+        assert_eq!(recorded[0].headers["anthropic-version"], "2023-06-01");
+        let body = &recorded[0].body;
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["max_tokens"], 64);
+    }
+
+    #[tokio::test]
+    async fn anthropic_stream() {
+        let body = [
+            "event: message_start",
+            r#"data: {"type":"message_start","message":{"usage":{"input_tokens":9,"output_tokens":1}}}"#,
+            "",
+            "event: content_block_delta",
+            r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            "",
+            "event: content_block_delta",
+            r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" there"}}"#,
+            "",
+            "event: message_delta",
+            r#"data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":3}}"#,
+            "",
+            "event: message_stop",
+            r#"data: {"type":"message_stop"}"#,
+            "",
+            "",
+        ]
+        .join("\n");
+        let (base_url, server) = mock_server(vec![stream_reply("text/event-stream", &body)]).await;
+
+        let (response, deltas) = stream(anthropic(&base_url).as_ref()).await;
+        let response = response.unwrap();
+        assert_eq!(deltas, vec!["Hi", " there"]);
+        assert_eq!(response.text, "Hi there");
+        assert_eq!(usage(&response), (9, 3));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn anthropic_rejected_key() {
+        let (base_url, _server) = mock_server(vec![Reply {
+            status: 401,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#.to_string(),
+        }])
+        .await;
+
+        match anthropic(&base_url).chat(&request()).await {
+            Err(AiError::Auth(message)) => assert_eq!(message, "invalid x-api-key"),
+            other => panic!("expected an auth error, got {:?}", other.map(|r| r.text)),
+        }
+    }
+
+    #[tokio::test]
+    async fn ollama_chat() {
+        let (base_url, server) = mock_server(vec![json_reply(serde_json::json!({
+            "message": { "role": "assistant", "content": "Hi" },
+            "done": true,
+            "prompt_eval_count": 4,
+            "eval_count": 1,
+        }))])
+        .await;
+
+        let response = ollama(&base_url).chat(&request()).await.unwrap();
+        assert_eq!(response.text, "Hi");
+        assert_eq!(usage(&response), (4, 1));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].path, "/api/chat");
+        let body = &recorded[0].body;
+        assert_eq!(body["model"], "llama-test");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["options"]["num_predict"], 64);
+        assert_eq!(body["options"]["temperature"], 0.5);
+    }
+
+    #[tokio::test]
+    async fn ollama_stream() {
+        let body = [
+            r#"{"message":{"role":"assistant","content":"Hi"},"done":false}"#,
+            r#"{"message":{"role":"assistant","content":" thére"},"done":false}"#,
+            r#"{"message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":4,"eval_count":2}"#,
+            "",
+        ]
+        .join("\n");
+        let (base_url, server) = mock_server(vec![stream_reply("application/x-ndjson", &body)]).await;
+
+        let (response, deltas) = stream(ollama(&base_url).as_ref()).await;
+        let response = response.unwrap();
+        assert_eq!(deltas, vec!["Hi", " thére"]);
+        assert_eq!(response.text, "Hi thére");
+        assert_eq!(usage(&response), (4, 2));
+
+        let recorded = server.await.unwrap();
+        assert_eq!(recorded[0].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn ollama_stream_error() {
+        let body = "{\"message\":{\"content\":\"Hi\"},\"done\":false}\n{\"error\":\"model not found\"}\n";
+        let (base_url, _server) = mock_server(vec![stream_reply("application/x-ndjson", body)]).await;
+
+        let (response, deltas) = stream(ollama(&base_url).as_ref()).await;
+        assert_eq!(deltas, vec!["Hi"]);
+        match response {
+            Err(AiError::Api(message)) => assert_eq!(message, "model not found"),
+            other => panic!("expected an API error, got {:?}", other.map(|r| r.text)),
+        }
+    }
+
+    struct TempProviders {
+        dir: PathBuf,
+        store: AiProviderStore,
+    }
+
+    impl TempProviders {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("vuno-providers-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let store = AiProviderStore {
+                settings: RwLock::new(ProviderSettings::default()),
+                secrets: Arc::new(SecretStore::in_dir(&dir)),
+                usage: Arc::new(UsageLedger::in_dir(&dir)),
+                config_path: dir.join("ai_providers.json"),
+            };
+            Self { dir, store }
+        }
+    }
+
+    impl Drop for TempProviders {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn keys_set_at_runtime_go_to_the_secret_store() {
+        let temp = TempProviders::new();
+        let store = &temp.store;
+        let config = ProviderConfig::OpenAi {
+            model: "gpt-4o".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key: Some("sk-live-key".to_string()),
+            key_name: None,
+        };
+        store.set_provider("work".to_string(), config).unwrap();
+
+        let saved = fs::read_to_string(&store.config_path).unwrap();
+        assert!(!saved.contains("sk-live-key"));
+        assert_eq!(store.secrets.get("openai", "work").as_deref(), Some("sk-live-key"));
+        match store.get_settings().providers.get("work") {
+            Some(ProviderConfig::OpenAi { api_key, key_name, .. }) => {
+                assert_eq!(*api_key, None);
+                assert_eq!(key_name.as_deref(), Some("work"));
+            }
+            other => panic!("unexpected profile: {:?}", other),
+        }
+        assert!(store.set_provider(" ".to_string(), ProviderConfig::default_gemini()).is_err());
+    }
+
+    #[test]
+    fn inline_keys_never_reach_the_webview() {
+        let temp = TempProviders::new();
+        let store = &temp.store;
+        // As if moving the key at startup had failed
+        store.settings.write().providers.insert(
+            "legacy".to_string(),
+            ProviderConfig::Gemini {
+                model: "gemini-2.0-flash".to_string(),
+                base_url: None,
+                api_key: Some("g-inline".to_string()),
+                key_name: None,
+            },
+        );
+
+        let settings = store.get_settings();
+        assert!(!serde_json::to_string(&settings).unwrap().contains("g-inline"));
+        assert!(serde_json::to_string(&*store.settings.read()).unwrap().contains("g-inline"));
+    }
+}
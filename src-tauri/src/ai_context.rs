@@ -0,0 +1,379 @@
+// بسم الله الرحمن الرحيم
+// AI commands that gather their context from the open buffer
+
+use serde::{Deserialize, Serialize};
+use tauri::{self, AppHandle};
+
+use crate::ai::{AiProvider, AiProviderStore, AiStreamManager, ChatMessage, ChatRequest, ChatResponse};
+use crate::buffer::{Buffer, BufferManager};
+use crate::lsp::{self, Diagnostic};
+
+// Lines of surrounding code sent either side of the selection or cursor
+const CONTEXT_LINES: usize = 30;
+// Whole files above this size are cut down to the surrounding lines
+const MAX_FILE_BYTES: usize = 48 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AiAction {
+    Explain,
+    Optimize,
+    Document,
+    Debug,
+    // Free-form question about the code
+    Ask,
+}
+
+impl AiAction {
+    // Matches the phrases offered by config::get_command_suggestions
+    pub fn from_command(command: &str) -> Option<(AiAction, String)> {
+        let command = command.trim();
+        let lower = command.to_lowercase();
+        let phrases = [
+            ("explain this code", AiAction::Explain),
+            ("optimize this code", AiAction::Optimize),
+            ("generate documentation", AiAction::Document),
+            ("help me debug", AiAction::Debug),
+        ];
+
+        phrases.iter().find_map(|(phrase, action)| {
+            lower
+                .starts_with(phrase)
+                .then(|| (*action, command.get(phrase.len()..).unwrap_or("").trim().to_string()))
+        })
+    }
+
+    fn instruction(&self) -> &'static str {
+        match self {
+            AiAction::Explain => "Explain what this code does, step by step. Point out anything surprising.",
+            AiAction::Optimize => "Suggest how to make this code faster, simpler or more idiomatic. Show the improved code and say why each change helps.",
+            AiAction::Document => "Write documentation comments for this code in the idiomatic style for its language. Reply with the documented code only.",
+            AiAction::Debug => "Help me find the bug in this code. Use the diagnostics if they're relevant, explain the likely cause and show a fix.",
+            AiAction::Ask => "Answer the question about this code.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditorContext {
+    pub path: Option<String>,
+    pub language: Option<String>,
+    // Selected text and its 1-based line span
+    pub selection: Option<String>,
+    pub selection_lines: Option<(usize, usize)>,
+    pub cursor_line: usize,
+    // Code around the selection or cursor; the whole file when scope is "file" and it fits
+    pub code: String,
+    pub code_start_line: usize,
+    pub whole_file: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl EditorContext {
+    pub fn from_buffer(buffer: &Buffer, whole_file: bool) -> Self {
+        let content = &buffer.content;
+        let line_of = |offset: usize| content[..floor_char_boundary(content, offset)].matches('\n').count();
+
+        let selection = buffer.selection.map(|(start, end)| {
+            let start = floor_char_boundary(content, start);
+            let end = floor_char_boundary(content, end);
+            (start, end)
+        });
+        let selection_text = selection.map(|(start, end)| content[start..end].to_string());
+        let selection_lines = selection.map(|(start, end)| (line_of(start) + 1, line_of(end) + 1));
+        let cursor_line = line_of(buffer.cursor_position);
+
+        let lines: Vec<&str> = content.lines().collect();
+        let (focus_start, focus_end) = selection_lines
+            .map(|(start, end)| (start - 1, end - 1))
+            .unwrap_or((cursor_line, cursor_line));
+
+        let whole_file = whole_file && content.len() <= MAX_FILE_BYTES;
+        let (code_start, code_end) = if whole_file {
+            (0, lines.len())
+        } else {
+            (
+                focus_start.saturating_sub(CONTEXT_LINES),
+                (focus_end + CONTEXT_LINES + 1).min(lines.len()),
+            )
+        };
+        let code = lines
+            .get(code_start..code_end)
+            .map(|lines| lines.join("\n"))
+            .unwrap_or_default();
+
+        let path = buffer.path.as_ref().map(|p| p.display().to_string());
+        let diagnostics = match (&buffer.language, &path) {
+            (Some(language), Some(path)) => {
+                lsp::get_diagnostics(path.clone(), content.clone(), language.clone()).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+
+        Self {
+            path,
+            language: buffer.language.clone(),
+            selection: selection_text,
+            selection_lines,
+            cursor_line: cursor_line + 1,
+            code,
+            code_start_line: code_start + 1,
+            whole_file,
+            diagnostics,
+        }
+    }
+
+    pub fn request(&self, action: AiAction, question: &str) -> ChatRequest {
+        let language = self.language.as_deref().unwrap_or("");
+        let mut prompt = String::new();
+
+        prompt.push_str(action.instruction());
+        if !question.is_empty() {
+            prompt.push_str("\n\n");
+            prompt.push_str(question);
+        }
+
+        prompt.push_str(&format!(
+            "\n\nFile: {}\nLanguage: {}\n",
+            self.path.as_deref().unwrap_or("untitled"),
+            if language.is_empty() { "unknown" } else { language }
+        ));
+
+        match (&self.selection, self.selection_lines) {
+            (Some(selection), Some((start, end))) => {
+                prompt.push_str(&format!(
+                    "\nSelected code (lines {}-{}):\n```{}\n{}\n```\n",
+                    start, end, language, selection
+                ));
+            }
+            _ => prompt.push_str(&format!("\nThe cursor is on line {}.\n", self.cursor_line)),
+        }
+
+        let label = if self.whole_file { "Full file" } else { "Surrounding code" };
+        prompt.push_str(&format!(
+            "\n{} (starting at line {}):\n```{}\n{}\n```\n",
+            label, self.code_start_line, language, self.code
+        ));
+
+        if !self.diagnostics.is_empty() {
+            prompt.push_str("\nDiagnostics:\n");
+            for diagnostic in &self.diagnostics {
+                prompt.push_str(&format!(
+                    "- line {}: {} ({})\n",
+                    diagnostic.line + 1,
+                    diagnostic.message,
+                    diagnostic.severity
+                ));
+            }
+        }
+
+        ChatRequest {
+            messages: vec![
+                ChatMessage {
+                    role: "system".to_string(),
+                    content: "You are a coding assistant inside the vuno text editor. Be concise and use fenced code blocks for code.".to_string(),
+                },
+                ChatMessage {
+                    role: "user".to_string(),
+                    content: prompt,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+}
+
+fn floor_char_boundary(content: &str, offset: usize) -> usize {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+pub async fn run_action(
+    provider: &dyn AiProvider,
+    buffer: &Buffer,
+    action: AiAction,
+    question: &str,
+    whole_file: bool,
+    stream: Option<(&AiStreamManager, &AppHandle, &str)>,
+) -> Result<ChatResponse, String> {
+    let request = EditorContext::from_buffer(buffer, whole_file).request(action, question);
+    let response = match stream {
+        Some((streams, app_handle, request_id)) => streams.run(app_handle, request_id, provider, &request).await?,
+        None => provider.chat(&request).await?,
+    };
+    Ok(response)
+}
+
+#[tauri::command]
+pub fn ai_get_editor_context(
+    buffer_id: usize,
+    scope: Option<String>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<EditorContext, String> {
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    Ok(EditorContext::from_buffer(&buffer, scope.as_deref() == Some("file")))
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ai_editor_action(
+    buffer_id: usize,
+    action: AiAction,
+    question: Option<String>,
+    scope: Option<String>, // "selection" (default) or "file"
+    provider: Option<String>,
+    request_id: Option<String>,
+    app_handle: AppHandle,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    providers: tauri::State<'_, AiProviderStore>,
+    streams: tauri::State<'_, AiStreamManager>,
+) -> Result<ChatResponse, String> {
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let provider = providers.provider(provider.as_deref())?;
+    let stream = request_id
+        .as_deref()
+        .map(|request_id| (&*streams, &app_handle, request_id));
+
+    run_action(
+        provider.as_ref(),
+        &buffer,
+        action,
+        question.as_deref().unwrap_or(""),
+        scope.as_deref() == Some("file"),
+        stream,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    // Byte offset of the start of a 1-based line
+    fn offset_of_line(content: &str, line: usize) -> usize {
+        content.split_inclusive('\n').take(line - 1).map(str::len).sum()
+    }
+
+    fn buffer(content: &str, path: Option<&str>, language: Option<&str>) -> (BufferManager, usize) {
+        let buffers = BufferManager::new();
+        let id = buffers.create_buffer(content.to_string(), path.map(PathBuf::from));
+        buffers.set_language(id, language.map(String::from)).unwrap();
+        (buffers, id)
+    }
+
+    #[test]
+    fn a_selection_is_sent_with_the_lines_around_it() {
+        let content = numbered_lines(100);
+        let (buffers, id) = buffer(&content, None, None);
+        let start = offset_of_line(&content, 50);
+        let end = offset_of_line(&content, 52) - 1;
+        buffers.update_selection(id, Some((start, end))).unwrap();
+
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), false);
+        assert_eq!(context.selection.as_deref(), Some("line 50\nline 51"));
+        assert_eq!(context.selection_lines, Some((50, 51)));
+        assert!(!context.whole_file);
+        assert_eq!(context.code_start_line, 20);
+        let code: Vec<&str> = context.code.lines().collect();
+        assert_eq!(code.first(), Some(&"line 20"));
+        assert_eq!(code.last(), Some(&"line 81"));
+    }
+
+    #[test]
+    fn without_a_selection_the_cursor_is_the_focus() {
+        let content = numbered_lines(5);
+        let (buffers, id) = buffer(&content, None, None);
+        buffers.update_cursor_position(id, offset_of_line(&content, 3) + 2).unwrap();
+
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), true);
+        assert_eq!(context.selection, None);
+        assert_eq!(context.cursor_line, 3);
+        assert!(context.whole_file);
+        assert_eq!(context.code_start_line, 1);
+        assert_eq!(context.code, content.trim_end());
+        assert_eq!((context.path, context.language), (None, None));
+    }
+
+    #[test]
+    fn files_too_big_to_send_whole_are_cut_down() {
+        let content = numbered_lines(10_000);
+        assert!(content.len() > MAX_FILE_BYTES);
+        let (buffers, id) = buffer(&content, None, None);
+        buffers.update_cursor_position(id, offset_of_line(&content, 5000)).unwrap();
+
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), true);
+        assert!(!context.whole_file);
+        assert_eq!(context.code_start_line, 4970);
+        assert_eq!(context.code.lines().count(), 2 * CONTEXT_LINES + 1);
+    }
+
+    #[test]
+    fn carries_the_path_language_and_diagnostics() {
+        let (buffers, id) = buffer("fn main()\n", Some("/project/src/main.rs"), Some("rust"));
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), false);
+        assert_eq!(context.path.as_deref(), Some("/project/src/main.rs"));
+        assert_eq!(context.language.as_deref(), Some("rust"));
+        assert_eq!(context.diagnostics.len(), 1);
+        assert_eq!(context.diagnostics[0].line, 0);
+    }
+
+    #[test]
+    fn builds_the_prompt_from_the_context() {
+        let content = "fn main()\n    let x = 1;\n}\n";
+        let (buffers, id) = buffer(content, Some("/project/src/main.rs"), Some("rust"));
+        buffers.update_selection(id, Some((offset_of_line(content, 2), offset_of_line(content, 3) - 1))).unwrap();
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), true);
+
+        let request = context.request(AiAction::Debug, "why won't it compile?");
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, "system");
+        let prompt = &request.messages[1].content;
+        assert!(prompt.starts_with(AiAction::Debug.instruction()));
+        assert!(prompt.contains("\n\nwhy won't it compile?\n\nFile: /project/src/main.rs\nLanguage: rust\n"));
+        assert!(prompt.contains("Selected code (lines 2-2):\n```rust\n    let x = 1;\n```"));
+        assert!(prompt.contains("Full file (starting at line 1):\n```rust\nfn main()\n    let x = 1;\n}\n```"));
+        assert!(prompt.contains("Diagnostics:\n- line 1: Function declaration may be incomplete (warning)\n"));
+    }
+
+    #[test]
+    fn untitled_buffers_get_the_cursor_line_instead() {
+        let (buffers, id) = buffer("x = 1\n", None, None);
+        let context = EditorContext::from_buffer(&buffers.get_buffer(id).unwrap(), false);
+
+        let prompt = context.request(AiAction::Explain, "").messages[1].content.clone();
+        assert!(prompt.contains("File: untitled\nLanguage: unknown\n\nThe cursor is on line 1.\n"));
+        assert!(prompt.contains("Surrounding code (starting at line 1):\n```\nx = 1\n```"));
+        assert!(!prompt.contains("Diagnostics"));
+    }
+
+    #[test]
+    fn recognises_the_suggested_commands() {
+        assert_eq!(AiAction::from_command("Explain this code"), Some((AiAction::Explain, String::new())));
+        assert_eq!(
+            AiAction::from_command("  optimize this code for memory use "),
+            Some((AiAction::Optimize, "for memory use".to_string()))
+        );
+        assert_eq!(
+            AiAction::from_command("Generate documentation"),
+            Some((AiAction::Document, String::new()))
+        );
+        assert_eq!(
+            AiAction::from_command("help me debug: the loop never ends"),
+            Some((AiAction::Debug, ": the loop never ends".to_string()))
+        );
+        assert_eq!(AiAction::from_command("explain"), None);
+        assert_eq!(AiAction::from_command("please explain this code"), None);
+    }
+}
@@ -0,0 +1,545 @@
+// بسم الله الرحمن الرحيم
+// AI edits as reviewable proposals: parse the reply, validate against the buffer, preview, apply
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::{self, AppHandle};
+
+use crate::ai::{AiProviderStore, AiStreamManager, ChatMessage};
+use crate::ai_context::{AiAction, EditorContext};
+use crate::buffer::BufferManager;
+
+// Unchanged lines shown around each change in the preview
+const PREVIEW_CONTEXT_LINES: usize = 3;
+
+const EDIT_FORMAT_INSTRUCTIONS: &str = "Reply with the changes as one or more search/replace blocks and nothing else:
+
+<<<<<<< SEARCH
+exact lines from the file
+=======
+replacement lines
+>>>>>>> REPLACE
+
+Each SEARCH section must match the file exactly, including indentation, and should include enough lines to be unique.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposedEdit {
+    // Byte range in the buffer as it was when the proposal was made
+    pub start: usize,
+    pub end: usize,
+    pub original: String,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditProposal {
+    pub id: String,
+    pub buffer_id: usize,
+    pub edits: Vec<ProposedEdit>,
+    // Unified diff of the whole proposal for review
+    pub diff: String,
+    // Anything the model said outside the edit blocks
+    pub notes: String,
+}
+
+// One search/replace pair before it has been located in the buffer
+struct RawEdit {
+    search: String,
+    replace: String,
+    // 1-based line the change is expected near, from a diff hunk header
+    line_hint: Option<usize>,
+}
+
+// Accepts search/replace blocks or a unified diff; returns the edits and leftover prose
+fn parse_edits(response: &str) -> Result<(Vec<RawEdit>, String), String> {
+    if response.contains("<<<<<<< SEARCH") {
+        parse_search_replace(response)
+    } else if response.lines().any(|line| line.starts_with("@@")) {
+        parse_unified_diff(response)
+    } else {
+        Err("The reply didn't contain any edits".to_string())
+    }
+}
+
+fn parse_search_replace(response: &str) -> Result<(Vec<RawEdit>, String), String> {
+    enum State {
+        Outside,
+        Search,
+        Replace,
+    }
+
+    let mut edits = Vec::new();
+    let mut notes = Vec::new();
+    let mut search = Vec::new();
+    let mut replace = Vec::new();
+    let mut state = State::Outside;
+
+    for line in response.lines() {
+        match state {
+            State::Outside => {
+                if line.trim_end() == "<<<<<<< SEARCH" {
+                    state = State::Search;
+                } else if !line.trim_start().starts_with("```") {
+                    notes.push(line);
+                }
+            }
+            State::Search => {
+                if line.trim_end() == "=======" {
+                    state = State::Replace;
+                } else {
+                    search.push(line);
+                }
+            }
+            State::Replace => {
+                if line.trim_end() == ">>>>>>> REPLACE" {
+                    edits.push(RawEdit {
+                        search: join_lines(&search),
+                        replace: join_lines(&replace),
+                        line_hint: None,
+                    });
+                    search.clear();
+                    replace.clear();
+                    state = State::Outside;
+                } else {
+                    replace.push(line);
+                }
+            }
+        }
+    }
+
+    if !matches!(state, State::Outside) {
+        return Err("The reply ended in the middle of an edit block".to_string());
+    }
+    Ok((edits, notes.join("\n").trim().to_string()))
+}
+
+fn parse_unified_diff(response: &str) -> Result<(Vec<RawEdit>, String), String> {
+    let mut edits = Vec::new();
+    let mut notes = Vec::new();
+    let mut old = Vec::new();
+    let mut new = Vec::new();
+    let mut line_hint = None;
+    let mut in_hunk = false;
+    // Lines each side of the current hunk still has to come, from its header; None without counts
+    let mut remaining: Option<(usize, usize)> = None;
+
+    let mut finish = |old: &mut Vec<&str>, new: &mut Vec<&str>, line_hint: Option<usize>| {
+        if !old.is_empty() || !new.is_empty() {
+            edits.push(RawEdit {
+                search: join_lines(old),
+                replace: join_lines(new),
+                line_hint,
+            });
+        }
+        old.clear();
+        new.clear();
+    };
+
+    let lines: Vec<&str> = response.lines().collect();
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        index += 1;
+
+        if let Some(header) = line.strip_prefix("@@") {
+            finish(&mut old, &mut new, line_hint);
+            in_hunk = true;
+            // "@@ -12,5 +12,6 @@"
+            let old_range = header.split_whitespace().find_map(|part| part.strip_prefix('-'));
+            let new_range = header.split_whitespace().find_map(|part| part.strip_prefix('+'));
+            line_hint = old_range
+                .and_then(|range| range.split(',').next())
+                .and_then(|start| start.parse().ok());
+            remaining = old_range.and_then(hunk_length).zip(new_range.and_then(hunk_length));
+            continue;
+        }
+
+        // A hunk ends once it has all the lines its header promised, so prose after it is kept as notes
+        if remaining == Some((0, 0)) {
+            in_hunk = false;
+        }
+
+        // Inside a hunk, "--- x" and "+++ y" are usually just a removed "-- x" and an added "++ y".
+        // Without counts to go by, only the pair is taken as the next file's header; fences and
+        // "diff" lines end the hunk too.
+        if !in_hunk || remaining.is_none() {
+            let file_header = line.starts_with("--- ") && lines.get(index).is_some_and(|next| next.starts_with("+++ "));
+            if file_header {
+                index += 1;
+                in_hunk = false;
+                continue;
+            }
+            let boundary = line.starts_with("```") || line.starts_with("diff ");
+            let stray_header = !in_hunk && (line.starts_with("--- ") || line.starts_with("+++ "));
+            if boundary || stray_header {
+                in_hunk = false;
+                continue;
+            }
+        }
+        if !in_hunk {
+            // "\ No newline at end of file" trailing the hunk's last line
+            if !line.starts_with("\\ ") {
+                notes.push(line);
+            }
+            continue;
+        }
+
+        let (old_taken, new_taken) = if let Some(removed) = line.strip_prefix('-') {
+            old.push(removed);
+            (1, 0)
+        } else if let Some(added) = line.strip_prefix('+') {
+            new.push(added);
+            (0, 1)
+        } else if line.starts_with('\\') {
+            // "\ No newline at end of file"
+            (0, 0)
+        } else {
+            let context = line.strip_prefix(' ').unwrap_or(line);
+            old.push(context);
+            new.push(context);
+            (1, 1)
+        };
+        if let Some((old_left, new_left)) = remaining.as_mut() {
+            *old_left = old_left.saturating_sub(old_taken);
+            *new_left = new_left.saturating_sub(new_taken);
+        }
+    }
+    finish(&mut old, &mut new, line_hint);
+
+    Ok((edits, notes.join("\n").trim().to_string()))
+}
+
+// The line count in a "12,5" hunk range; a bare "12" means one line
+fn hunk_length(range: &str) -> Option<usize> {
+    match range.split_once(',') {
+        Some((_, count)) => count.parse().ok(),
+        None => range.parse::<usize>().ok().map(|_| 1),
+    }
+}
+
+fn join_lines(lines: &[&str]) -> String {
+    lines.join("\n")
+}
+
+// Locate each edit in the buffer; every search text must be found and edits may not overlap
+fn resolve_edits(content: &str, raw_edits: Vec<RawEdit>) -> Result<Vec<ProposedEdit>, String> {
+    let mut edits = Vec::new();
+
+    for (index, raw) in raw_edits.into_iter().enumerate() {
+        if raw.search.is_empty() {
+            return Err(format!("Edit {} has nothing to search for", index + 1));
+        }
+
+        let matches: Vec<usize> = content.match_indices(&raw.search).map(|(i, _)| i).collect();
+        let start = match (matches.as_slice(), raw.line_hint) {
+            ([], _) => {
+                let first_line = raw.search.lines().next().unwrap_or_default().trim();
+                return Err(format!("Edit {} doesn't match the buffer (near \"{}\")", index + 1, first_line));
+            }
+            ([only], _) => *only,
+            (many, Some(line)) => *many
+                .iter()
+                .min_by_key(|&&offset| (line_number(content, offset) as isize - line as isize).abs())
+                .unwrap_or(&many[0]),
+            (_, None) => {
+                return Err(format!("Edit {} matches more than one place in the buffer", index + 1));
+            }
+        };
+
+        edits.push(ProposedEdit {
+            start,
+            end: start + raw.search.len(),
+            original: raw.search,
+            replacement: raw.replace,
+        });
+    }
+
+    edits.sort_by_key(|edit| edit.start);
+    for pair in edits.windows(2) {
+        if pair[0].end > pair[1].start {
+            return Err("Two edits change the same lines".to_string());
+        }
+    }
+    Ok(edits)
+}
+
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+fn line_start(content: &str, offset: usize) -> usize {
+    content[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn line_end(content: &str, offset: usize) -> usize {
+    content[offset..].find('\n').map(|i| offset + i).unwrap_or(content.len())
+}
+
+// One hunk per edit, widened to whole lines with a little context
+fn render_diff(path: &str, content: &str, edits: &[ProposedEdit]) -> String {
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let all_lines: Vec<&str> = content.split('\n').collect();
+    let mut offset_delta: isize = 0;
+
+    for edit in edits {
+        let block_start = line_start(content, edit.start);
+        let block_end = line_end(content, edit.end);
+        let old_block = &content[block_start..block_end];
+        let new_block = format!(
+            "{}{}{}",
+            &content[block_start..edit.start],
+            edit.replacement,
+            &content[edit.end..block_end]
+        );
+
+        let first_line = line_number(content, block_start) - 1;
+        let old_lines: Vec<&str> = old_block.split('\n').collect();
+        let new_lines: Vec<&str> = new_block.split('\n').collect();
+
+        let before_start = first_line.saturating_sub(PREVIEW_CONTEXT_LINES);
+        let after_start = first_line + old_lines.len();
+        let after_end = (after_start + PREVIEW_CONTEXT_LINES).min(all_lines.len());
+        let before = &all_lines[before_start..first_line];
+        let after = &all_lines[after_start.min(all_lines.len())..after_end];
+
+        let old_count = before.len() + old_lines.len() + after.len();
+        let new_count = before.len() + new_lines.len() + after.len();
+        let new_start = (before_start as isize + offset_delta) as usize;
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            before_start + 1,
+            old_count,
+            new_start + 1,
+            new_count
+        ));
+        for line in before {
+            diff.push_str(&format!(" {}\n", line));
+        }
+        for line in &old_lines {
+            diff.push_str(&format!("-{}\n", line));
+        }
+        for line in &new_lines {
+            diff.push_str(&format!("+{}\n", line));
+        }
+        for line in after {
+            diff.push_str(&format!(" {}\n", line));
+        }
+
+        offset_delta += new_lines.len() as isize - old_lines.len() as isize;
+    }
+
+    diff
+}
+
+pub fn build_proposal(
+    buffer_id: usize,
+    path: &str,
+    content: &str,
+    response: &str,
+) -> Result<EditProposal, String> {
+    let (raw_edits, notes) = parse_edits(response)?;
+    if raw_edits.is_empty() {
+        return Err("The reply didn't contain any edits".to_string());
+    }
+    let edits = resolve_edits(content, raw_edits)?;
+    let diff = render_diff(path, content, &edits);
+
+    Ok(EditProposal {
+        id: uuid::Uuid::new_v4().to_string(),
+        buffer_id,
+        edits,
+        diff,
+        notes,
+    })
+}
+
+// Proposals waiting for the user to accept or discard them
+pub struct AiEditManager {
+    proposals: Mutex<HashMap<String, EditProposal>>,
+}
+
+impl AiEditManager {
+    pub fn new() -> Self {
+        Self {
+            proposals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(&self, proposal: EditProposal) {
+        self.proposals.lock().insert(proposal.id.clone(), proposal);
+    }
+
+    pub fn take(&self, id: &str) -> Result<EditProposal, String> {
+        self.proposals
+            .lock()
+            .remove(id)
+            .ok_or_else(|| format!("Edit proposal {} not found", id))
+    }
+
+    // Held throughout so a proposal can't be applied twice, and only dropped once it has been,
+    // so one that no longer fits the buffer can still be looked at or discarded
+    pub fn apply(&self, id: &str, buffer_manager: &BufferManager) -> Result<(), String> {
+        let mut proposals = self.proposals.lock();
+        let proposal = proposals
+            .get(id)
+            .ok_or_else(|| format!("Edit proposal {} not found", id))?;
+        apply_proposal(proposal, buffer_manager)?;
+        proposals.remove(id);
+        Ok(())
+    }
+}
+
+// Apply every edit as a single replacement so it lands, and undoes, as one history entry
+fn apply_proposal(proposal: &EditProposal, buffer_manager: &BufferManager) -> Result<(), String> {
+    let (Some(first), Some(last)) = (proposal.edits.first(), proposal.edits.last()) else {
+        return Ok(());
+    };
+
+    let buffer = buffer_manager
+        .get_buffer(proposal.buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", proposal.buffer_id))?;
+    let (span_start, span_end) = (first.start, last.end);
+    let original_span = buffer
+        .content
+        .get(span_start..span_end)
+        .ok_or_else(|| "Buffer has changed since the edit was prepared".to_string())?;
+
+    let mut replacement = String::new();
+    let mut cursor = span_start;
+    for edit in &proposal.edits {
+        if buffer.content.get(edit.start..edit.end) != Some(edit.original.as_str()) {
+            return Err("Buffer has changed since the edit was prepared".to_string());
+        }
+        replacement.push_str(&buffer.content[cursor..edit.start]);
+        replacement.push_str(&edit.replacement);
+        cursor = edit.end;
+    }
+
+    buffer_manager.apply_edit_if_unchanged(
+        proposal.buffer_id,
+        span_start,
+        span_end,
+        original_span,
+        &replacement,
+    )
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ai_propose_edits(
+    buffer_id: usize,
+    instruction: String,
+    scope: Option<String>, // "selection" (default) or "file"
+    provider: Option<String>,
+    request_id: Option<String>,
+    app_handle: AppHandle,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    providers: tauri::State<'_, AiProviderStore>,
+    streams: tauri::State<'_, AiStreamManager>,
+    edits: tauri::State<'_, AiEditManager>,
+) -> Result<EditProposal, String> {
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let provider = providers.provider(provider.as_deref())?;
+
+    let mut request = EditorContext::from_buffer(&buffer, scope.as_deref() == Some("file"))
+        .request(AiAction::Ask, &instruction);
+    request.messages.push(ChatMessage {
+        role: "user".to_string(),
+        content: EDIT_FORMAT_INSTRUCTIONS.to_string(),
+    });
+
+    let response = match request_id.as_deref() {
+        Some(request_id) => streams.run(&app_handle, request_id, provider.as_ref(), &request).await?,
+        None => provider.chat(&request).await?,
+    };
+
+    let path = buffer
+        .path
+        .as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| format!("untitled-{}", buffer_id));
+    let proposal = build_proposal(buffer_id, &path, &buffer.content, &response.text)?;
+    edits.insert(proposal.clone());
+    Ok(proposal)
+}
+
+#[tauri::command]
+pub fn ai_apply_edits(
+    proposal_id: String,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    edits: tauri::State<'_, AiEditManager>,
+) -> Result<(), String> {
+    edits.apply(&proposal_id, &buffer_manager)
+}
+
+#[tauri::command]
+pub fn ai_discard_edits(proposal_id: String, edits: tauri::State<'_, AiEditManager>) -> Result<(), String> {
+    edits.take(&proposal_id).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn searches(edits: &[RawEdit]) -> Vec<(&str, &str)> {
+        edits.iter().map(|edit| (edit.search.as_str(), edit.replace.as_str())).collect()
+    }
+
+    #[test]
+    fn dashes_inside_a_hunk_are_content() {
+        let reply = "--- a/notes.md\n+++ b/notes.md\n@@ -1,3 +1,3 @@\n title\n--- old rule\n+++ new rule\n end\n";
+        let (edits, notes) = parse_unified_diff(reply).unwrap();
+        assert_eq!(searches(&edits), vec![("title\n-- old rule\nend", "title\n++ new rule\nend")]);
+        assert_eq!(edits[0].line_hint, Some(1));
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn file_headers_after_a_hunk_start_the_next_file() {
+        let reply = "Two changes:\n--- a/one.rs\n+++ b/one.rs\n@@ -4 +4 @@\n-let a = 1;\n+let a = 2;\n--- a/two.rs\n+++ b/two.rs\n@@ -9,2 +9,2 @@\n fn b() {\n-    old();\n+    new();\n";
+        let (edits, notes) = parse_unified_diff(reply).unwrap();
+        assert_eq!(
+            searches(&edits),
+            vec![("let a = 1;", "let a = 2;"), ("fn b() {\n    old();", "fn b() {\n    new();")]
+        );
+        assert_eq!(edits[1].line_hint, Some(9));
+        assert_eq!(notes, "Two changes:");
+    }
+
+    #[test]
+    fn prose_after_a_complete_hunk_is_kept_as_notes() {
+        let reply = "@@ -2,2 +2,2 @@\n fn a() {\n-    old();\n+    new();\n\\ No newline at end of file\nThis swaps the call.\n\nIt keeps the signature.\n";
+        let (edits, notes) = parse_unified_diff(reply).unwrap();
+        assert_eq!(searches(&edits), vec![("fn a() {\n    old();", "fn a() {\n    new();")]);
+        assert_eq!(notes, "This swaps the call.\n\nIt keeps the signature.");
+    }
+
+    #[test]
+    fn hunks_without_counts_still_end_at_a_file_header() {
+        let reply = "@@ @@\n-x\n+y\n--- a/b.rs\n+++ b/b.rs\n@@ @@\n-z\n+w\n";
+        let (edits, _) = parse_unified_diff(reply).unwrap();
+        assert_eq!(searches(&edits), vec![("x", "y"), ("z", "w")]);
+    }
+
+    #[test]
+    fn a_failed_apply_keeps_the_proposal() {
+        let buffers = BufferManager::new();
+        let buffer_id = buffers.create_buffer("one\ntwo\nthree\n".to_string(), None);
+        let reply = "<<<<<<< SEARCH\ntwo\n=======\nTWO\n>>>>>>> REPLACE\n";
+        let proposal = build_proposal(buffer_id, "file.txt", "one\ntwo\nthree\n", reply).unwrap();
+        let id = proposal.id.clone();
+        let manager = AiEditManager::new();
+        manager.insert(proposal);
+
+        buffers.update_buffer_content(buffer_id, "one\n2\nthree\n".to_string()).unwrap();
+        assert!(manager.apply(&id, &buffers).is_err());
+
+        buffers.update_buffer_content(buffer_id, "one\ntwo\nthree\n".to_string()).unwrap();
+        manager.apply(&id, &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(buffer_id).unwrap().content, "one\nTWO\nthree\n");
+        assert!(manager.apply(&id, &buffers).is_err());
+    }
+}
@@ -2,7 +2,6 @@ use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use tauri;
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
 
@@ -17,6 +16,12 @@ pub struct Buffer {
     pub cursor_position: usize,
     pub scroll_position: usize,
     pub language: Option<String>,
+    // Byte range of the current selection, if any
+    #[serde(default)]
+    pub selection: Option<(usize, usize)>,
+    // Set for buffers showing a file as of a past revision; edits and saves are refused
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,6 +29,9 @@ pub struct BufferEdit {
     pub start: usize,
     pub end: usize,
     pub text: String,
+    // Text that was between start and end, so the edit can be undone
+    #[serde(default)]
+    pub replaced: String,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -37,12 +45,18 @@ pub struct BufferInfo {
     pub language: Option<String>,
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
+    #[serde(default)]
+    pub read_only: bool,
 }
 
+// Called with a buffer's id after its content changes or it's closed
+type ChangeListener = Box<dyn Fn(usize) + Send + Sync>;
+
 pub struct BufferManager {
     buffers: RwLock<HashMap<usize, Buffer>>,
     next_id: RwLock<usize>,
     edit_history: RwLock<HashMap<usize, Vec<BufferEdit>>>,
+    listeners: RwLock<Vec<ChangeListener>>,
 }
 
 impl BufferManager {
@@ -51,6 +65,18 @@ impl BufferManager {
             buffers: RwLock::new(HashMap::new()),
             next_id: RwLock::new(1),
             edit_history: RwLock::new(HashMap::new()),
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+    
+    pub fn on_change(&self, listener: impl Fn(usize) + Send + Sync + 'static) {
+        self.listeners.write().push(Box::new(listener));
+    }
+    
+    // Listeners may read buffers, so this runs only once the buffer locks are released
+    fn notify(&self, id: usize) {
+        for listener in self.listeners.read().iter() {
+            listener(id);
         }
     }
     
@@ -60,7 +86,7 @@ impl BufferManager {
         *next_id += 1;
         
         let now = Utc::now();
-        let language = self.detect_language(&path, &content);
+        let language = Self::detect_language(&path, &content);
         
         let buffer = Buffer { 
             content: content.clone(),
@@ -71,6 +97,8 @@ impl BufferManager {
             cursor_position: 0,
             scroll_position: 0,
             language,
+            selection: None,
+            read_only: false,
         };
         
         self.buffers.write().insert(id, buffer);
@@ -78,7 +106,15 @@ impl BufferManager {
         id
     }
     
-    fn detect_language(&self, path: &Option<PathBuf>, content: &str) -> Option<String> {
+    pub fn create_read_only_buffer(&self, content: String, path: Option<PathBuf>) -> usize {
+        let id = self.create_buffer(content, path);
+        if let Some(buffer) = self.buffers.write().get_mut(&id) {
+            buffer.read_only = true;
+        }
+        id
+    }
+    
+    pub fn detect_language(path: &Option<PathBuf>, content: &str) -> Option<String> {
         if let Some(path) = path {
             if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
                 return match ext.to_lowercase().as_str() {
@@ -136,6 +172,7 @@ impl BufferManager {
                 language: buffer.language.clone(),
                 created_at: buffer.created_at,
                 modified_at: buffer.modified_at,
+                read_only: buffer.read_only,
             })
         } else {
             None
@@ -155,26 +192,42 @@ impl BufferManager {
                 language: buffer.language.clone(),
                 created_at: buffer.created_at,
                 modified_at: buffer.modified_at,
+                read_only: buffer.read_only,
             }
         }).collect()
     }
     
     pub fn update_buffer_content(&self, id: usize, content: String) -> Result<(), String> {
-        let mut buffers = self.buffers.write();
-        if let Some(buffer) = buffers.get_mut(&id) {
+        {
+            let mut buffers = self.buffers.write();
+            let buffer = buffers.get_mut(&id).ok_or_else(|| format!("Buffer {} not found", id))?;
+            if buffer.read_only {
+                return Err(format!("Buffer {} is read-only", id));
+            }
             buffer.content = content;
             buffer.modified = true;
             buffer.modified_at = Utc::now();
+        }
+        self.notify(id);
+        Ok(())
+    }
+    
+    pub fn update_cursor_position(&self, id: usize, position: usize) -> Result<(), String> {
+        let mut buffers = self.buffers.write();
+        if let Some(buffer) = buffers.get_mut(&id) {
+            buffer.cursor_position = position;
             Ok(())
         } else {
             Err(format!("Buffer {} not found", id))
         }
     }
     
-    pub fn update_cursor_position(&self, id: usize, position: usize) -> Result<(), String> {
+    pub fn update_selection(&self, id: usize, selection: Option<(usize, usize)>) -> Result<(), String> {
         let mut buffers = self.buffers.write();
         if let Some(buffer) = buffers.get_mut(&id) {
-            buffer.cursor_position = position;
+            buffer.selection = selection
+                .map(|(start, end)| (start.min(end), start.max(end)))
+                .filter(|(start, end)| start != end);
             Ok(())
         } else {
             Err(format!("Buffer {} not found", id))
@@ -191,10 +244,10 @@ impl BufferManager {
         }
     }
     
-    pub fn update_buffer(&self, id: usize, content: String) -> Result<(), String> {
+    pub fn set_language(&self, id: usize, language: Option<String>) -> Result<(), String> {
         let mut buffers = self.buffers.write();
         if let Some(buffer) = buffers.get_mut(&id) {
-            buffer.content = content;
+            buffer.language = language;
             Ok(())
         } else {
             Err(format!("Buffer {} not found", id))
@@ -202,20 +255,46 @@ impl BufferManager {
     }
     
     pub fn apply_edit(&self, id: usize, start: usize, end: usize, text: &str) -> Result<(), String> {
+        self.apply_edit_checked(id, start, end, None, text)
+    }
+    
+    // Like apply_edit, but fails instead of editing if the range no longer holds `expected`
+    pub fn apply_edit_if_unchanged(&self, id: usize, start: usize, end: usize, expected: &str, text: &str) -> Result<(), String> {
+        self.apply_edit_checked(id, start, end, Some(expected), text)
+    }
+    
+    fn apply_edit_checked(&self, id: usize, start: usize, end: usize, expected: Option<&str>, text: &str) -> Result<(), String> {
+        self.edit_content(id, start, end, expected, text)?;
+        self.notify(id);
+        Ok(())
+    }
+    
+    fn edit_content(&self, id: usize, start: usize, end: usize, expected: Option<&str>, text: &str) -> Result<(), String> {
         let mut buffers = self.buffers.write();
         if let Some(buffer) = buffers.get_mut(&id) {
+            if buffer.read_only {
+                return Err(format!("Buffer {} is read-only", id));
+            }
             let content = &buffer.content;
             
             // Ensure start and end are valid
-            if start > content.len() || end > content.len() {
+            if start > end || end > content.len()
+                || !content.is_char_boundary(start) || !content.is_char_boundary(end) {
                 return Err("Invalid range".to_string());
             }
             
+            if let Some(expected) = expected {
+                if &content[start..end] != expected {
+                    return Err("Buffer has changed since the edit was prepared".to_string());
+                }
+            }
+            
             // Store edit in history
             let edit = BufferEdit {
                 start,
                 end,
                 text: text.to_string(),
+                replaced: content[start..end].to_string(),
                 timestamp: Utc::now(),
             };
             
@@ -243,6 +322,41 @@ impl BufferManager {
         }
     }
     
+    // Revert the most recent edit and drop it from the history
+    pub fn undo_edit(&self, id: usize) -> Result<BufferEdit, String> {
+        let edit = self.undo_last(id)?;
+        self.notify(id);
+        Ok(edit)
+    }
+    
+    fn undo_last(&self, id: usize) -> Result<BufferEdit, String> {
+        let mut buffers = self.buffers.write();
+        let buffer = buffers.get_mut(&id).ok_or_else(|| format!("Buffer {} not found", id))?;
+        if buffer.read_only {
+            return Err(format!("Buffer {} is read-only", id));
+        }
+        
+        let mut history = self.edit_history.write();
+        let edit = history
+            .get_mut(&id)
+            .and_then(|buffer_history| buffer_history.pop())
+            .ok_or_else(|| "Nothing to undo".to_string())?;
+        
+        let end = edit.start + edit.text.len();
+        if buffer.content.get(edit.start..end) != Some(edit.text.as_str()) {
+            // Content was replaced wholesale since; keep the entry rather than corrupting the buffer
+            if let Some(buffer_history) = history.get_mut(&id) {
+                buffer_history.push(edit);
+            }
+            return Err("Buffer has changed since the last edit".to_string());
+        }
+        
+        buffer.content.replace_range(edit.start..end, &edit.replaced);
+        buffer.modified = true;
+        buffer.modified_at = Utc::now();
+        Ok(edit)
+    }
+    
     pub fn get_edit_history(&self, id: usize) -> Vec<BufferEdit> {
         let history = self.edit_history.read();
         history.get(&id).cloned().unwrap_or_default()
@@ -279,8 +393,17 @@ impl BufferManager {
     }
     
     pub fn replace_in_buffer(&self, id: usize, query: &str, replacement: &str, case_sensitive: bool) -> Result<usize, String> {
+        let replacements = self.replace_content(id, query, replacement, case_sensitive)?;
+        self.notify(id);
+        Ok(replacements)
+    }
+    
+    fn replace_content(&self, id: usize, query: &str, replacement: &str, case_sensitive: bool) -> Result<usize, String> {
         let mut buffers = self.buffers.write();
         if let Some(buffer) = buffers.get_mut(&id) {
+            if buffer.read_only {
+                return Err(format!("Buffer {} is read-only", id));
+            }
             let original_content = buffer.content.clone();
             
             let new_content = if case_sensitive {
@@ -329,15 +452,17 @@ impl BufferManager {
     }
     
     pub fn close_buffer(&self, id: usize) -> Result<(), String> {
-        let mut buffers = self.buffers.write();
-        let mut history = self.edit_history.write();
-        
-        if buffers.remove(&id).is_some() {
+        {
+            let mut buffers = self.buffers.write();
+            let mut history = self.edit_history.write();
+            
+            if buffers.remove(&id).is_none() {
+                return Err(format!("Buffer {} not found", id));
+            }
             history.remove(&id);
-            Ok(())
-        } else {
-            Err(format!("Buffer {} not found", id))
         }
+        self.notify(id);
+        Ok(())
     }
 }
 
@@ -388,6 +513,11 @@ pub fn update_cursor_position(buffer_id: usize, position: usize, buffer_manager:
     buffer_manager.update_cursor_position(buffer_id, position)
 }
 
+#[tauri::command]
+pub fn update_selection(buffer_id: usize, start: Option<usize>, end: Option<usize>, buffer_manager: tauri::State<'_, BufferManager>) -> Result<(), String> {
+    buffer_manager.update_selection(buffer_id, start.zip(end))
+}
+
 #[tauri::command]
 pub fn update_scroll_position(buffer_id: usize, position: usize, buffer_manager: tauri::State<'_, BufferManager>) -> Result<(), String> {
     buffer_manager.update_scroll_position(buffer_id, position)
@@ -408,6 +538,11 @@ pub fn get_edit_history(buffer_id: usize, buffer_manager: tauri::State<'_, Buffe
     Ok(buffer_manager.get_edit_history(buffer_id))
 }
 
+#[tauri::command]
+pub fn undo_edit(buffer_id: usize, buffer_manager: tauri::State<'_, BufferManager>) -> Result<BufferEdit, String> {
+    buffer_manager.undo_edit(buffer_id)
+}
+
 #[tauri::command]
 pub fn update_buffer_content_command(buffer_id: usize, content: String, buffer_manager: tauri::State<'_, BufferManager>) -> Result<(), String> {
     buffer_manager.update_buffer_content(buffer_id, content)
@@ -416,6 +551,9 @@ pub fn update_buffer_content_command(buffer_id: usize, content: String, buffer_m
 #[tauri::command]
 pub fn save_file(buffer_id: usize, path: Option<String>, buffer_manager: tauri::State<'_, BufferManager>) -> Result<String, String> {
     if let Some(buffer) = buffer_manager.get_buffer(buffer_id) {
+        if buffer.read_only {
+            return Err(format!("Buffer {} is read-only", buffer_id));
+        }
         let file_path = if let Some(path) = path {
             PathBuf::from(path)
         } else if let Some(existing_path) = buffer.path {
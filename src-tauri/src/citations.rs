@@ -0,0 +1,243 @@
+// بسم الله الرحمن الرحيم
+// Details for web search citations: page titles and descriptions, and the [n] markers that point at them
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::http;
+use crate::perplexity::SearchResult;
+
+const FETCH_CONCURRENCY: usize = 4;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+// Titles and descriptions live in <head>, so there's no need for the whole page
+const MAX_PAGE_BYTES: usize = 256 * 1024;
+const MAX_SNIPPET_CHARS: usize = 300;
+
+static MARKER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[(\d{1,3})\]").unwrap());
+static TITLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static META: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+static ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?is)([a-z:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+// A [n] in the answer; `start` and `end` are byte offsets, `result` indexes into the results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CitationMarker {
+    pub number: usize,
+    pub start: usize,
+    pub end: usize,
+    pub result: Option<usize>,
+}
+
+pub fn find_markers(answer: &str, result_count: usize) -> Vec<CitationMarker> {
+    MARKER
+        .captures_iter(answer)
+        .filter_map(|captures| {
+            let whole = captures.get(0)?;
+            let number: usize = captures[1].parse().ok()?;
+            Some(CitationMarker {
+                number,
+                start: whole.start(),
+                end: whole.end(),
+                // Markers count from 1
+                result: (number >= 1 && number <= result_count).then(|| number - 1),
+            })
+        })
+        .collect()
+}
+
+pub fn needs_metadata(result: &SearchResult) -> bool {
+    result.title.is_empty() || result.title == result.url || result.snippet.is_empty()
+}
+
+// Fill in missing titles and snippets from the pages themselves, a few at a time.
+// Pages that fail or time out keep what they had.
+pub async fn fetch_metadata(mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+    let permits = Arc::new(Semaphore::new(FETCH_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+
+    for (index, result) in results.iter().enumerate() {
+        if !needs_metadata(result) || !result.url.starts_with("http") {
+            continue;
+        }
+        let url = result.url.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            let metadata = tokio::time::timeout(FETCH_TIMEOUT, page_metadata(&url)).await.ok()??;
+            Some((index, metadata))
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        let Ok(Some((index, (title, description)))) = joined else {
+            continue;
+        };
+        let result = &mut results[index];
+        if let Some(title) = title.filter(|_| result.title.is_empty() || result.title == result.url) {
+            result.title = title;
+        }
+        if let Some(description) = description.filter(|_| result.snippet.is_empty()) {
+            result.snippet = description;
+        }
+    }
+    results
+}
+
+async fn page_metadata(url: &str) -> Option<(Option<String>, Option<String>)> {
+    let mut response = http::client()
+        .get(url)
+        .header("User-Agent", "Mozilla/5.0 (compatible; vuno)")
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    // Servers that don't say are given the benefit of the doubt
+    let is_html = match response.headers().get(reqwest::header::CONTENT_TYPE) {
+        Some(value) => value.to_str().is_ok_and(|value| value.contains("html")),
+        None => true,
+    };
+    if !is_html {
+        return None;
+    }
+
+    let mut body = Vec::new();
+    while body.len() < MAX_PAGE_BYTES {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    Some(html_metadata(&String::from_utf8_lossy(&body)))
+}
+
+// The page title and description from a page's markup
+fn html_metadata(html: &str) -> (Option<String>, Option<String>) {
+    let title = TITLE
+        .captures(html)
+        .map(|captures| clean_text(&captures[1]))
+        .filter(|title| !title.is_empty());
+
+    let mut description = None;
+    for tag in META.find_iter(html) {
+        let mut name = None;
+        let mut content = None;
+        for attribute in ATTRIBUTE.captures_iter(tag.as_str()) {
+            let value = attribute.get(2).or_else(|| attribute.get(3)).map_or("", |v| v.as_str());
+            match attribute[1].to_lowercase().as_str() {
+                "name" | "property" => name = Some(value.to_lowercase()),
+                "content" => content = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        match (name.as_deref(), content) {
+            (Some("description"), Some(content)) => {
+                description = Some(content);
+                break;
+            }
+            // Open Graph only when there's no plain description
+            (Some("og:description"), Some(content)) if description.is_none() => description = Some(content),
+            _ => {}
+        }
+    }
+    let description = description
+        .map(|d| truncate(&clean_text(&d), MAX_SNIPPET_CHARS))
+        .filter(|d| !d.is_empty());
+
+    (title, description)
+}
+
+pub(crate) fn clean_text(text: &str) -> String {
+    // &amp; goes last, so an escaped entity like &amp;lt; comes out as the text "&lt;"
+    let decoded = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    decoded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(title: &str, url: &str, snippet: &str) -> SearchResult {
+        SearchResult {
+            title: title.to_string(),
+            url: url.to_string(),
+            snippet: snippet.to_string(),
+            date: None,
+        }
+    }
+
+    #[test]
+    fn markers_point_at_results_counting_from_one() {
+        let markers = find_markers("Rust is fast[1][2]. Also safe [0] and [3], see [12345].", 2);
+        let found: Vec<(usize, Option<usize>)> = markers.iter().map(|m| (m.number, m.result)).collect();
+        assert_eq!(found, vec![(1, Some(0)), (2, Some(1)), (0, None), (3, None)]);
+        assert_eq!((markers[0].start, markers[0].end), (12, 15));
+    }
+
+    #[test]
+    fn results_without_a_title_or_snippet_need_metadata() {
+        assert!(!needs_metadata(&result("The Book", "https://a.example", "Learn Rust")));
+        assert!(needs_metadata(&result("", "https://a.example", "Learn Rust")));
+        assert!(needs_metadata(&result("https://a.example", "https://a.example", "Learn Rust")));
+        assert!(needs_metadata(&result("The Book", "https://a.example", "")));
+    }
+
+    #[test]
+    fn reads_the_title_and_description() {
+        let html = r#"<html><head>
+            <TITLE lang="en">
+                The Rust   Book &amp; Friends
+            </TITLE>
+            <meta property="og:description" content="Open Graph text">
+            <meta name='Description' content="Learn &quot;Rust&quot;">
+        </head></html>"#;
+        let (title, description) = html_metadata(html);
+        assert_eq!(title.as_deref(), Some("The Rust Book & Friends"));
+        assert_eq!(description.as_deref(), Some("Learn \"Rust\""));
+    }
+
+    #[test]
+    fn falls_back_to_the_open_graph_description() {
+        let html = r#"<title></title><meta charset="utf-8"><meta content="Shared text" property="og:description" />"#;
+        let (title, description) = html_metadata(html);
+        assert_eq!(title, None);
+        assert_eq!(description.as_deref(), Some("Shared text"));
+
+        assert_eq!(html_metadata("<p>No head at all</p>"), (None, None));
+    }
+
+    #[test]
+    fn long_descriptions_are_truncated() {
+        let html = format!(r#"<meta name="description" content="{}">"#, "word ".repeat(100));
+        let description = html_metadata(&html).1.unwrap();
+        assert!(description.ends_with('…'));
+        assert_eq!(description.chars().count(), MAX_SNIPPET_CHARS);
+    }
+
+    #[test]
+    fn escaped_entities_stay_escaped() {
+        assert_eq!(clean_text("a &amp;lt; b &lt; c"), "a &lt; b < c");
+        assert_eq!(clean_text("Tom&#39;s&nbsp;&amp;&nbsp;Jerry&#x27;s"), "Tom's & Jerry's");
+    }
+}
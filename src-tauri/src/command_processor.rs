@@ -3,7 +3,10 @@
 use std::collections::HashMap;
 use std::process::Command;
 use serde::{Deserialize, Serialize};
-use crate::api::send_chat_message;
+use crate::ai::{AiProviderStore, ChatMessage, ChatRequest};
+use crate::ai_context::{self, AiAction};
+use crate::buffer::BufferManager;
+use crate::conversation::ConversationStore;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandResult {
@@ -21,8 +24,10 @@ pub struct CommandSuggestion {
     pub priority: i32,
 }
 
+type CommandHandler = Box<dyn Fn(&[String]) -> CommandResult + Send + Sync>;
+
 pub struct CommandProcessor {
-    commands: HashMap<String, Box<dyn Fn(&[String]) -> CommandResult + Send + Sync>>,
+    commands: HashMap<String, CommandHandler>,
     aliases: HashMap<String, String>,
 }
 
@@ -39,15 +44,13 @@ impl CommandProcessor {
     fn register_default_commands(&mut self) {
         // File operations
         self.register_command("ls", Box::new(|args| {
-            let path = args.get(0).map(|s| s.as_str()).unwrap_or(".");
+            let path = args.first().map(|s| s.as_str()).unwrap_or(".");
             match std::fs::read_dir(path) {
                 Ok(entries) => {
                     let mut output = String::new();
-                    for entry in entries {
-                        if let Ok(entry) = entry {
-                            let name = entry.file_name().to_string_lossy().to_string();
-                            output.push_str(&format!("{}\n", name));
-                        }
+                    for entry in entries.flatten() {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        output.push_str(&format!("{}\n", name));
                     }
                     CommandResult {
                         success: true,
@@ -179,6 +182,17 @@ System:
 AI:
   ai <prompt>      - Send prompt to AI assistant
   explain <text>   - Get AI explanation of text
+  explain this code - Explain the selection or the code around the cursor
+  optimize this code - Suggest improvements to the selected code
+  generate documentation - Write doc comments for the selection or file
+  help me debug    - Look for bugs using the code and its diagnostics
+  thread [list]    - List conversations (* marks the active one)
+  thread new <title> - Start a conversation that ai <prompt> continues
+  thread resume <id|title> - Continue an earlier conversation
+  thread fork [id] - Copy a conversation and continue the copy
+  thread delete <id|title> - Delete a conversation
+  thread system <prompt> - Set the active conversation's system prompt
+  thread close     - Go back to one-off ai questions
   
 Special:
   help             - Show this help message
@@ -214,7 +228,7 @@ Example: !git status
         self.aliases.insert("h".to_string(), "help".to_string());
     }
 
-    fn register_command(&mut self, name: &str, handler: Box<dyn Fn(&[String]) -> CommandResult + Send + Sync>) {
+    fn register_command(&mut self, name: &str, handler: CommandHandler) {
         self.commands.insert(name.to_string(), handler);
     }
 
@@ -230,8 +244,8 @@ Example: !git status
         }
 
         // Handle shell commands (prefixed with !)
-        if input.starts_with('!') {
-            return self.execute_shell_command(&input[1..], working_dir);
+        if let Some(shell_command) = input.strip_prefix('!') {
+            return self.execute_shell_command(shell_command, working_dir);
         }
 
         // Parse command and arguments
@@ -267,7 +281,7 @@ Example: !git status
     fn execute_shell_command(&self, command: &str, working_dir: Option<&str>) -> CommandResult {
         let mut cmd = if cfg!(target_os = "windows") {
             let mut c = Command::new("cmd");
-            c.args(&["/C", command]);
+            c.args(["/C", command]);
             c
         } else {
             let mut c = Command::new("sh");
@@ -318,7 +332,7 @@ Example: !git status
         let input_lower = input.to_lowercase();
 
         // Add command suggestions
-        for (command, _) in &self.commands {
+        for command in self.commands.keys() {
             if command.starts_with(&input_lower) {
                 let description = match command.as_str() {
                     "ls" => "List directory contents",
@@ -353,17 +367,37 @@ Example: !git status
         }
 
         // Add alias suggestions
-        for (alias, _) in &self.aliases {
+        for alias in self.aliases.keys() {
             if alias.starts_with(&input_lower) {
                 suggestions.push(CommandSuggestion {
                     command: alias.clone(),
-                    description: format!("Alias for command"),
+                    description: "Alias for command".to_string(),
                     category: "Alias".to_string(),
                     priority: 60,
                 });
             }
         }
 
+        // Add conversation thread suggestions
+        for (command, description) in [
+            ("thread list", "List AI conversations"),
+            ("thread new ", "Start a new AI conversation"),
+            ("thread resume ", "Continue an AI conversation"),
+            ("thread fork", "Fork the active AI conversation"),
+            ("thread delete ", "Delete an AI conversation"),
+            ("thread system ", "Set the conversation's system prompt"),
+            ("thread close", "Stop continuing the active conversation"),
+        ] {
+            if input_lower.len() >= 2 && command.starts_with(&input_lower) {
+                suggestions.push(CommandSuggestion {
+                    command: command.to_string(),
+                    description: description.to_string(),
+                    category: "AI".to_string(),
+                    priority: 70,
+                });
+            }
+        }
+
         // Add AI suggestions for natural language
         if input.len() > 3 && !input.starts_with('!') && !self.commands.contains_key(&input_lower) {
             suggestions.push(CommandSuggestion {
@@ -382,71 +416,227 @@ Example: !git status
         }
 
         // Sort by priority
-        suggestions.sort_by(|a, b| b.priority.cmp(&a.priority));
+        suggestions.sort_by_key(|s| std::cmp::Reverse(s.priority));
         suggestions
     }
 }
 
 // Tauri commands
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_enhanced_command(
     command: String,
     api_key: Option<String>,
     working_dir: Option<String>,
+    provider: Option<String>,
+    buffer_id: Option<usize>,
+    provider_store: tauri::State<'_, AiProviderStore>,
+    conversation_store: tauri::State<'_, ConversationStore>,
+    buffer_manager: tauri::State<'_, BufferManager>,
 ) -> Result<CommandResult, String> {
     let processor = CommandProcessor::new();
     
+    // Conversation threads
+    if command == "thread" || command.starts_with("thread ") {
+        return Ok(execute_thread_command(&command[6..], &conversation_store));
+    }
+    
+    // AI commands about the code in the current buffer, e.g. "explain this code"
+    if let Some((action, question)) = AiAction::from_command(&command) {
+        let buffer = match buffer_id.and_then(|id| buffer_manager.get_buffer(id)) {
+            Some(buffer) => buffer,
+            None => {
+                return Ok(CommandResult {
+                    success: false,
+                    output: String::new(),
+                    error: Some("Open a file to use this command".to_string()),
+                    command_type: "ai".to_string(),
+                });
+            }
+        };
+        
+        let result = match provider_store.provider_with_key(provider.as_deref(), api_key.as_deref()) {
+            Ok(provider) => {
+                // "generate documentation" covers the whole file unless something is selected
+                let whole_file = action == AiAction::Document && buffer.selection.is_none();
+                ai_context::run_action(provider.as_ref(), &buffer, action, &question, whole_file, None).await
+            }
+            Err(e) => Err(e),
+        };
+        
+        return Ok(match result {
+            Ok(response) => CommandResult {
+                success: true,
+                output: response.text,
+                error: None,
+                command_type: "ai".to_string(),
+            },
+            Err(e) => CommandResult {
+                success: false,
+                output: String::new(),
+                error: Some(format!("AI Error: {}", e)),
+                command_type: "ai".to_string(),
+            },
+        });
+    }
+    
     // Handle AI commands
     if command.starts_with("ai ") || command.starts_with("explain ") {
-        let prompt = if command.starts_with("ai ") {
-            &command[3..]
+        let prompt = command
+            .strip_prefix("ai ")
+            .or_else(|| command.strip_prefix("explain "))
+            .unwrap_or(&command);
+        
+        // `ai` continues a resumed thread; `explain` stays a one-off question
+        let thread = if command.starts_with("ai ") {
+            conversation_store.active().and_then(|id| conversation_store.get(&id).ok())
         } else {
-            &command[8..]
+            None
         };
         
-        if let Some(key) = api_key {
-            if !key.is_empty() {
-                // Create a simple message for AI
-                let messages = vec![
-                    serde_json::json!({
-                        "role": "user",
-                        "content": prompt
-                    })
-                ];
-                
-                match send_chat_message(messages, key).await {
-                    Ok(response) => {
-                        return Ok(CommandResult {
-                            success: true,
-                            output: response,
-                            error: None,
-                            command_type: "ai".to_string(),
-                        });
-                    }
-                    Err(e) => {
-                        return Ok(CommandResult {
-                            success: false,
-                            output: String::new(),
-                            error: Some(format!("AI Error: {}", e)),
-                            command_type: "ai".to_string(),
-                        });
-                    }
-                }
+        // A thread keeps answering with its own provider, as in the conversation panel.
+        // A key passed by the caller wins over the stored one.
+        let provider_name = match &thread {
+            Some(thread) => thread.provider.as_deref(),
+            None => provider.as_deref(),
+        };
+        let provider = match provider_store.provider_with_key(provider_name, api_key.as_deref()) {
+            Ok(provider) => provider,
+            Err(e) => {
+                return Ok(CommandResult {
+                    success: false,
+                    output: String::new(),
+                    error: Some(e),
+                    command_type: "ai".to_string(),
+                });
             }
+        };
+        
+        if let Some(thread) = thread {
+            return Ok(match conversation_store.send(&thread.id, prompt.to_string(), provider.as_ref(), None).await {
+                Ok(reply) => CommandResult {
+                    success: true,
+                    output: reply.content,
+                    error: None,
+                    command_type: "ai".to_string(),
+                },
+                Err(e) => CommandResult {
+                    success: false,
+                    output: String::new(),
+                    error: Some(format!("AI Error: {}", e)),
+                    command_type: "ai".to_string(),
+                },
+            });
         }
         
-        return Ok(CommandResult {
-            success: false,
-            output: String::new(),
-            error: Some("No API key provided for AI commands".to_string()),
-            command_type: "ai".to_string(),
-        });
+        // Create a simple message for AI
+        let request = ChatRequest {
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: prompt.to_string(),
+            }],
+            ..Default::default()
+        };
+        
+        return match provider.chat(&request).await {
+            Ok(response) => Ok(CommandResult {
+                success: true,
+                output: response.text,
+                error: None,
+                command_type: "ai".to_string(),
+            }),
+            Err(e) => Ok(CommandResult {
+                success: false,
+                output: String::new(),
+                error: Some(format!("AI Error: {}", e)),
+                command_type: "ai".to_string(),
+            }),
+        };
     }
     
     // Execute regular command with working directory
     Ok(processor.execute(&command, working_dir.as_deref()))
 }
 
+fn execute_thread_command(args: &str, store: &ConversationStore) -> CommandResult {
+    let args = args.trim();
+    let (action, rest) = match args.split_once(' ') {
+        Some((action, rest)) => (action, rest.trim()),
+        None => (args, ""),
+    };
+    
+    let result = match action {
+        "" | "list" => {
+            let active = store.active();
+            let lines: Vec<String> = store
+                .list()
+                .iter()
+                .map(|thread| {
+                    let marker = if active.as_deref() == Some(thread.id.as_str()) { "*" } else { " " };
+                    let short_id = thread.id.get(..8).unwrap_or(&thread.id);
+                    format!("{} {}  {} ({} messages)", marker, short_id, thread.title, thread.message_count)
+                })
+                .collect();
+            if lines.is_empty() {
+                Ok("No conversations yet. Start one with: thread new <title>".to_string())
+            } else {
+                Ok(lines.join("\n"))
+            }
+        }
+        "new" => {
+            let title = if rest.is_empty() { "New conversation" } else { rest };
+            store.create(title.to_string(), None, None).map(|thread| {
+                store.set_active(Some(thread.id.clone()));
+                format!("Started '{}'; ai <prompt> now continues it", thread.title)
+            })
+        }
+        "resume" => store.find(rest).map(|thread| {
+            store.set_active(Some(thread.id.clone()));
+            format!("Resumed '{}' ({} messages)", thread.title, thread.messages.len())
+        }),
+        "fork" => {
+            let source = if rest.is_empty() {
+                store.active().ok_or_else(|| "No active conversation to fork".to_string())
+            } else {
+                store.find(rest).map(|thread| thread.id)
+            };
+            source.and_then(|id| store.fork(&id, None, None)).map(|thread| {
+                store.set_active(Some(thread.id.clone()));
+                format!("Forked into '{}'", thread.title)
+            })
+        }
+        "delete" => store
+            .find(rest)
+            .and_then(|thread| store.delete(&thread.id).map(|_| format!("Deleted '{}'", thread.title))),
+        "system" => match store.active() {
+            Some(id) => store
+                .update(&id, |thread| thread.system_prompt = Some(rest.to_string()).filter(|p| !p.is_empty()))
+                .map(|_| "System prompt updated".to_string()),
+            None => Err("No active conversation".to_string()),
+        },
+        "close" => {
+            store.set_active(None);
+            Ok("ai <prompt> is back to one-off questions".to_string())
+        }
+        _ => Err(format!("Unknown thread command: {}. Try list, new, resume, fork, delete, system or close", action)),
+    };
+    
+    match result {
+        Ok(output) => CommandResult {
+            success: true,
+            output,
+            error: None,
+            command_type: "thread".to_string(),
+        },
+        Err(e) => CommandResult {
+            success: false,
+            output: String::new(),
+            error: Some(e),
+            command_type: "thread".to_string(),
+        },
+    }
+}
+
 #[tauri::command]
 pub fn get_enhanced_command_suggestions(input: String) -> Result<Vec<CommandSuggestion>, String> {
     let processor = CommandProcessor::new();
@@ -2,7 +2,6 @@ use std::process::Stdio;
 use tokio::process::Command as TokioCommand;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tauri::Manager;
-use serde_json;
 
 #[tauri::command]
 pub async fn set_ui_state(app_handle: tauri::AppHandle, state: String, value: bool) -> Result<(), String> {
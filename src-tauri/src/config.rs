@@ -1,7 +1,5 @@
 use std::fs;
 use std::path::PathBuf;
-use tauri;
-use chrono;
 use rand::seq::SliceRandom;
 
 pub struct FirstRunStore {
@@ -44,32 +42,32 @@ impl FirstRunStore {
 
 #[tauri::command]
 pub fn get_command_suggestions(mode: String, editor_language: String, _context: String, current_file: String) -> Vec<String> {
-    let mut suggestions = Vec::new();
-    
-    // Add file operations commands
-    suggestions.push("new markdown".to_string());
-    suggestions.push("new javascript".to_string());
-    suggestions.push("new python".to_string());
-    suggestions.push("new html".to_string());
-    suggestions.push("new rust".to_string());
-    suggestions.push("new c".to_string());
-    suggestions.push("new cpp".to_string());
-    suggestions.push("new typescript".to_string());
-    suggestions.push("open file".to_string());
-    suggestions.push("save".to_string());
-    suggestions.push("save as".to_string());
-    
-    // Add window management commands
-    suggestions.push("minimize".to_string());
-    suggestions.push("fullscreen".to_string());
-    suggestions.push("help".to_string());
-    
-    // Add terminal commands
-    suggestions.push("git status".to_string());
-    suggestions.push("ls".to_string());
-    suggestions.push("pwd".to_string());
-    suggestions.push("npm run dev".to_string());
-    suggestions.push("npm start".to_string());
+    let mut suggestions = vec![
+        // Add file operations commands
+        "new markdown".to_string(),
+        "new javascript".to_string(),
+        "new python".to_string(),
+        "new html".to_string(),
+        "new rust".to_string(),
+        "new c".to_string(),
+        "new cpp".to_string(),
+        "new typescript".to_string(),
+        "open file".to_string(),
+        "save".to_string(),
+        "save as".to_string(),
+
+        // Add window management commands
+        "minimize".to_string(),
+        "fullscreen".to_string(),
+        "help".to_string(),
+
+        // Add terminal commands
+        "git status".to_string(),
+        "ls".to_string(),
+        "pwd".to_string(),
+        "npm run dev".to_string(),
+        "npm start".to_string(),
+    ];
     
     // Add code editing commands
     if mode == "code" {
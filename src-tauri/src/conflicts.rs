@@ -0,0 +1,374 @@
+// بسم الله الرحمن الرحيم
+// Merge conflicts in buffers: marker regions as ours/base/theirs blocks, per-region resolution and a three-way view
+
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::BufferManager;
+use crate::git::{run_git, status, RepoStatus};
+use crate::git_history::buffer_location;
+
+const OURS_MARKER: &str = "<<<<<<<";
+const BASE_MARKER: &str = "|||||||";
+const SEPARATOR: &str = "=======";
+const THEIRS_MARKER: &str = ">>>>>>>";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictSide {
+    // Whatever follows the marker, e.g. "HEAD" or "feature"
+    pub label: Option<String>,
+    // Line endings included, so a resolution can be put back verbatim
+    pub text: String,
+    // 1-based line of the side's first line of text
+    pub start_line: usize,
+    pub line_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictRegion {
+    pub index: usize,
+    // Byte range from the start of the <<<<<<< line to the end of the >>>>>>> line
+    pub start: usize,
+    pub end: usize,
+    // 1-based lines of the opening and closing markers
+    pub start_line: usize,
+    pub end_line: usize,
+    pub ours: ConflictSide,
+    // Only with merge.conflictStyle set to diff3 or zdiff3
+    pub base: Option<ConflictSide>,
+    pub theirs: ConflictSide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    // Ours followed by theirs
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeView {
+    pub buffer_id: usize,
+    // Relative to the repository root
+    pub path: String,
+    // Index stages 1 to 3; None when that side doesn't have the file, e.g. added on one side only
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+    // The buffer as it stands, which becomes the merge result as regions are resolved
+    pub result: String,
+    pub conflicts: Vec<ConflictRegion>,
+}
+
+enum Section {
+    Ours,
+    Base,
+    Theirs,
+}
+
+// A region being read, with the sides seen so far
+struct OpenRegion {
+    start: usize,
+    start_line: usize,
+    section: Section,
+    ours: ConflictSide,
+    base: Option<ConflictSide>,
+    theirs: ConflictSide,
+}
+
+fn side(label: Option<String>, start_line: usize) -> ConflictSide {
+    ConflictSide {
+        label,
+        text: String::new(),
+        start_line,
+        line_count: 0,
+    }
+}
+
+// The label after a marker at the start of `line`, or Some(None) for a bare marker
+fn marker(line: &str, marker: &str) -> Option<Option<String>> {
+    let rest = line.trim_end_matches(['\r', '\n']).strip_prefix(marker)?;
+    if rest.is_empty() {
+        Some(None)
+    } else if rest.starts_with(' ') {
+        Some(Some(rest.trim().to_string()).filter(|label| !label.is_empty()))
+    } else {
+        // A longer run of the same character, or text glued to it, isn't a marker
+        None
+    }
+}
+
+pub fn parse_conflicts(content: &str) -> Vec<ConflictRegion> {
+    let mut regions = Vec::new();
+    let mut open: Option<OpenRegion> = None;
+    let mut offset = 0;
+
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        let line_number = index + 1;
+
+        // A new opening marker starts over, dropping a region that never closed
+        if let Some(label) = marker(line, OURS_MARKER) {
+            open = Some(OpenRegion {
+                start: offset,
+                start_line: line_number,
+                section: Section::Ours,
+                ours: side(label, line_number + 1),
+                base: None,
+                theirs: side(None, 0),
+            });
+        } else if let Some(region) = open.as_mut() {
+            let base_label = marker(line, BASE_MARKER);
+            let is_separator = marker(line, SEPARATOR) == Some(None);
+            let closing = marker(line, THEIRS_MARKER);
+            match region.section {
+                Section::Ours if base_label.is_some() => {
+                    region.base = Some(side(base_label.flatten(), line_number + 1));
+                    region.section = Section::Base;
+                }
+                Section::Ours | Section::Base if is_separator => {
+                    region.theirs.start_line = line_number + 1;
+                    region.section = Section::Theirs;
+                }
+                Section::Ours => {
+                    region.ours.text.push_str(line);
+                    region.ours.line_count += 1;
+                }
+                Section::Base => {
+                    if let Some(base) = region.base.as_mut() {
+                        base.text.push_str(line);
+                        base.line_count += 1;
+                    }
+                }
+                Section::Theirs if closing.is_none() => {
+                    region.theirs.text.push_str(line);
+                    region.theirs.line_count += 1;
+                }
+                Section::Theirs => {
+                    if let (Some(region), Some(label)) = (open.take(), closing) {
+                        regions.push(ConflictRegion {
+                            index: regions.len(),
+                            start: region.start,
+                            end: offset + line.len(),
+                            start_line: region.start_line,
+                            end_line: line_number,
+                            ours: region.ours,
+                            base: region.base,
+                            theirs: ConflictSide { label, ..region.theirs },
+                        });
+                    }
+                }
+            }
+        }
+        offset += line.len();
+    }
+    regions
+}
+
+impl ConflictRegion {
+    fn resolved_text(&self, resolution: Resolution) -> String {
+        match resolution {
+            Resolution::Ours => self.ours.text.clone(),
+            Resolution::Theirs => self.theirs.text.clone(),
+            Resolution::Both => format!("{}{}", self.ours.text, self.theirs.text),
+        }
+    }
+}
+
+pub fn buffer_conflicts(buffer_id: usize, buffers: &BufferManager) -> Result<Vec<ConflictRegion>, String> {
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    Ok(parse_conflicts(&buffer.content))
+}
+
+// Replace one region with the chosen side(s); returns the regions left
+pub fn resolve(
+    buffer_id: usize,
+    index: usize,
+    resolution: Resolution,
+    buffers: &BufferManager,
+) -> Result<Vec<ConflictRegion>, String> {
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let region = parse_conflicts(&buffer.content)
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| format!("No conflict {} in buffer {}", index, buffer_id))?;
+
+    // An ordinary edit, so it can be undone like any other
+    buffers.apply_edit_if_unchanged(
+        buffer_id,
+        region.start,
+        region.end,
+        &buffer.content[region.start..region.end],
+        &region.resolved_text(resolution),
+    )?;
+    buffer_conflicts(buffer_id, buffers)
+}
+
+pub async fn merge_view(buffer_id: usize, buffers: &BufferManager) -> Result<MergeView, String> {
+    let (root, path) = buffer_location(buffer_id, buffers).await?;
+
+    let base_spec = format!(":1:{}", path);
+    let ours_spec = format!(":2:{}", path);
+    let theirs_spec = format!(":3:{}", path);
+    let base_args = ["show", base_spec.as_str()];
+    let ours_args = ["show", ours_spec.as_str()];
+    let theirs_args = ["show", theirs_spec.as_str()];
+    let (base, ours, theirs) = tokio::join!(
+        run_git(&root, &base_args),
+        run_git(&root, &ours_args),
+        run_git(&root, &theirs_args),
+    );
+    let (base, ours, theirs) = (base.ok(), ours.ok(), theirs.ok());
+    if base.is_none() && ours.is_none() && theirs.is_none() {
+        return Err(format!("{} has no merge conflict", path));
+    }
+
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    Ok(MergeView {
+        buffer_id,
+        path,
+        base,
+        ours,
+        theirs,
+        conflicts: parse_conflicts(&buffer.content),
+        result: buffer.content,
+    })
+}
+
+// Stage the file once every region is resolved, which is what tells git the conflict is over
+pub async fn mark_resolved(buffer_id: usize, buffers: &BufferManager) -> Result<RepoStatus, String> {
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let remaining = parse_conflicts(&buffer.content).len();
+    if remaining > 0 {
+        return Err(format!("{} conflict(s) still unresolved", remaining));
+    }
+    if buffer.modified {
+        return Err("Save the buffer before marking it resolved".to_string());
+    }
+
+    let (root, path) = buffer_location(buffer_id, buffers).await?;
+    run_git(&root, &["add", "--", &path]).await?;
+    status(&root).await
+}
+
+#[tauri::command]
+pub fn get_conflicts(buffer_id: usize, buffer_manager: tauri::State<'_, BufferManager>) -> Result<Vec<ConflictRegion>, String> {
+    buffer_conflicts(buffer_id, &buffer_manager)
+}
+
+#[tauri::command]
+pub fn resolve_conflict(
+    buffer_id: usize,
+    index: usize,
+    resolution: Resolution,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<Vec<ConflictRegion>, String> {
+    resolve(buffer_id, index, resolution, &buffer_manager)
+}
+
+#[tauri::command]
+pub async fn git_merge_view(buffer_id: usize, buffer_manager: tauri::State<'_, BufferManager>) -> Result<MergeView, String> {
+    merge_view(buffer_id, &buffer_manager).await
+}
+
+#[tauri::command]
+pub async fn git_mark_resolved(
+    buffer_id: usize,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<RepoStatus, String> {
+    mark_resolved(buffer_id, &buffer_manager).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_WAY: &str = "keep\n<<<<<<< HEAD\nours 1\nours 2\n=======\ntheirs\n>>>>>>> feature\ntail\n";
+
+    #[test]
+    fn two_way_regions_with_labels_offsets_and_lines() {
+        let regions = parse_conflicts(TWO_WAY);
+        assert_eq!(regions.len(), 1);
+        let region = &regions[0];
+
+        assert_eq!(&TWO_WAY[region.start..region.end], "<<<<<<< HEAD\nours 1\nours 2\n=======\ntheirs\n>>>>>>> feature\n");
+        assert_eq!((region.start_line, region.end_line), (2, 7));
+        assert_eq!(region.ours.label.as_deref(), Some("HEAD"));
+        assert_eq!(region.ours.text, "ours 1\nours 2\n");
+        assert_eq!((region.ours.start_line, region.ours.line_count), (3, 2));
+        assert_eq!(region.theirs.label.as_deref(), Some("feature"));
+        assert_eq!(region.theirs.text, "theirs\n");
+        assert_eq!((region.theirs.start_line, region.theirs.line_count), (6, 1));
+        assert!(region.base.is_none());
+    }
+
+    #[test]
+    fn diff3_regions_carry_the_base() {
+        let content = "<<<<<<< ours\na\n||||||| merged common ancestors\nbase\n=======\nb\n>>>>>>>\n";
+        let regions = parse_conflicts(content);
+        assert_eq!(regions.len(), 1);
+
+        let base = regions[0].base.as_ref().unwrap();
+        assert_eq!(base.label.as_deref(), Some("merged common ancestors"));
+        assert_eq!(base.text, "base\n");
+        assert_eq!((base.start_line, base.line_count), (4, 1));
+        assert_eq!(regions[0].ours.text, "a\n");
+        assert_eq!(regions[0].theirs.text, "b\n");
+        assert_eq!(regions[0].theirs.label, None);
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept_in_the_sides() {
+        let content = "x\r\n<<<<<<< HEAD\r\nmine\r\n=======\r\nyours\r\n>>>>>>> other\r\n";
+        let regions = parse_conflicts(content);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].ours.label.as_deref(), Some("HEAD"));
+        assert_eq!(regions[0].ours.text, "mine\r\n");
+        assert_eq!(regions[0].theirs.label.as_deref(), Some("other"));
+        assert_eq!(regions[0].start, 3);
+        assert_eq!(regions[0].end, content.len());
+    }
+
+    #[test]
+    fn longer_marker_runs_are_text() {
+        let content = "<<<<<<<< not a marker\n========\n>>>>>>>>\n";
+        assert!(parse_conflicts(content).is_empty());
+
+        // Inside a region they're ordinary lines of the side
+        let content = "<<<<<<< HEAD\n========\n=======\n>>>>>>>>\n>>>>>>> b\n";
+        let regions = parse_conflicts(content);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].ours.text, "========\n");
+        assert_eq!(regions[0].theirs.text, ">>>>>>>>\n");
+    }
+
+    #[test]
+    fn an_unclosed_region_gives_way_to_the_next() {
+        let content = "<<<<<<< HEAD\nlost\n=======\n<<<<<<< HEAD\nkept\n=======\nother\n>>>>>>> b\n";
+        let regions = parse_conflicts(content);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].start_line, 4);
+        assert_eq!(regions[0].start, "<<<<<<< HEAD\nlost\n=======\n".len());
+        assert_eq!(regions[0].ours.text, "kept\n");
+        assert_eq!(regions[0].index, 0);
+    }
+
+    #[test]
+    fn resolving_replaces_one_region_at_a_time() {
+        let content = format!("{}{}", TWO_WAY, "<<<<<<< HEAD\nx\n=======\ny\n>>>>>>> b\n<<<<<<< HEAD\np\n=======\nq\n>>>>>>> b\n");
+        let buffers = BufferManager::new();
+        let id = buffers.create_buffer(content, None);
+
+        let remaining = resolve(id, 1, Resolution::Theirs, &buffers).unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(remaining[1].ours.text, "p\n");
+
+        let remaining = resolve(id, 0, Resolution::Ours, &buffers).unwrap();
+        assert_eq!(remaining.len(), 1);
+        let remaining = resolve(id, 0, Resolution::Both, &buffers).unwrap();
+        assert!(remaining.is_empty());
+
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "keep\nours 1\nours 2\ntail\ny\np\nq\n");
+        assert!(resolve(id, 0, Resolution::Ours, &buffers).is_err());
+    }
+}
@@ -0,0 +1,503 @@
+// بسم الله الرحمن الرحيم
+// Multi-turn AI conversations: named threads persisted as one JSON file each
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{self, AppHandle};
+
+use crate::ai::{AiProvider, AiProviderStore, AiStreamManager, ChatMessage, ChatRequest, ChatResponse};
+
+// Context budget when a thread doesn't set its own
+const DEFAULT_CONTEXT_TOKENS: usize = 8000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationMessage {
+    pub role: String, // "user", "assistant"
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub system_prompt: Option<String>,
+    // Provider profile name; None uses the default
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub max_context_tokens: Option<usize>,
+    pub messages: Vec<ConversationMessage>,
+    #[serde(default)]
+    pub forked_from: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub message_count: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Conversation {
+    pub fn new(title: String, system_prompt: Option<String>, provider: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            title,
+            system_prompt,
+            provider,
+            max_context_tokens: None,
+            messages: Vec::new(),
+            forked_from: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn summary(&self) -> ConversationSummary {
+        ConversationSummary {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            message_count: self.messages.len(),
+            updated_at: self.updated_at,
+        }
+    }
+
+    // The system prompt plus as many of the newest messages as fit the token budget
+    pub fn request(&self) -> ChatRequest {
+        let budget = self.max_context_tokens.unwrap_or(DEFAULT_CONTEXT_TOKENS);
+        let mut used = self
+            .system_prompt
+            .as_deref()
+            .map(estimate_tokens)
+            .unwrap_or(0);
+
+        let mut kept = Vec::new();
+        for message in self.messages.iter().rev() {
+            let cost = estimate_tokens(&message.content);
+            // Always keep the latest message, even if it alone is over budget
+            if !kept.is_empty() && used + cost > budget {
+                break;
+            }
+            used += cost;
+            kept.push(ChatMessage {
+                role: message.role.clone(),
+                content: message.content.clone(),
+            });
+        }
+
+        // Most providers reject a history that opens with an assistant turn
+        while kept.len() > 1 && kept.last().map(|m| m.role.as_str()) == Some("assistant") {
+            kept.pop();
+        }
+
+        let mut messages = Vec::new();
+        if let Some(system_prompt) = &self.system_prompt {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: system_prompt.clone(),
+            });
+        }
+        messages.extend(kept.into_iter().rev());
+
+        ChatRequest {
+            messages,
+            ..Default::default()
+        }
+    }
+}
+
+// Rough count without a tokenizer: about four characters per token for English and code
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub struct ConversationStore {
+    threads: RwLock<HashMap<String, Conversation>>,
+    // Thread the command bar's `ai` command continues, if any
+    active: RwLock<Option<String>>,
+    dir: PathBuf,
+}
+
+impl ConversationStore {
+    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        let dir = app_dir.join("conversations");
+        fs::create_dir_all(&dir).expect("Failed to create conversations directory");
+
+        let mut threads = HashMap::new();
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                match fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| serde_json::from_str::<Conversation>(&content).map_err(|e| e.to_string()))
+                {
+                    Ok(conversation) => {
+                        threads.insert(conversation.id.clone(), conversation);
+                    }
+                    Err(e) => log::warn!("Skipping unreadable conversation {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        Self {
+            threads: RwLock::new(threads),
+            active: RwLock::new(None),
+            dir,
+        }
+    }
+
+    fn save(&self, conversation: &Conversation) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(conversation)
+            .map_err(|e| format!("Failed to serialize conversation: {}", e))?;
+        fs::write(self.dir.join(format!("{}.json", conversation.id)), content)
+            .map_err(|e| format!("Failed to save conversation: {}", e))
+    }
+
+    pub fn list(&self) -> Vec<ConversationSummary> {
+        let mut summaries: Vec<_> = self.threads.read().values().map(|c| c.summary()).collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        summaries
+    }
+
+    pub fn get(&self, id: &str) -> Result<Conversation, String> {
+        self.threads
+            .read()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("Conversation {} not found", id))
+    }
+
+    // Accepts an id, an id prefix or an exact title
+    pub fn find(&self, query: &str) -> Result<Conversation, String> {
+        // An empty prefix would match every id
+        let query = query.trim();
+        if query.is_empty() {
+            return Err("Name a conversation by id or title".to_string());
+        }
+
+        let threads = self.threads.read();
+        let mut matches: Vec<&Conversation> = threads
+            .values()
+            .filter(|c| c.id == query || c.title == query)
+            .collect();
+        if matches.is_empty() {
+            matches = threads.values().filter(|c| c.id.starts_with(query)).collect();
+        }
+
+        match matches.as_slice() {
+            [conversation] => Ok((*conversation).clone()),
+            [] => Err(format!("No conversation matches '{}'", query)),
+            _ => Err(format!("'{}' matches more than one conversation", query)),
+        }
+    }
+
+    pub fn create(&self, title: String, system_prompt: Option<String>, provider: Option<String>) -> Result<Conversation, String> {
+        let conversation = Conversation::new(title, system_prompt, provider);
+        self.save(&conversation)?;
+        self.threads.write().insert(conversation.id.clone(), conversation.clone());
+        Ok(conversation)
+    }
+
+    pub fn update<F>(&self, id: &str, change: F) -> Result<Conversation, String>
+    where
+        F: FnOnce(&mut Conversation),
+    {
+        let mut threads = self.threads.write();
+        let conversation = threads
+            .get_mut(id)
+            .ok_or_else(|| format!("Conversation {} not found", id))?;
+        change(conversation);
+        conversation.updated_at = Utc::now();
+        self.save(conversation)?;
+        Ok(conversation.clone())
+    }
+
+    // Copy a thread, optionally keeping only the first `keep` messages
+    pub fn fork(&self, id: &str, keep: Option<usize>, title: Option<String>) -> Result<Conversation, String> {
+        let source = self.get(id)?;
+        let mut fork = Conversation::new(
+            title.unwrap_or_else(|| format!("{} (fork)", source.title)),
+            source.system_prompt.clone(),
+            source.provider.clone(),
+        );
+        fork.max_context_tokens = source.max_context_tokens;
+        fork.forked_from = Some(source.id.clone());
+        fork.messages = match keep {
+            Some(keep) => source.messages.into_iter().take(keep).collect(),
+            None => source.messages,
+        };
+
+        self.save(&fork)?;
+        self.threads.write().insert(fork.id.clone(), fork.clone());
+        Ok(fork)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), String> {
+        if self.threads.write().remove(id).is_none() {
+            return Err(format!("Conversation {} not found", id));
+        }
+
+        let mut active = self.active.write();
+        if active.as_deref() == Some(id) {
+            *active = None;
+        }
+
+        let path = self.dir.join(format!("{}.json", id));
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| format!("Failed to delete conversation: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn active(&self) -> Option<String> {
+        self.active.read().clone()
+    }
+
+    pub fn set_active(&self, id: Option<String>) {
+        *self.active.write() = id;
+    }
+
+    // Send a user message and record the reply; nothing is stored if the request fails.
+    // With a request id the reply is streamed as "ai_stream" events.
+    pub async fn send(
+        &self,
+        id: &str,
+        content: String,
+        provider: &dyn AiProvider,
+        stream: Option<(&AiStreamManager, &AppHandle, &str)>,
+    ) -> Result<ConversationMessage, String> {
+        let user_message = ConversationMessage {
+            role: "user".to_string(),
+            content,
+            created_at: Utc::now(),
+        };
+
+        let mut pending = self.get(id)?;
+        pending.messages.push(user_message.clone());
+        let request = pending.request();
+
+        let response: ChatResponse = match stream {
+            Some((streams, app_handle, request_id)) => streams.run(app_handle, request_id, provider, &request).await?,
+            None => provider.chat(&request).await?,
+        };
+
+        let reply = ConversationMessage {
+            role: "assistant".to_string(),
+            content: response.text,
+            created_at: Utc::now(),
+        };
+
+        self.update(id, |conversation| {
+            conversation.messages.push(user_message);
+            conversation.messages.push(reply.clone());
+        })?;
+
+        Ok(reply)
+    }
+}
+
+#[tauri::command]
+pub fn conversation_list(store: tauri::State<'_, ConversationStore>) -> Vec<ConversationSummary> {
+    store.list()
+}
+
+#[tauri::command]
+pub fn conversation_get(store: tauri::State<'_, ConversationStore>, id: String) -> Result<Conversation, String> {
+    store.get(&id)
+}
+
+#[tauri::command]
+pub fn conversation_create(
+    store: tauri::State<'_, ConversationStore>,
+    title: String,
+    system_prompt: Option<String>,
+    provider: Option<String>,
+) -> Result<Conversation, String> {
+    store.create(title, system_prompt, provider)
+}
+
+#[tauri::command]
+pub fn conversation_update_settings(
+    store: tauri::State<'_, ConversationStore>,
+    id: String,
+    title: Option<String>,
+    system_prompt: Option<String>,
+    provider: Option<String>,
+    max_context_tokens: Option<usize>,
+) -> Result<Conversation, String> {
+    store.update(&id, |conversation| {
+        if let Some(title) = title {
+            conversation.title = title;
+        }
+        if let Some(system_prompt) = system_prompt {
+            // An empty prompt clears it
+            conversation.system_prompt = Some(system_prompt).filter(|p| !p.is_empty());
+        }
+        if let Some(provider) = provider {
+            conversation.provider = Some(provider).filter(|p| !p.is_empty());
+        }
+        if let Some(max_context_tokens) = max_context_tokens {
+            conversation.max_context_tokens = Some(max_context_tokens);
+        }
+    })
+}
+
+#[tauri::command]
+pub fn conversation_fork(
+    store: tauri::State<'_, ConversationStore>,
+    id: String,
+    keep_messages: Option<usize>,
+    title: Option<String>,
+) -> Result<Conversation, String> {
+    store.fork(&id, keep_messages, title)
+}
+
+#[tauri::command]
+pub fn conversation_delete(store: tauri::State<'_, ConversationStore>, id: String) -> Result<(), String> {
+    store.delete(&id)
+}
+
+#[tauri::command]
+pub fn conversation_resume(store: tauri::State<'_, ConversationStore>, id: Option<String>) -> Result<(), String> {
+    if let Some(id) = &id {
+        store.get(id)?;
+    }
+    store.set_active(id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn conversation_send(
+    id: String,
+    content: String,
+    request_id: Option<String>,
+    app_handle: AppHandle,
+    store: tauri::State<'_, ConversationStore>,
+    providers: tauri::State<'_, AiProviderStore>,
+    streams: tauri::State<'_, AiStreamManager>,
+) -> Result<ConversationMessage, String> {
+    if content.trim().is_empty() {
+        return Err("Message is empty".to_string());
+    }
+
+    let conversation = store.get(&id)?;
+    let provider = providers.provider(conversation.provider.as_deref())?;
+    let stream = request_id
+        .as_deref()
+        .map(|request_id| (&*streams, &app_handle, request_id));
+
+    store.send(&id, content, provider.as_ref(), stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_with(conversations: &[Conversation]) -> ConversationStore {
+        ConversationStore {
+            threads: RwLock::new(conversations.iter().map(|c| (c.id.clone(), c.clone())).collect()),
+            active: RwLock::new(None),
+            dir: std::env::temp_dir(),
+        }
+    }
+
+    #[test]
+    fn find_matches_by_id_prefix_or_title() {
+        let mut first = Conversation::new("Parser bug".to_string(), None, None);
+        first.id = "abc12345-first".to_string();
+        let mut second = Conversation::new("Release notes".to_string(), None, None);
+        second.id = "abd67890-second".to_string();
+        let store = store_with(&[first, second]);
+
+        assert_eq!(store.find("abc").unwrap().title, "Parser bug");
+        assert_eq!(store.find(" Release notes ").unwrap().id, "abd67890-second");
+        assert!(store.find("ab").unwrap_err().contains("more than one"));
+        assert!(store.find("zzz").unwrap_err().contains("No conversation"));
+    }
+
+    #[test]
+    fn find_rejects_an_empty_query() {
+        let store = store_with(&[Conversation::new("Only thread".to_string(), None, None)]);
+        assert!(store.find("").is_err());
+        assert!(store.find("   ").is_err());
+    }
+
+    // Five alternating turns of ten tokens each, under a two-token system prompt
+    fn thread(budget: usize) -> Conversation {
+        let mut conversation = Conversation::new("Budget".to_string(), Some("Be brief".to_string()), None);
+        conversation.max_context_tokens = Some(budget);
+        for (index, role) in ["user", "assistant", "user", "assistant", "user"].iter().enumerate() {
+            conversation.messages.push(ConversationMessage {
+                role: role.to_string(),
+                content: format!("{} turn {:<33}", role.chars().next().unwrap(), index + 1),
+                created_at: Utc::now(),
+            });
+        }
+        conversation
+    }
+
+    fn turns(request: &ChatRequest) -> Vec<String> {
+        request
+            .messages
+            .iter()
+            .map(|message| format!("{}: {}", message.role, message.content.trim_end()))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_turns_that_fit_the_budget() {
+        let conversation = thread(32);
+        assert!(conversation.messages.iter().all(|m| estimate_tokens(&m.content) == 10));
+        assert_eq!(estimate_tokens("Be brief"), 2);
+
+        // Exactly at the budget: the system prompt and three turns
+        assert_eq!(
+            turns(&conversation.request()),
+            vec!["system: Be brief", "user: u turn 3", "assistant: a turn 4", "user: u turn 5"]
+        );
+    }
+
+    #[test]
+    fn never_opens_with_an_assistant_turn() {
+        // One token short: the oldest turn that fits is an assistant's, so it goes too
+        assert_eq!(turns(&thread(31).request()), vec!["system: Be brief", "user: u turn 5"]);
+    }
+
+    #[test]
+    fn keeps_the_latest_turn_even_over_budget() {
+        assert_eq!(turns(&thread(1).request()), vec!["system: Be brief", "user: u turn 5"]);
+    }
+
+    #[test]
+    fn sends_everything_that_fits() {
+        let mut conversation = thread(52);
+        assert_eq!(conversation.request().messages.len(), 6);
+
+        conversation.system_prompt = None;
+        conversation.max_context_tokens = None;
+        assert_eq!(turns(&conversation.request())[0], "user: u turn 1");
+    }
+}
@@ -2,13 +2,22 @@
 // GitHub Copilot Language Server Integration
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
 use tokio::process::{Child, Command};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use crate::buffer::{Buffer, BufferManager};
+use crate::framing::{read_frame, write_frame};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CopilotConfig {
@@ -16,6 +25,14 @@ pub struct CopilotConfig {
     pub telemetry: bool,
     pub auto_trigger: bool,
     pub debounce_ms: u64,
+    // Documents matching these are never sent to the server
+    #[serde(default)]
+    pub disabled_languages: Vec<String>,
+    #[serde(default = "default_disabled_paths")]
+    pub disabled_paths: Vec<String>,
+    // Explicit language server executable, skipping discovery
+    #[serde(default)]
+    pub server_path: Option<String>,
 }
 
 impl Default for CopilotConfig {
@@ -25,10 +42,111 @@ impl Default for CopilotConfig {
             telemetry: true,
             auto_trigger: true,
             debounce_ms: 150,
+            disabled_languages: Vec::new(),
+            disabled_paths: default_disabled_paths(),
+            server_path: None,
         }
     }
 }
 
+fn default_disabled_paths() -> Vec<String> {
+    vec![
+        ".env".to_string(),
+        ".env.*".to_string(),
+        "*.pem".to_string(),
+        "*.key".to_string(),
+        "id_rsa*".to_string(),
+    ]
+}
+
+impl CopilotConfig {
+    pub fn allows_document(&self, uri: &str, language_id: &str) -> bool {
+        if self
+            .disabled_languages
+            .iter()
+            .any(|language| language.eq_ignore_ascii_case(language_id))
+        {
+            return false;
+        }
+
+        let path = uri_path(uri).unwrap_or_else(|| uri.to_string());
+        !self
+            .disabled_paths
+            .iter()
+            .any(|pattern| glob_matches(pattern, &path))
+    }
+
+    // Settings served to the language server through workspace/configuration
+    fn server_settings(&self) -> serde_json::Value {
+        serde_json::json!({
+            "telemetry": {
+                "telemetryLevel": if self.telemetry { "all" } else { "off" }
+            }
+        })
+    }
+}
+
+// The file path a file:// URI names, with percent-escapes decoded so that patterns see the real
+// file name; None for other schemes
+fn uri_path(uri: &str) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+    while index < encoded.len() {
+        let escaped = (encoded[index] == b'%')
+            .then(|| encoded.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(encoded[index]);
+                index += 1;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+// Minimal glob support: `*`, `?` and `**`. Patterns without a slash match the
+// file name alone, the same way .gitignore treats them.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let path = path.replace('\\', "/");
+    let target = if pattern.contains('/') {
+        path.as_str()
+    } else {
+        path.rsplit('/').next().unwrap_or(path.as_str())
+    };
+
+    let mut expression = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    expression.push_str("(?:.*/)?");
+                } else {
+                    expression.push_str(".*");
+                }
+            }
+            '*' => expression.push_str("[^/]*"),
+            '?' => expression.push_str("[^/]"),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+
+    regex::Regex::new(&expression)
+        .map(|re| re.is_match(target))
+        .unwrap_or(false)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Position {
@@ -46,6 +164,9 @@ pub struct Range {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InlineCompletionItem {
+    // Taken from the item's command arguments; used to report shown/accepted/rejected
+    #[serde(default)]
+    pub id: Option<String>,
     pub insert_text: String,
     pub range: Option<Range>,
     pub command: Option<serde_json::Value>,
@@ -57,11 +178,22 @@ pub struct InlineCompletionList {
     pub items: Vec<InlineCompletionItem>,
 }
 
+// Payload of "copilot_panel_completions"; one event per batch, then a final one with `done`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PanelCompletionBatch {
+    pub token: String,
+    pub items: Vec<InlineCompletionItem>,
+    pub done: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CopilotStatus {
     pub status: String,        // "Normal", "Error", "Warning", "Inactive"
     pub message: String,
     pub signed_in: bool,
+    pub user: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +201,47 @@ pub struct CopilotStatus {
 pub struct SignInResponse {
     pub user_code: String,
     pub verification_uri: String,
+    pub status: String,        // "PromptUserDeviceFlow" or "AlreadySignedIn"
+    pub user: Option<String>,
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionSelection {
+    pub item: InlineCompletionItem,
+    pub index: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialAcceptance {
+    pub text: String,
+    pub remaining: String,
+    pub complete: bool,
+}
+
+// Text last sent to the server for an open document
+struct OpenDocument {
+    version: i32,
+    language_id: String,
+    text: String,
+}
+
+// Suggestions for the current cursor location, used for cycling and partial acceptance
+struct CompletionSession {
+    // The request that produced it, so a reply can tell whether the session was replaced meanwhile
+    generation: u64,
+    uri: String,
+    language_id: String,
+    version: i32,
+    position: Position,
+    items: Vec<InlineCompletionItem>,
+    index: usize,
+    // Byte offset into the current item's unaccepted text
+    accepted: usize,
+    fetched_alternatives: bool,
 }
 
 // JSON-RPC structures
@@ -90,33 +263,59 @@ struct JsonRpcNotification {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct JsonRpcResponse {
     jsonrpc: String,
-    id: u64,
+    // Server-to-client requests may use string or numeric ids
+    id: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     result: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<serde_json::Value>,
 }
 
-pub struct CopilotServer {
-    process: Arc<Mutex<Option<Child>>>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopilotNotification {
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+const REQUEST_CANCELLED: &str = "Copilot request cancelled";
+// How long to wait for the user to finish the device flow if the server doesn't say
+const DEVICE_FLOW_TIMEOUT: Duration = Duration::from_secs(900);
+
+// Panel completions are generated in bulk and take much longer than ghost text
+const PANEL_TIMEOUT: Duration = Duration::from_secs(60);
+const PANEL_TOKEN_PREFIX: &str = "copilot-panel-";
+
+// Crash recovery: exponential backoff, reset once the server has stayed up for a while
+const MAX_RESTART_ATTEMPTS: u32 = 5;
+const RESTART_BASE_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(30);
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+const SERVER_LOG_LINES: usize = 500;
+
+// InlineCompletionTriggerKind from the LSP spec
+const TRIGGER_INVOKED: u32 = 1;
+const TRIGGER_AUTOMATIC: u32 = 2;
+
+// The server's stdin, or the other end of a pipe in tests
+type ServerWriter = Box<dyn AsyncWrite + Send + Unpin>;
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<serde_json::Value, String>>>>>;
+
+// Write half of the JSON-RPC connection plus the table of requests awaiting replies
+#[derive(Clone)]
+struct RpcChannel {
+    stdin: Arc<Mutex<Option<ServerWriter>>>,
+    pending: PendingRequests,
     request_id: Arc<Mutex<u64>>,
-    status: Arc<RwLock<CopilotStatus>>,
-    config: Arc<RwLock<CopilotConfig>>,
-    initialized: Arc<Mutex<bool>>,
 }
 
-impl CopilotServer {
-    pub fn new() -> Self {
+impl RpcChannel {
+    fn new() -> Self {
         Self {
-            process: Arc::new(Mutex::new(None)),
+            stdin: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
             request_id: Arc::new(Mutex::new(0)),
-            status: Arc::new(RwLock::new(CopilotStatus {
-                status: "Inactive".to_string(),
-                message: "Not started".to_string(),
-                signed_in: false,
-            })),
-            config: Arc::new(RwLock::new(CopilotConfig::default())),
-            initialized: Arc::new(Mutex::new(false)),
         }
     }
 
@@ -126,279 +325,1686 @@ impl CopilotServer {
         *id
     }
 
-    pub async fn start(&self, workspace_path: Option<String>) -> Result<(), String> {
-        let mut process_guard = self.process.lock().await;
-        
-        if process_guard.is_some() {
-            return Err("Copilot server already running".to_string());
-        }
-
-        // Find the Copilot language server binary
-        let copilot_path = self.find_copilot_binary()?;
-
-        // Start the language server process
-        let mut child = Command::new(copilot_path)
-            .arg("--stdio")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to start Copilot server: {}", e))?;
-
-        // Initialize the server
-        let stdin = child.stdin.take().ok_or("Failed to get stdin")?;
-        let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
-
-        // Store the process
-        *process_guard = Some(child);
-        drop(process_guard);
+    async fn send<T: Serialize>(&self, message: &T) -> Result<(), String> {
+        let mut stdin_guard = self.stdin.lock().await;
+        let stdin = stdin_guard
+            .as_mut()
+            .ok_or("Copilot server not running")?;
+        write_frame(stdin, message).await
+    }
 
-        // Send initialize request
-        self.initialize(stdin, stdout, workspace_path).await?;
+    async fn request(&self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, String> {
+        self.request_with_timeout(method, params, REQUEST_TIMEOUT).await
+    }
 
-        // Update status
-        let mut status = self.status.write().await;
-        status.status = "Normal".to_string();
-        status.message = "Copilot server started".to_string();
+    async fn request_with_timeout(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        limit: Duration,
+    ) -> Result<serde_json::Value, String> {
+        self.request_inner(method, params, limit, None).await
+    }
 
-        Ok(())
+    // Like `request`, but gives up with REQUEST_CANCELLED as soon as `cancel` fires or is dropped
+    async fn request_cancellable(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        cancel: oneshot::Receiver<()>,
+    ) -> Result<serde_json::Value, String> {
+        self.request_inner(method, params, REQUEST_TIMEOUT, Some(cancel)).await
     }
 
-    async fn initialize(
+    async fn request_inner(
         &self,
-        mut stdin: tokio::process::ChildStdin,
-        stdout: tokio::process::ChildStdout,
-        workspace_path: Option<String>,
-    ) -> Result<(), String> {
+        method: &str,
+        params: serde_json::Value,
+        limit: Duration,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<serde_json::Value, String> {
         let id = self.next_request_id().await;
-
-        let workspace_folders = if let Some(path) = workspace_path {
-            vec![serde_json::json!({ "uri": format!("file://{}", path) })]
-        } else {
-            vec![]
-        };
-
-        let init_params = serde_json::json!({
-            "processId": std::process::id(),
-            "workspaceFolders": workspace_folders,
-            "capabilities": {
-                "workspace": { "workspaceFolders": true }
-            },
-            "initializationOptions": {
-                "editorInfo": {
-                    "name": "Vuno",
-                    "version": "0.1.0"
-                },
-                "editorPluginInfo": {
-                    "name": "Vuno Copilot Integration",
-                    "version": "1.0.0"
-                }
-            }
-        });
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(id, tx);
 
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
             id,
-            method: "initialize".to_string(),
-            params: init_params,
+            method: method.to_string(),
+            params,
+        };
+
+        if let Err(e) = self.send(&request).await {
+            self.pending.lock().await.remove(&id);
+            return Err(e);
+        }
+
+        let outcome = match cancel {
+            Some(cancel) => tokio::select! {
+                outcome = tokio::time::timeout(limit, rx) => Some(outcome),
+                _ = cancel => None,
+            },
+            None => Some(tokio::time::timeout(limit, rx).await),
         };
 
-        // Send the request
-        self.send_message(&mut stdin, &request).await?;
+        match outcome {
+            Some(Ok(Ok(outcome))) => outcome,
+            Some(Ok(Err(_))) => Err("Copilot server closed the connection".to_string()),
+            Some(Err(_)) => {
+                self.cancel(id).await;
+                Err(format!("Copilot request '{}' timed out", method))
+            }
+            None => {
+                self.cancel(id).await;
+                Err(REQUEST_CANCELLED.to_string())
+            }
+        }
+    }
 
-        // TODO: Read and parse response from stdout
-        // For now, we'll assume it succeeded and send initialized notification
+    async fn cancel(&self, id: u64) {
+        self.pending.lock().await.remove(&id);
+        let _ = self
+            .notify("$/cancelRequest", serde_json::json!({ "id": id }))
+            .await;
+    }
 
+    async fn notify(&self, method: &str, params: serde_json::Value) -> Result<(), String> {
         let notification = JsonRpcNotification {
             jsonrpc: "2.0".to_string(),
-            method: "initialized".to_string(),
-            params: serde_json::json!({}),
+            method: method.to_string(),
+            params,
         };
+        self.send(&notification).await
+    }
 
-        self.send_notification(&mut stdin, &notification).await?;
+    async fn respond(
+        &self,
+        id: serde_json::Value,
+        outcome: Result<serde_json::Value, serde_json::Value>,
+    ) -> Result<(), String> {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
 
-        let mut initialized = self.initialized.lock().await;
-        *initialized = true;
+        let response = JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        };
+        self.send(&response).await
+    }
 
-        Ok(())
+    async fn resolve(&self, id: u64, outcome: Result<serde_json::Value, String>) {
+        if let Some(tx) = self.pending.lock().await.remove(&id) {
+            let _ = tx.send(outcome);
+        } else {
+            log::debug!("Copilot response for unknown request {}", id);
+        }
     }
 
-    async fn send_message<T: Serialize>(
-        &self,
-        stdin: &mut tokio::process::ChildStdin,
-        message: &T,
-    ) -> Result<(), String> {
-        let content = serde_json::to_string(message)
-            .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    async fn fail_all(&self, reason: &str) {
+        for (_, tx) in self.pending.lock().await.drain() {
+            let _ = tx.send(Err(reason.to_string()));
+        }
+    }
+}
 
-        let header = format!("Content-Length: {}\r\n\r\n", content.len());
-        let full_message = format!("{}{}", header, content);
+// State the background reader needs to route server messages
+#[derive(Clone)]
+struct MessageRouter {
+    rpc: RpcChannel,
+    config: Arc<RwLock<CopilotConfig>>,
+    status: Arc<RwLock<CopilotStatus>>,
+    initialized: Arc<Mutex<bool>>,
+    app_handle: Arc<RwLock<Option<AppHandle>>>,
+    panel_items: Arc<Mutex<Vec<InlineCompletionItem>>>,
+    panel_sequence: Arc<AtomicU64>,
+}
 
-        stdin
-            .write_all(full_message.as_bytes())
-            .await
-            .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+impl MessageRouter {
+    async fn run<R: AsyncRead + Unpin>(self, stdout: R) {
+        let mut reader = BufReader::new(stdout);
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(Some(message)) => self.dispatch(message).await,
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Copilot server stream error: {}", e);
+                    break;
+                }
+            }
+        }
 
-        stdin
-            .flush()
-            .await
-            .map_err(|e| format!("Failed to flush stdin: {}", e))?;
+        *self.initialized.lock().await = false;
+        self.rpc.fail_all("Copilot server closed the connection").await;
+    }
 
-        Ok(())
+    async fn dispatch(&self, message: serde_json::Value) {
+        let id = message.get("id").cloned();
+        let method = message
+            .get("method")
+            .and_then(|m| m.as_str())
+            .map(String::from);
+        let params = message
+            .get("params")
+            .cloned()
+            .unwrap_or(serde_json::Value::Null);
+
+        match (id, method) {
+            (Some(id), None) => {
+                // Response to one of our requests
+                let id = match id.as_u64() {
+                    Some(id) => id,
+                    None => {
+                        log::warn!("Copilot response with unexpected id: {}", id);
+                        return;
+                    }
+                };
+
+                let outcome = match message.get("error").filter(|e| !e.is_null()) {
+                    Some(error) => Err(format_rpc_error(error)),
+                    None => Ok(message
+                        .get("result")
+                        .cloned()
+                        .unwrap_or(serde_json::Value::Null)),
+                };
+
+                self.rpc.resolve(id, outcome).await;
+            }
+            (Some(id), Some(method)) => {
+                let outcome = self.handle_request(&method, &params).await;
+                if let Err(e) = self.rpc.respond(id, outcome).await {
+                    log::warn!("Failed to answer Copilot request '{}': {}", method, e);
+                }
+            }
+            (None, Some(method)) => self.handle_notification(&method, params).await,
+            (None, None) => log::warn!("Ignoring malformed Copilot message: {}", message),
+        }
     }
 
-    async fn send_notification(
+    async fn handle_request(
         &self,
-        stdin: &mut tokio::process::ChildStdin,
-        notification: &JsonRpcNotification,
-    ) -> Result<(), String> {
-        self.send_message(stdin, notification).await
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, serde_json::Value> {
+        match method {
+            "workspace/configuration" => {
+                let settings = self.config.read().await.server_settings();
+                let items = params
+                    .get("items")
+                    .and_then(|items| items.as_array())
+                    .cloned()
+                    .unwrap_or_default();
+
+                let values = items
+                    .iter()
+                    .map(|item| match item.get("section").and_then(|s| s.as_str()) {
+                        Some(section) => section
+                            .split('.')
+                            .try_fold(&settings, |value, key| value.get(key))
+                            .cloned()
+                            .unwrap_or(serde_json::Value::Null),
+                        None => settings.clone(),
+                    })
+                    .collect();
+                Ok(serde_json::Value::Array(values))
+            }
+            "window/workDoneProgress/create" => Ok(serde_json::Value::Null),
+            "window/showMessageRequest" => {
+                // Surface the message but don't pick an action on the user's behalf
+                self.emit(method, params.clone()).await;
+                Ok(serde_json::Value::Null)
+            }
+            "window/showDocument" => {
+                self.emit(method, params.clone()).await;
+                Ok(serde_json::json!({ "success": false }))
+            }
+            _ => Err(serde_json::json!({
+                "code": -32601,
+                "message": format!("Method not found: {}", method),
+            })),
+        }
     }
 
-    pub async fn stop(&self) -> Result<(), String> {
-        let mut process_guard = self.process.lock().await;
-        
-        if let Some(mut child) = process_guard.take() {
-            child
-                .kill()
-                .await
-                .map_err(|e| format!("Failed to kill Copilot server: {}", e))?;
+    async fn handle_notification(&self, method: &str, params: serde_json::Value) {
+        match method {
+            "didChangeStatus" | "statusNotification" => {
+                let kind = params
+                    .get("kind")
+                    .or_else(|| params.get("status"))
+                    .and_then(|k| k.as_str());
+                let message = params.get("message").and_then(|m| m.as_str());
+
+                let mut status = self.status.write().await;
+                if let Some(kind) = kind {
+                    status.status = kind.to_string();
+                }
+                if let Some(message) = message {
+                    status.message = message.to_string();
+                }
+            }
+            "window/logMessage" => {
+                let message = params.get("message").and_then(|m| m.as_str()).unwrap_or_default();
+                log::info!("Copilot: {}", message);
+            }
+            "$/progress" => {
+                let token = params.get("token").and_then(|t| t.as_str()).unwrap_or_default();
+                if let Some(sequence) = token.strip_prefix(PANEL_TOKEN_PREFIX) {
+                    // Late results for a superseded panel request are dropped
+                    if sequence.parse::<u64>().ok() != Some(self.panel_sequence.load(Ordering::SeqCst)) {
+                        return;
+                    }
+                    let value = params.get("value").cloned().unwrap_or(serde_json::Value::Null);
+                    match parse_completion_list(value) {
+                        Ok(list) if !list.items.is_empty() => {
+                            self.panel_items.lock().await.extend(list.items.iter().cloned());
+                            self.emit_panel(token, list.items, false).await;
+                        }
+                        Ok(_) => {}
+                        Err(e) => log::warn!("Ignoring malformed panel completions: {}", e),
+                    }
+                    return;
+                }
+            }
+            _ => {}
         }
 
-        let mut status = self.status.write().await;
-        status.status = "Inactive".to_string();
-        status.message = "Copilot server stopped".to_string();
-        status.signed_in = false;
+        self.emit(method, params).await;
+    }
 
-        let mut initialized = self.initialized.lock().await;
-        *initialized = false;
+    async fn set_auth(&self, signed_in: bool, user: Option<String>, message: Option<String>) {
+        let snapshot = {
+            let mut status = self.status.write().await;
+            status.signed_in = signed_in;
+            status.user = user;
+            if let Some(message) = message {
+                status.message = message;
+            }
+            status.clone()
+        };
 
-        Ok(())
+        if let Some(app_handle) = self.app_handle.read().await.as_ref() {
+            let _ = app_handle.emit_all("copilot_status", snapshot);
+        }
     }
 
-    pub async fn get_status(&self) -> CopilotStatus {
-        self.status.read().await.clone()
+    async fn emit_panel(&self, token: &str, items: Vec<InlineCompletionItem>, done: bool) {
+        if let Some(app_handle) = self.app_handle.read().await.as_ref() {
+            let _ = app_handle.emit_all(
+                "copilot_panel_completions",
+                PanelCompletionBatch {
+                    token: token.to_string(),
+                    items,
+                    done,
+                },
+            );
+        }
     }
 
-    fn find_copilot_binary(&self) -> Result<PathBuf, String> {
-        // Try to find the Copilot language server binary
-        // Priority:
-        // 1. Check node_modules/@github/copilot-language-server-*
-        // 2. Check global npm installation
-        // 3. Check PATH
-
-        #[cfg(target_os = "macos")]
-        {
-            #[cfg(target_arch = "aarch64")]
-            let platform = "darwin-arm64";
-            #[cfg(target_arch = "x86_64")]
-            let platform = "darwin-x64";
+    async fn emit(&self, method: &str, params: serde_json::Value) {
+        if let Some(app_handle) = self.app_handle.read().await.as_ref() {
+            let _ = app_handle.emit_all(
+                "copilot_notification",
+                CopilotNotification {
+                    method: method.to_string(),
+                    params,
+                },
+            );
+        }
+    }
+}
 
-            // Check node_modules
-            let node_modules_path = PathBuf::from("node_modules")
-                .join(format!("@github/copilot-language-server-{}", platform))
-                .join("copilot-language-server");
+fn format_rpc_error(error: &serde_json::Value) -> String {
+    let code = error.get("code").and_then(|c| c.as_i64()).unwrap_or(0);
+    let message = error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown error");
+    format!("Copilot server error ({}): {}", code, message)
+}
 
-            if node_modules_path.exists() {
-                return Ok(node_modules_path);
-            }
-        }
+// Owns the server process; dropping or firing `shutdown` kills it
+struct Supervisor {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
 
-        #[cfg(target_os = "windows")]
-        {
-            let platform = "win32-x64";
-            let node_modules_path = PathBuf::from("node_modules")
-                .join(format!("@github/copilot-language-server-{}", platform))
-                .join("copilot-language-server.exe");
+#[derive(Clone)]
+pub struct CopilotServer {
+    supervisor: Arc<Mutex<Option<Supervisor>>>,
+    rpc: RpcChannel,
+    reader_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    app_handle: Arc<RwLock<Option<AppHandle>>>,
+    workspace_path: Arc<RwLock<Option<String>>>,
+    status: Arc<RwLock<CopilotStatus>>,
+    config: Arc<RwLock<CopilotConfig>>,
+    // Where the config is persisted; resolved the first time an app handle is available
+    config_path: Arc<RwLock<Option<PathBuf>>>,
+    initialized: Arc<Mutex<bool>>,
+    // Set by start and cleared by stop; the supervisor only restarts while it's set
+    keep_running: Arc<AtomicBool>,
+    launch_complete: Arc<AtomicBool>,
+    restart_attempts: Arc<AtomicU32>,
+    server_log: Arc<Mutex<VecDeque<String>>>,
+    documents: Arc<Mutex<HashMap<String, OpenDocument>>>,
+    completion_generation: Arc<AtomicU64>,
+    completion_cancel: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    completion_session: Arc<Mutex<Option<CompletionSession>>>,
+    // The panel request in flight and everything it has produced so far
+    panel_cancel: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    panel_items: Arc<Mutex<Vec<InlineCompletionItem>>>,
+    panel_sequence: Arc<AtomicU64>,
+}
 
-            if node_modules_path.exists() {
-                return Ok(node_modules_path);
-            }
+impl CopilotServer {
+    pub fn new() -> Self {
+        Self {
+            supervisor: Arc::new(Mutex::new(None)),
+            rpc: RpcChannel::new(),
+            reader_task: Arc::new(Mutex::new(None)),
+            app_handle: Arc::new(RwLock::new(None)),
+            workspace_path: Arc::new(RwLock::new(None)),
+            status: Arc::new(RwLock::new(CopilotStatus {
+                status: "Inactive".to_string(),
+                message: "Not started".to_string(),
+                signed_in: false,
+                user: None,
+            })),
+            config: Arc::new(RwLock::new(CopilotConfig::default())),
+            config_path: Arc::new(RwLock::new(None)),
+            initialized: Arc::new(Mutex::new(false)),
+            keep_running: Arc::new(AtomicBool::new(false)),
+            launch_complete: Arc::new(AtomicBool::new(false)),
+            restart_attempts: Arc::new(AtomicU32::new(0)),
+            server_log: Arc::new(Mutex::new(VecDeque::with_capacity(SERVER_LOG_LINES))),
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            completion_generation: Arc::new(AtomicU64::new(0)),
+            completion_cancel: Arc::new(Mutex::new(None)),
+            completion_session: Arc::new(Mutex::new(None)),
+            panel_cancel: Arc::new(Mutex::new(None)),
+            panel_items: Arc::new(Mutex::new(Vec::new())),
+            panel_sequence: Arc::new(AtomicU64::new(0)),
         }
+    }
 
-        #[cfg(target_os = "linux")]
-        {
-            #[cfg(target_arch = "x86_64")]
-            let platform = "linux-x64";
-            #[cfg(target_arch = "aarch64")]
-            let platform = "linux-arm64";
+    pub async fn start(&self, workspace_path: Option<String>, app_handle: AppHandle) -> Result<(), String> {
+        if self.keep_running.swap(true, Ordering::SeqCst) {
+            return Err("Copilot server already running".to_string());
+        }
 
-            let node_modules_path = PathBuf::from("node_modules")
-                .join(format!("@github/copilot-language-server-{}", platform))
-                .join("copilot-language-server");
+        self.load_config(&app_handle).await;
+        *self.workspace_path.write().await = workspace_path;
+        *self.app_handle.write().await = Some(app_handle);
+        self.restart_attempts.store(0, Ordering::SeqCst);
 
-            if node_modules_path.exists() {
-                return Ok(node_modules_path);
-            }
+        if let Err(e) = self.launch().await {
+            let _ = self.stop().await;
+            return Err(e);
         }
 
-        // Try using npx as fallback
-        Err("Copilot language server not found. Please install @github/copilot-language-server".to_string())
+        Ok(())
     }
 
-    pub async fn sign_in(&self) -> Result<SignInResponse, String> {
-        // TODO: Implement authentication flow
-        // This requires sending signIn request and handling the device flow
+    // Spawn the process, connect, initialize and bring the server up to date with open documents
+    async fn launch(&self) -> Result<(), String> {
+        self.launch_complete.store(false, Ordering::SeqCst);
 
-        Err("Sign in not yet implemented".to_string())
-    }
+        // Find the Copilot language server binary
+        let server = self.find_copilot_binary().await?;
+        log::info!("Starting Copilot server: {} {:?}", server.program.display(), server.args);
 
-    pub async fn sign_out(&self) -> Result<(), String> {
-        // TODO: Implement sign out
-        Err("Sign out not yet implemented".to_string())
-    }
+        // Start the language server process
+        let mut child = Command::new(&server.program)
+            .args(&server.args)
+            .arg("--stdio")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to start Copilot server: {}", e))?;
 
-    pub async fn get_completions(
-        &self,
-        file_uri: String,
-        _content: String,
-        position: Position,
-        _version: u32,
-    ) -> Result<InlineCompletionList, String> {
-        let initialized = self.initialized.lock().await;
-        if !*initialized {
-            return Err("Copilot server not initialized".to_string());
+        let stdin = child.stdin.take().ok_or("Failed to get stdin")?;
+        let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+
+        // Keep recent stderr output for the log viewer; draining also stops the pipe filling up
+        if let Some(stderr) = child.stderr.take() {
+            let server = self.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log::debug!("Copilot stderr: {}", line);
+                    server.append_log(line).await;
+                }
+            });
         }
-        drop(initialized);
 
-        // TODO: Implement actual completion request
-        // For now, return empty list
-        println!("Requesting completion for {} at {:?}", file_uri, position);
+        *self.rpc.stdin.lock().await = Some(Box::new(stdin));
 
-        Ok(InlineCompletionList { items: vec![] })
-    }
-}
+        // Start reading responses and notifications before the first request goes out
+        let router = self.router();
+        *self.reader_task.lock().await = Some(tokio::spawn(router.run(stdout)));
 
-// Global Copilot server instance
-static COPILOT_SERVER: once_cell::sync::Lazy<CopilotServer> =
-    once_cell::sync::Lazy::new(|| CopilotServer::new());
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let task = tokio::spawn(self.clone().supervise(child, shutdown_rx));
+        *self.supervisor.lock().await = Some(Supervisor {
+            shutdown: shutdown_tx,
+            task,
+        });
 
-// Tauri commands
-#[tauri::command]
-pub async fn copilot_start_server(workspace_path: Option<String>) -> Result<(), String> {
-    COPILOT_SERVER.start(workspace_path).await
-}
+        // Send initialize request
+        let workspace_path = self.workspace_path.read().await.clone();
+        if let Err(e) = self.initialize(workspace_path).await {
+            self.shutdown_process().await;
+            self.teardown_connection("Copilot server failed to initialize").await;
+            return Err(e);
+        }
 
-#[tauri::command]
-pub async fn copilot_stop_server() -> Result<(), String> {
-    COPILOT_SERVER.stop().await
-}
+        if let Err(e) = self.replay_documents().await {
+            log::warn!("Failed to reopen documents on the Copilot server: {}", e);
+        }
 
-#[tauri::command]
-pub async fn copilot_get_status() -> Result<CopilotStatus, String> {
-    Ok(COPILOT_SERVER.get_status().await)
-}
+        self.set_status("Normal", "Copilot server started".to_string()).await;
+        self.launch_complete.store(true, Ordering::SeqCst);
 
-#[tauri::command]
-pub async fn copilot_sign_in() -> Result<SignInResponse, String> {
-    COPILOT_SERVER.sign_in().await
-}
+        // The server keeps the GitHub token itself, so a previous sign-in survives restarts
+        if let Err(e) = self.refresh_auth().await {
+            log::warn!("Failed to check Copilot sign-in status: {}", e);
+        }
+
+        Ok(())
+    }
+
+    // Boxed so the launch -> supervise -> recover -> launch cycle has a nameable type
+    fn supervise(self, mut child: Child, mut shutdown: oneshot::Receiver<()>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            let started_at = Instant::now();
+
+            let exit = tokio::select! {
+                exit = child.wait() => exit,
+                _ = &mut shutdown => {
+                    let _ = child.kill().await;
+                    return;
+                }
+            };
+
+            let reason = match exit {
+                Ok(status) => format!("Copilot server exited unexpectedly ({})", status),
+                Err(e) => format!("Lost track of the Copilot server process: {}", e),
+            };
+            log::warn!("{}", reason);
+
+            // A crash during launch is reported by launch itself
+            if !self.launch_complete.load(Ordering::SeqCst) {
+                return;
+            }
+
+            if started_at.elapsed() >= STABLE_UPTIME {
+                self.restart_attempts.store(0, Ordering::SeqCst);
+            }
+
+            self.recover(reason).await;
+        })
+    }
+
+    async fn recover(&self, reason: String) {
+        self.append_log(format!("[vuno] {}", reason)).await;
+        *self.supervisor.lock().await = None;
+        self.teardown_connection(&reason).await;
+        self.set_status("Error", reason.clone()).await;
+
+        loop {
+            if !self.keep_running.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let attempt = self.restart_attempts.fetch_add(1, Ordering::SeqCst);
+            if attempt >= MAX_RESTART_ATTEMPTS {
+                self.keep_running.store(false, Ordering::SeqCst);
+                self.set_status(
+                    "Error",
+                    format!("{}; gave up after {} restart attempts", reason, attempt),
+                )
+                .await;
+                return;
+            }
+
+            let delay = (RESTART_BASE_DELAY * 2u32.pow(attempt)).min(RESTART_MAX_DELAY);
+            self.append_log(format!(
+                "[vuno] Restarting Copilot server in {}s (attempt {})",
+                delay.as_secs(),
+                attempt + 1
+            ))
+            .await;
+            tokio::time::sleep(delay).await;
+
+            if !self.keep_running.load(Ordering::SeqCst) {
+                return;
+            }
+
+            match self.launch().await {
+                Ok(()) => {
+                    // A stop that landed mid-launch found no supervisor to shut down yet
+                    if !self.keep_running.load(Ordering::SeqCst) {
+                        self.launch_complete.store(false, Ordering::SeqCst);
+                        self.shutdown_process().await;
+                        self.teardown_connection("Copilot server stopped").await;
+                        self.set_status("Inactive", "Copilot server stopped".to_string()).await;
+                    }
+                    return;
+                }
+                Err(e) => {
+                    self.append_log(format!("[vuno] Restart failed: {}", e)).await;
+                    self.set_status("Error", format!("Restart failed: {}", e)).await;
+                }
+            }
+        }
+    }
+
+    async fn shutdown_process(&self) {
+        // Taken in its own statement so the lock is released before waiting on the task,
+        // which may itself be in recover or launch and need the lock
+        let supervisor = self.supervisor.lock().await.take();
+        if let Some(supervisor) = supervisor {
+            let _ = supervisor.shutdown.send(());
+            let _ = supervisor.task.await;
+        }
+    }
+
+    async fn teardown_connection(&self, reason: &str) {
+        if let Some(reader_task) = self.reader_task.lock().await.take() {
+            reader_task.abort();
+        }
+        *self.rpc.stdin.lock().await = None;
+        self.rpc.fail_all(reason).await;
+        *self.initialized.lock().await = false;
+    }
+
+    // A restarted server knows nothing about the documents we had open
+    async fn replay_documents(&self) -> Result<(), String> {
+        let documents = self.documents.lock().await;
+        for (uri, document) in documents.iter() {
+            self.rpc
+                .notify(
+                    "textDocument/didOpen",
+                    serde_json::json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": document.language_id,
+                            "version": document.version,
+                            "text": document.text,
+                        }
+                    }),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn append_log(&self, line: String) {
+        let mut log = self.server_log.lock().await;
+        if log.len() >= SERVER_LOG_LINES {
+            log.pop_front();
+        }
+        log.push_back(line);
+    }
+
+    // Read the saved config once; later calls are no-ops
+    pub async fn load_config(&self, app_handle: &AppHandle) {
+        let mut config_path = self.config_path.write().await;
+        if config_path.is_some() {
+            return;
+        }
+
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle.path_resolver().resolve_resource(".")
+        } else {
+            app_handle.path_resolver().app_config_dir()
+        };
+        let Some(app_dir) = app_dir else {
+            log::warn!("No config directory; Copilot settings won't be saved");
+            return;
+        };
+
+        let path = app_dir.join("copilot.json");
+        if path.exists() {
+            match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| serde_json::from_str::<CopilotConfig>(&content).map_err(|e| e.to_string()))
+            {
+                Ok(config) => *self.config.write().await = config,
+                Err(e) => log::warn!("Ignoring invalid Copilot config {}: {}", path.display(), e),
+            }
+        }
+
+        *config_path = Some(path);
+    }
+
+    pub async fn get_config(&self) -> CopilotConfig {
+        self.config.read().await.clone()
+    }
+
+    pub async fn set_config(&self, config: CopilotConfig) -> Result<(), String> {
+        if let Some(path) = self.config_path.read().await.as_ref() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            let content = serde_json::to_string_pretty(&config)
+                .map_err(|e| format!("Failed to serialize Copilot config: {}", e))?;
+            std::fs::write(path, content)
+                .map_err(|e| format!("Failed to save Copilot config: {}", e))?;
+        }
+
+        let settings = config.server_settings();
+        *self.config.write().await = config;
+
+        // server_path only takes effect on the next start
+        if *self.initialized.lock().await {
+            self.rpc
+                .notify(
+                    "workspace/didChangeConfiguration",
+                    serde_json::json!({ "settings": settings }),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_log(&self) -> Vec<String> {
+        self.server_log.lock().await.iter().cloned().collect()
+    }
+
+    async fn set_status(&self, kind: &str, message: String) {
+        let snapshot = {
+            let mut status = self.status.write().await;
+            status.status = kind.to_string();
+            status.message = message;
+            status.clone()
+        };
+
+        if let Some(app_handle) = self.app_handle.read().await.as_ref() {
+            let _ = app_handle.emit_all("copilot_status", snapshot);
+        }
+    }
+
+    fn router(&self) -> MessageRouter {
+        MessageRouter {
+            rpc: self.rpc.clone(),
+            config: self.config.clone(),
+            status: self.status.clone(),
+            initialized: self.initialized.clone(),
+            app_handle: self.app_handle.clone(),
+            panel_items: self.panel_items.clone(),
+            panel_sequence: self.panel_sequence.clone(),
+        }
+    }
+
+    async fn ensure_initialized(&self) -> Result<(), String> {
+        if *self.initialized.lock().await {
+            Ok(())
+        } else {
+            Err("Copilot server not initialized".to_string())
+        }
+    }
+
+    pub async fn refresh_auth(&self) -> Result<bool, String> {
+        self.ensure_initialized().await?;
+
+        let result = self
+            .rpc
+            .request("checkStatus", serde_json::json!({ "localChecksOnly": false }))
+            .await?;
+
+        let (signed_in, user) = parse_auth_status(&result);
+        self.router().set_auth(signed_in, user, None).await;
+        Ok(signed_in)
+    }
+
+    async fn initialize(&self, workspace_path: Option<String>) -> Result<(), String> {
+        let workspace_folders = if let Some(path) = workspace_path {
+            vec![serde_json::json!({ "uri": format!("file://{}", path) })]
+        } else {
+            vec![]
+        };
+
+        let init_params = serde_json::json!({
+            "processId": std::process::id(),
+            "workspaceFolders": workspace_folders,
+            "capabilities": {
+                "workspace": { "workspaceFolders": true }
+            },
+            "initializationOptions": {
+                "editorInfo": {
+                    "name": "Vuno",
+                    "version": "0.1.0"
+                },
+                "editorPluginInfo": {
+                    "name": "Vuno Copilot Integration",
+                    "version": "1.0.0"
+                }
+            }
+        });
+
+        let result = self
+            .rpc
+            .request_with_timeout("initialize", init_params, INITIALIZE_TIMEOUT)
+            .await?;
+
+        if let Some(server_info) = result.get("serverInfo") {
+            log::info!("Copilot server initialized: {}", server_info);
+        }
+
+        self.rpc.notify("initialized", serde_json::json!({})).await?;
+
+        // Push settings straight away so a telemetry opt-out applies from the first request
+        let settings = self.config.read().await.server_settings();
+        self.rpc
+            .notify(
+                "workspace/didChangeConfiguration",
+                serde_json::json!({ "settings": settings }),
+            )
+            .await?;
+
+        let mut initialized = self.initialized.lock().await;
+        *initialized = true;
+
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<(), String> {
+        self.keep_running.store(false, Ordering::SeqCst);
+        self.launch_complete.store(false, Ordering::SeqCst);
+
+        self.shutdown_process().await;
+        self.teardown_connection("Copilot server stopped").await;
+        self.documents.lock().await.clear();
+        self.panel_items.lock().await.clear();
+        *self.completion_session.lock().await = None;
+
+        let mut status = self.status.write().await;
+        status.status = "Inactive".to_string();
+        status.message = "Copilot server stopped".to_string();
+        status.signed_in = false;
+        status.user = None;
+
+        Ok(())
+    }
+
+    pub async fn get_status(&self) -> CopilotStatus {
+        self.status.read().await.clone()
+    }
+
+    async fn find_copilot_binary(&self) -> Result<ServerCommand, String> {
+        let configured = self.config.read().await.server_path.clone();
+        let search = std::env::var_os("PATH").unwrap_or_default();
+        locate_server(configured, &search).await
+    }
+
+    pub async fn sign_in(&self) -> Result<SignInResponse, String> {
+        self.ensure_initialized().await?;
+
+        let result = self
+            .rpc
+            .request("signInInitiate", serde_json::json!({}))
+            .await?;
+
+        let status = result
+            .get("status")
+            .and_then(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let user = result.get("user").and_then(|u| u.as_str()).map(String::from);
+
+        if status == "AlreadySignedIn" {
+            self.router()
+                .set_auth(true, user.clone(), Some("Signed in to GitHub Copilot".to_string()))
+                .await;
+
+            return Ok(SignInResponse {
+                user_code: String::new(),
+                verification_uri: String::new(),
+                status,
+                user,
+                expires_in: None,
+            });
+        }
+
+        let user_code = result
+            .get("userCode")
+            .and_then(|c| c.as_str())
+            .ok_or("Copilot did not return a device code")?
+            .to_string();
+        let verification_uri = result
+            .get("verificationUri")
+            .and_then(|u| u.as_str())
+            .ok_or("Copilot did not return a verification URI")?
+            .to_string();
+        let expires_in = result.get("expiresIn").and_then(|e| e.as_u64());
+
+        // signInConfirm only answers once the user has entered the code in the browser
+        let router = self.router();
+        let confirm_code = user_code.clone();
+        let limit = expires_in.map(Duration::from_secs).unwrap_or(DEVICE_FLOW_TIMEOUT);
+        tokio::spawn(async move {
+            let outcome = router
+                .rpc
+                .request_with_timeout(
+                    "signInConfirm",
+                    serde_json::json!({ "userCode": confirm_code }),
+                    limit,
+                )
+                .await;
+
+            match outcome {
+                Ok(result) => {
+                    let (signed_in, user) = parse_auth_status(&result);
+                    let message = if signed_in {
+                        "Signed in to GitHub Copilot".to_string()
+                    } else {
+                        "GitHub account is not authorized for Copilot".to_string()
+                    };
+                    router.set_auth(signed_in, user, Some(message)).await;
+                }
+                Err(e) => {
+                    log::warn!("Copilot sign-in was not confirmed: {}", e);
+                    router
+                        .set_auth(false, None, Some(format!("Sign in failed: {}", e)))
+                        .await;
+                }
+            }
+        });
+
+        Ok(SignInResponse {
+            user_code,
+            verification_uri,
+            status,
+            user,
+            expires_in,
+        })
+    }
+
+    pub async fn sign_out(&self) -> Result<(), String> {
+        self.ensure_initialized().await?;
+
+        self.rpc
+            .request("signOut", serde_json::json!({}))
+            .await?;
+
+        self.router()
+            .set_auth(false, None, Some("Signed out of GitHub Copilot".to_string()))
+            .await;
+        Ok(())
+    }
+
+    pub async fn get_completions(
+        &self,
+        file_uri: String,
+        content: String,
+        position: Position,
+        version: u32,
+        language_id: Option<String>,
+        invoked: bool,
+    ) -> Result<InlineCompletionList, String> {
+        let config = self.config.read().await.clone();
+        if !config.enabled || (!invoked && !config.auto_trigger) {
+            return Ok(InlineCompletionList { items: vec![] });
+        }
+
+        self.ensure_initialized().await?;
+
+        let language_id = language_id.unwrap_or_else(|| language_for_uri(&file_uri, &content));
+
+        // Excluded documents never reach the server, not even as didOpen
+        if !config.allows_document(&file_uri, &language_id) {
+            return Ok(InlineCompletionList { items: vec![] });
+        }
+
+        // Explicit requests go out straight away
+        let debounce_ms = if invoked { 0 } else { config.debounce_ms };
+
+        // A newer request supersedes this one, both while debouncing and while in flight
+        let generation = self.completion_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        if let Some(previous) = self.completion_cancel.lock().await.replace(cancel_tx) {
+            let _ = previous.send(());
+        }
+
+        if debounce_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(debounce_ms)).await;
+        }
+        if self.completion_generation.load(Ordering::SeqCst) != generation {
+            return Ok(InlineCompletionList { items: vec![] });
+        }
+
+        let version = self
+            .sync_document(&file_uri, &content, &language_id, version as i32)
+            .await?;
+
+        let trigger = if invoked { TRIGGER_INVOKED } else { TRIGGER_AUTOMATIC };
+        let params = inline_completion_params(&file_uri, version, &position, trigger);
+        let result = match self
+            .rpc
+            .request_cancellable("textDocument/inlineCompletion", params, cancel_rx)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) if e == REQUEST_CANCELLED => return Ok(InlineCompletionList { items: vec![] }),
+            Err(e) => return Err(e),
+        };
+
+        let list = parse_completion_list(result)?;
+
+        *self.completion_session.lock().await = Some(CompletionSession {
+            generation,
+            uri: file_uri,
+            language_id,
+            version,
+            position,
+            items: list.items.clone(),
+            index: 0,
+            accepted: 0,
+            fetched_alternatives: false,
+        });
+
+        Ok(list)
+    }
+
+    // Open the document on the server, or send the full new text if it changed.
+    // Returns the version the server now knows the document by.
+    async fn sync_document(
+        &self,
+        uri: &str,
+        content: &str,
+        language_id: &str,
+        version: i32,
+    ) -> Result<i32, String> {
+        let mut documents = self.documents.lock().await;
+
+        match documents.get_mut(uri) {
+            None => {
+                self.rpc
+                    .notify(
+                        "textDocument/didOpen",
+                        serde_json::json!({
+                            "textDocument": {
+                                "uri": uri,
+                                "languageId": language_id,
+                                "version": version,
+                                "text": content,
+                            }
+                        }),
+                    )
+                    .await?;
+
+                documents.insert(
+                    uri.to_string(),
+                    OpenDocument {
+                        version,
+                        language_id: language_id.to_string(),
+                        text: content.to_string(),
+                    },
+                );
+                Ok(version)
+            }
+            Some(document) if document.text != content => {
+                // Versions must increase even if the caller doesn't track them
+                let version = version.max(document.version + 1);
+                self.rpc
+                    .notify(
+                        "textDocument/didChange",
+                        serde_json::json!({
+                            "textDocument": { "uri": uri, "version": version },
+                            "contentChanges": [{ "text": content }],
+                        }),
+                    )
+                    .await?;
+
+                document.version = version;
+                document.text = content.to_string();
+                Ok(version)
+            }
+            Some(document) => Ok(document.version),
+        }
+    }
+
+    pub async fn cycle_completion(&self, direction: i32) -> Result<InlineCompletionSelection, String> {
+        let pending = {
+            let session_guard = self.completion_session.lock().await;
+            let session = session_guard
+                .as_ref()
+                .ok_or("No active Copilot completion")?;
+            let config = self.config.read().await;
+            if !config.enabled || !config.allows_document(&session.uri, &session.language_id) {
+                return Err("Copilot is disabled for this file".to_string());
+            }
+            (!session.fetched_alternatives).then(|| {
+                let params = inline_completion_params(&session.uri, session.version, &session.position, TRIGGER_INVOKED);
+                (session.generation, params)
+            })
+        };
+
+        // Automatic requests return a single suggestion; ask again explicitly for alternatives.
+        // The session isn't held meanwhile, so typing can replace or dismiss it.
+        let alternatives = match pending {
+            Some((generation, params)) => {
+                let result = self
+                    .rpc
+                    .request("textDocument/inlineCompletion", params)
+                    .await?;
+                Some((generation, parse_completion_list(result)?.items))
+            }
+            None => None,
+        };
+
+        let mut session_guard = self.completion_session.lock().await;
+        let session = session_guard
+            .as_mut()
+            .ok_or("No active Copilot completion")?;
+        if let Some((generation, items)) = alternatives {
+            if session.generation != generation {
+                return Err("The Copilot completion changed while fetching alternatives".to_string());
+            }
+            for item in items {
+                if !session.items.iter().any(|i| i.insert_text == item.insert_text) {
+                    session.items.push(item);
+                }
+            }
+            session.fetched_alternatives = true;
+        }
+
+        if session.items.is_empty() {
+            return Err("Copilot has no suggestions here".to_string());
+        }
+
+        let total = session.items.len() as i64;
+        session.index = (session.index as i64 + direction as i64).rem_euclid(total) as usize;
+        session.accepted = 0;
+
+        Ok(InlineCompletionSelection {
+            item: session.items[session.index].clone(),
+            index: session.index,
+            total: session.items.len(),
+        })
+    }
+
+    pub async fn accept_partial_completion(&self, granularity: &str) -> Result<PartialAcceptance, String> {
+        let mut session_guard = self.completion_session.lock().await;
+        let session = session_guard
+            .as_mut()
+            .ok_or("No active Copilot completion")?;
+        let item = session
+            .items
+            .get(session.index)
+            .ok_or("No active Copilot completion")?;
+
+        let unaccepted = unaccepted_text(item, &session.position);
+        let remaining = &unaccepted[session.accepted.min(unaccepted.len())..];
+        let length = match granularity {
+            "word" => next_word_length(remaining),
+            "line" => remaining.find('\n').map(|i| i + 1).unwrap_or(remaining.len()),
+            other => return Err(format!("Unknown acceptance granularity: {}", other)),
+        };
+
+        let text = remaining[..length].to_string();
+        let rest = remaining[length..].to_string();
+        session.accepted += length;
+
+        let accepted_length = unaccepted[..session.accepted.min(unaccepted.len())]
+            .encode_utf16()
+            .count();
+        let item = item.clone();
+
+        let complete = rest.is_empty();
+        if complete {
+            *session_guard = None;
+        }
+        drop(session_guard);
+
+        self.report_telemetry(
+            "textDocument/didPartiallyAcceptCompletion",
+            serde_json::json!({ "item": item, "acceptedLength": accepted_length }),
+        )
+        .await;
+
+        Ok(PartialAcceptance {
+            text,
+            remaining: rest,
+            complete,
+        })
+    }
+
+    // Every telemetry message goes through here; with telemetry off nothing is sent at all
+    async fn send_telemetry(&self, method: &str, params: serde_json::Value) -> Result<(), String> {
+        if !self.config.read().await.telemetry {
+            return Ok(());
+        }
+        self.ensure_initialized().await?;
+
+        if method == "workspace/executeCommand" {
+            self.rpc.request(method, params).await.map(|_| ())
+        } else {
+            self.rpc.notify(method, params).await
+        }
+    }
+
+    // For telemetry sent after the session has already moved on: the caller's change stands either
+    // way, so a failure to report it is only logged
+    async fn report_telemetry(&self, method: &str, params: serde_json::Value) {
+        if let Err(e) = self.send_telemetry(method, params).await {
+            log::warn!("Failed to send Copilot telemetry '{}': {}", method, e);
+        }
+    }
+
+    // Ask for a batch of alternative suggestions. Partial results arrive as
+    // "copilot_panel_completions" events tagged with the returned token.
+    pub async fn get_panel_completions(
+        &self,
+        file_uri: String,
+        content: String,
+        position: Position,
+        version: u32,
+        language_id: Option<String>,
+    ) -> Result<PanelCompletionBatch, String> {
+        let config = self.config.read().await.clone();
+        if !config.enabled {
+            return Err("Copilot is disabled".to_string());
+        }
+
+        self.ensure_initialized().await?;
+
+        let language_id = language_id.unwrap_or_else(|| language_for_uri(&file_uri, &content));
+        if !config.allows_document(&file_uri, &language_id) {
+            return Err("Copilot is disabled for this file".to_string());
+        }
+
+        let token = format!(
+            "{}{}",
+            PANEL_TOKEN_PREFIX,
+            self.panel_sequence.fetch_add(1, Ordering::SeqCst) + 1
+        );
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        if let Some(previous) = self.panel_cancel.lock().await.replace(cancel_tx) {
+            let _ = previous.send(());
+        }
+        self.panel_items.lock().await.clear();
+
+        let version = self
+            .sync_document(&file_uri, &content, &language_id, version as i32)
+            .await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": file_uri, "version": version },
+            "position": position,
+            "partialResultToken": token,
+        });
+        let result = self
+            .rpc
+            .request_inner("textDocument/copilotPanelCompletion", params, PANEL_TIMEOUT, Some(cancel_rx))
+            .await;
+
+        let list = match result {
+            Ok(result) => parse_completion_list(result)?,
+            Err(e) if e == REQUEST_CANCELLED => InlineCompletionList { items: vec![] },
+            Err(e) => return Err(e),
+        };
+
+        // Servers that don't stream put everything in the final response
+        let items = {
+            let mut panel_items = self.panel_items.lock().await;
+            panel_items.extend(list.items.iter().cloned());
+            list.items
+        };
+
+        let batch = PanelCompletionBatch {
+            token,
+            items,
+            done: true,
+        };
+        self.router().emit_panel(&batch.token, batch.items.clone(), true).await;
+        Ok(batch)
+    }
+
+    pub async fn cancel_panel_completions(&self) {
+        if let Some(cancel) = self.panel_cancel.lock().await.take() {
+            let _ = cancel.send(());
+        }
+    }
+
+    async fn session_item(&self, completion_id: &str) -> Option<InlineCompletionItem> {
+        let inline = self
+            .completion_session
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|session| {
+                session
+                    .items
+                    .iter()
+                    .find(|item| item.id.as_deref() == Some(completion_id))
+                    .cloned()
+            });
+
+        match inline {
+            Some(item) => Some(item),
+            None => self
+                .panel_items
+                .lock()
+                .await
+                .iter()
+                .find(|item| item.id.as_deref() == Some(completion_id))
+                .cloned(),
+        }
+    }
+
+    pub async fn notify_shown(&self, completion_id: &str) -> Result<(), String> {
+        match self.session_item(completion_id).await {
+            Some(item) => {
+                self.send_telemetry(
+                    "textDocument/didShowCompletion",
+                    serde_json::json!({ "item": item }),
+                )
+                .await
+            }
+            None => {
+                self.send_telemetry("notifyShown", serde_json::json!({ "uuid": completion_id }))
+                    .await
+            }
+        }
+    }
+
+    pub async fn notify_accepted(&self, completion_id: &str) -> Result<(), String> {
+        let item = self.session_item(completion_id).await;
+        *self.completion_session.lock().await = None;
+
+        // The server tracks acceptance through the command attached to each item
+        match item.and_then(|item| item.command) {
+            Some(command) => {
+                self.report_telemetry(
+                    "workspace/executeCommand",
+                    serde_json::json!({
+                        "command": command.get("command"),
+                        "arguments": command.get("arguments"),
+                    }),
+                )
+                .await
+            }
+            None => {
+                self.report_telemetry("notifyAccepted", serde_json::json!({ "uuid": completion_id }))
+                    .await
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn notify_rejected(&self, completion_id: &str) -> Result<(), String> {
+        *self.completion_session.lock().await = None;
+        self.report_telemetry(
+            "notifyRejected",
+            serde_json::json!({ "uuids": [completion_id] }),
+        )
+        .await;
+        Ok(())
+    }
+}
+
+// How to launch the language server once it has been located
+#[derive(Debug)]
+struct ServerCommand {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl ServerCommand {
+    fn direct(program: PathBuf) -> Self {
+        Self {
+            program,
+            args: Vec::new(),
+        }
+    }
+}
+
+fn server_binary_name() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "copilot-language-server.exe"
+    } else {
+        "copilot-language-server"
+    }
+}
+
+// Suffix of the platform-specific npm package that ships the native binary
+fn platform_suffix() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "win32-x64"
+    } else if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        "darwin-arm64"
+    } else if cfg!(target_os = "macos") {
+        "darwin-x64"
+    } else if cfg!(target_arch = "aarch64") {
+        "linux-arm64"
+    } else {
+        "linux-x64"
+    }
+}
+
+// Where the language server is, in order of preference; `search` is a PATH-style list of
+// directories. The error lists every place that was tried.
+async fn locate_server(configured: Option<String>, search: &OsStr) -> Result<ServerCommand, String> {
+    let binary = server_binary_name();
+    let mut tried = Vec::new();
+
+    // 1. A user-configured path wins outright
+    if let Some(path) = configured {
+        let path = PathBuf::from(path);
+        if path.is_file() {
+            return Ok(ServerCommand::direct(path));
+        }
+        return Err(format!(
+            "Configured Copilot language server not found at {}",
+            path.display()
+        ));
+    }
+
+    // 2. PATH
+    match search_path("copilot-language-server", search) {
+        Some(path) => return Ok(ServerCommand::direct(path)),
+        None => tried.push("PATH (copilot-language-server)".to_string()),
+    }
+
+    // 3. node_modules relative to the working directory
+    let local_package = PathBuf::from("node_modules")
+        .join("@github")
+        .join(format!("copilot-language-server-{}", platform_suffix()))
+        .join(binary);
+    if local_package.is_file() {
+        return Ok(ServerCommand::direct(local_package));
+    }
+    tried.push(local_package.display().to_string());
+
+    // 4. Global npm installation
+    match npm_global_prefix(search).await {
+        Some(prefix) => {
+            for candidate in global_npm_candidates(&prefix) {
+                if candidate.is_file() {
+                    return Ok(ServerCommand::direct(candidate));
+                }
+                tried.push(candidate.display().to_string());
+            }
+        }
+        None => tried.push("global npm prefix (npm not found or failed)".to_string()),
+    }
+
+    // 5. Let npx fetch and run the package
+    match search_path("npx", search) {
+        Some(npx) => {
+            return Ok(ServerCommand {
+                program: npx,
+                args: vec![
+                    "--yes".to_string(),
+                    "@github/copilot-language-server".to_string(),
+                ],
+            })
+        }
+        None => tried.push("npx --yes @github/copilot-language-server (npx not found on PATH)".to_string()),
+    }
+
+    Err(format!(
+        "Copilot language server not found. Please install @github/copilot-language-server. Tried:\n  {}",
+        tried.join("\n  ")
+    ))
+}
+
+fn search_path(name: &str, search: &OsStr) -> Option<PathBuf> {
+    let extensions: &[&str] = if cfg!(target_os = "windows") {
+        &["exe", "cmd", "bat"]
+    } else {
+        &[""]
+    };
+
+    for dir in std::env::split_paths(search) {
+        for extension in extensions {
+            let candidate = if extension.is_empty() {
+                dir.join(name)
+            } else {
+                dir.join(name).with_extension(extension)
+            };
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+async fn npm_global_prefix(search: &OsStr) -> Option<PathBuf> {
+    let npm = search_path("npm", search)?;
+    let output = Command::new(npm)
+        .args(["prefix", "-g"])
+        .env("PATH", search)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if prefix.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(prefix))
+    }
+}
+
+fn global_npm_candidates(prefix: &Path) -> Vec<PathBuf> {
+    // npm puts global packages under lib/ and shims under bin/, except on Windows
+    let (modules, bin) = if cfg!(target_os = "windows") {
+        (prefix.join("node_modules"), prefix.to_path_buf())
+    } else {
+        (prefix.join("lib").join("node_modules"), prefix.join("bin"))
+    };
+
+    let shim = if cfg!(target_os = "windows") {
+        "copilot-language-server.cmd"
+    } else {
+        "copilot-language-server"
+    };
+
+    vec![
+        modules
+            .join("@github")
+            .join(format!("copilot-language-server-{}", platform_suffix()))
+            .join(server_binary_name()),
+        bin.join(shim),
+    ]
+}
+
+// Both checkStatus and signInConfirm answer with a status string and the GitHub user
+fn parse_auth_status(result: &serde_json::Value) -> (bool, Option<String>) {
+    let signed_in = matches!(
+        result.get("status").and_then(|s| s.as_str()),
+        Some("OK") | Some("AlreadySignedIn") | Some("MaybeOk")
+    );
+    let user = result
+        .get("user")
+        .and_then(|u| u.as_str())
+        .filter(|_| signed_in)
+        .map(String::from);
+    (signed_in, user)
+}
+
+fn completion_id(item: &InlineCompletionItem) -> Option<String> {
+    item.command
+        .as_ref()
+        .and_then(|command| command.get("arguments"))
+        .and_then(|arguments| arguments.get(0))
+        .and_then(|argument| argument.as_str())
+        .map(String::from)
+}
+
+fn inline_completion_params(uri: &str, version: i32, position: &Position, trigger_kind: u32) -> serde_json::Value {
+    serde_json::json!({
+        "textDocument": { "uri": uri, "version": version },
+        "position": position,
+        "context": { "triggerKind": trigger_kind },
+        "formattingOptions": { "tabSize": 4, "insertSpaces": true },
+    })
+}
+
+fn parse_completion_list(result: serde_json::Value) -> Result<InlineCompletionList, String> {
+    // The server may answer with a bare array or an InlineCompletionList
+    let items = if result.is_array() {
+        result
+    } else {
+        result.get("items").cloned().unwrap_or(serde_json::json!([]))
+    };
+
+    let mut items: Vec<InlineCompletionItem> = serde_json::from_value(items)
+        .map_err(|e| format!("Failed to parse Copilot completions: {}", e))?;
+    for item in &mut items {
+        if item.id.is_none() {
+            item.id = completion_id(item);
+        }
+    }
+    Ok(InlineCompletionList { items })
+}
+
+fn language_for_uri(uri: &str, content: &str) -> String {
+    let path = uri_path(uri).map(PathBuf::from);
+    BufferManager::detect_language(&path, content).unwrap_or_else(|| "plaintext".to_string())
+}
+
+// The part of a suggestion after the cursor; insert text starts at the range start,
+// so anything between there and the cursor is already in the document.
+fn unaccepted_text(item: &InlineCompletionItem, position: &Position) -> String {
+    let typed = match &item.range {
+        Some(range) if range.start.line == position.line => {
+            position.character.saturating_sub(range.start.character) as usize
+        }
+        _ => 0,
+    };
+
+    let mut units = 0;
+    for (index, ch) in item.insert_text.char_indices() {
+        if units >= typed {
+            return item.insert_text[index..].to_string();
+        }
+        units += ch.len_utf16();
+    }
+    String::new()
+}
+
+// Leading whitespace plus the next identifier, or a single punctuation character
+fn next_word_length(text: &str) -> usize {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut chars = text.char_indices().peekable();
+    let mut end = 0;
+
+    while let Some(&(index, ch)) = chars.peek() {
+        if !ch.is_whitespace() {
+            break;
+        }
+        end = index + ch.len_utf8();
+        chars.next();
+    }
+
+    match chars.peek() {
+        Some(&(_, ch)) if is_word(ch) => {
+            while let Some(&(index, ch)) = chars.peek() {
+                if !is_word(ch) {
+                    break;
+                }
+                end = index + ch.len_utf8();
+                chars.next();
+            }
+        }
+        Some(&(index, ch)) => end = index + ch.len_utf8(),
+        None => {}
+    }
+
+    end
+}
+
+// LSP positions count UTF-16 code units within a line
+fn offset_to_position(content: &str, offset: usize) -> Position {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn buffer_uri(buffer_id: usize, buffer: &Buffer) -> String {
+    match &buffer.path {
+        Some(path) => format!("file://{}", path.display()),
+        None => format!("untitled:buffer-{}", buffer_id),
+    }
+}
+
+// Global Copilot server instance
+static COPILOT_SERVER: once_cell::sync::Lazy<CopilotServer> =
+    once_cell::sync::Lazy::new(CopilotServer::new);
+
+// Tauri commands
+#[tauri::command]
+pub async fn copilot_start_server(workspace_path: Option<String>, app_handle: AppHandle) -> Result<(), String> {
+    COPILOT_SERVER.start(workspace_path, app_handle).await
+}
+
+#[tauri::command]
+pub async fn copilot_stop_server() -> Result<(), String> {
+    COPILOT_SERVER.stop().await
+}
+
+#[tauri::command]
+pub async fn copilot_get_status() -> Result<CopilotStatus, String> {
+    Ok(COPILOT_SERVER.get_status().await)
+}
+
+#[tauri::command]
+pub async fn copilot_get_config(app_handle: AppHandle) -> Result<CopilotConfig, String> {
+    COPILOT_SERVER.load_config(&app_handle).await;
+    Ok(COPILOT_SERVER.get_config().await)
+}
+
+#[tauri::command]
+pub async fn copilot_set_config(config: CopilotConfig, app_handle: AppHandle) -> Result<(), String> {
+    COPILOT_SERVER.load_config(&app_handle).await;
+    COPILOT_SERVER.set_config(config).await
+}
+
+#[tauri::command]
+pub async fn copilot_get_server_log() -> Result<Vec<String>, String> {
+    Ok(COPILOT_SERVER.get_log().await)
+}
+
+#[tauri::command]
+pub async fn copilot_sign_in() -> Result<SignInResponse, String> {
+    COPILOT_SERVER.sign_in().await
+}
 
 #[tauri::command]
 pub async fn copilot_sign_out() -> Result<(), String> {
     COPILOT_SERVER.sign_out().await
 }
 
+#[tauri::command]
+pub async fn copilot_check_auth() -> Result<bool, String> {
+    COPILOT_SERVER.refresh_auth().await
+}
+
 #[tauri::command]
 pub async fn copilot_get_completions(
     file_uri: String,
@@ -406,23 +2012,503 @@ pub async fn copilot_get_completions(
     line: u32,
     character: u32,
     version: u32,
+    invoked: Option<bool>,
 ) -> Result<InlineCompletionList, String> {
     let position = Position { line, character };
     COPILOT_SERVER
-        .get_completions(file_uri, content, position, version)
+        .get_completions(file_uri, content, position, version, None, invoked.unwrap_or(false))
         .await
 }
 
 #[tauri::command]
-pub async fn copilot_accept_completion(completion_id: String) -> Result<(), String> {
-    // TODO: Send telemetry for accepted completion
-    println!("Completion accepted: {}", completion_id);
+pub async fn copilot_get_buffer_completions(
+    buffer_id: usize,
+    invoked: Option<bool>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<InlineCompletionList, String> {
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+
+    let uri = buffer_uri(buffer_id, &buffer);
+    let position = offset_to_position(&buffer.content, buffer.cursor_position);
+
+    COPILOT_SERVER
+        .get_completions(uri, buffer.content, position, 0, buffer.language, invoked.unwrap_or(false))
+        .await
+}
+
+#[tauri::command]
+pub async fn copilot_get_panel_completions(
+    file_uri: String,
+    content: String,
+    line: u32,
+    character: u32,
+    version: u32,
+) -> Result<PanelCompletionBatch, String> {
+    let position = Position { line, character };
+    COPILOT_SERVER
+        .get_panel_completions(file_uri, content, position, version, None)
+        .await
+}
+
+#[tauri::command]
+pub async fn copilot_cancel_panel_completions() -> Result<(), String> {
+    COPILOT_SERVER.cancel_panel_completions().await;
     Ok(())
 }
 
+#[tauri::command]
+pub async fn copilot_cycle_completion(direction: i32) -> Result<InlineCompletionSelection, String> {
+    COPILOT_SERVER.cycle_completion(direction).await
+}
+
+#[tauri::command]
+pub async fn copilot_accept_partial_completion(granularity: String) -> Result<PartialAcceptance, String> {
+    COPILOT_SERVER.accept_partial_completion(&granularity).await
+}
+
+#[tauri::command]
+pub async fn copilot_show_completion(completion_id: String) -> Result<(), String> {
+    COPILOT_SERVER.notify_shown(&completion_id).await
+}
+
+#[tauri::command]
+pub async fn copilot_accept_completion(completion_id: String) -> Result<(), String> {
+    COPILOT_SERVER.notify_accepted(&completion_id).await
+}
+
 #[tauri::command]
 pub async fn copilot_reject_completion(completion_id: String) -> Result<(), String> {
-    // TODO: Send telemetry for rejected completion
-    println!("Completion rejected: {}", completion_id);
-    Ok(())
+    COPILOT_SERVER.notify_rejected(&completion_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, split};
+
+    // Every request and notification the stub saw, in order
+    type Received = Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>;
+
+    // Plays the language server's side of sign-in over an in-memory pipe. The device code is
+    // confirmed straight away, as if the user had entered it in the browser.
+    fn scripted_server(confirm: Option<&'static str>) -> (CopilotServer, Received) {
+        let (client, stub) = duplex(4096);
+        let (client_reader, client_writer) = split(client);
+        let received: Received = Arc::default();
+
+        let log = received.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = split(stub);
+            let mut reader = BufReader::new(reader);
+            let mut signed_in = false;
+
+            while let Ok(Some(message)) = read_frame(&mut reader).await {
+                let method = message["method"].as_str().unwrap_or_default().to_string();
+                log.lock().unwrap().push((method.clone(), message["params"].clone()));
+                let Some(id) = message.get("id").cloned() else {
+                    continue;
+                };
+
+                let outcome = match method.as_str() {
+                    "initialize" => Ok(serde_json::json!({ "capabilities": {}, "serverInfo": { "name": "stub" } })),
+                    "checkStatus" if signed_in => Ok(serde_json::json!({ "status": "OK", "user": "octocat" })),
+                    "checkStatus" => Ok(serde_json::json!({ "status": "NotSignedIn" })),
+                    "signInInitiate" => Ok(serde_json::json!({
+                        "status": "PromptUserDeviceFlow",
+                        "userCode": "ABCD-1234",
+                        "verificationUri": "https://github.com/login/device",
+                        "expiresIn": 900,
+                    })),
+                    "signInConfirm" => match confirm {
+                        Some(status) => {
+                            signed_in = status == "OK";
+                            Ok(serde_json::json!({ "status": status, "user": "octocat" }))
+                        }
+                        None => Err(serde_json::json!({ "code": 1000, "message": "device code expired" })),
+                    },
+                    "signOut" => {
+                        signed_in = false;
+                        Ok(serde_json::json!({ "status": "NotSignedIn" }))
+                    }
+                    "textDocument/inlineCompletion" => Ok(serde_json::json!({
+                        "items": [
+                            {
+                                "insertText": "let a = 1;",
+                                "command": { "command": "github.copilot.didAcceptCompletionItem", "arguments": ["a"] },
+                            },
+                            { "insertText": "let b = 2;" },
+                        ],
+                    })),
+                    _ => Err(serde_json::json!({ "code": -32601, "message": "Method not found" })),
+                };
+
+                let response = match outcome {
+                    Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err(error) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+                };
+                if write_frame(&mut writer, &response).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let server = CopilotServer::new();
+        let rpc = server.rpc.clone();
+        let router = server.router();
+        tokio::spawn(async move {
+            *rpc.stdin.lock().await = Some(Box::new(client_writer));
+            router.run(client_reader).await;
+        });
+        (server, received)
+    }
+
+    fn methods(received: &Received) -> Vec<String> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, _)| method.clone())
+            .filter(|method| method != "initialized" && method != "workspace/didChangeConfiguration")
+            .collect()
+    }
+
+    // signInConfirm is answered in the background, so wait for its outcome to land in the status
+    async fn wait_for_message(server: &CopilotServer, expected: &str) -> CopilotStatus {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let status = server.get_status().await;
+            if status.message.contains(expected) {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "status never reached '{}': {:?}", expected, status.message);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn initialize(server: &CopilotServer) {
+        // The writer is installed by a spawned task; wait until requests can go out
+        while server.rpc.stdin.lock().await.is_none() {
+            tokio::task::yield_now().await;
+        }
+        server.initialize(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn signs_in_through_the_device_flow_and_out_again() {
+        let (server, received) = scripted_server(Some("OK"));
+        initialize(&server).await;
+        assert!(!server.refresh_auth().await.unwrap());
+
+        let response = server.sign_in().await.unwrap();
+        assert_eq!(response.status, "PromptUserDeviceFlow");
+        assert_eq!(response.user_code, "ABCD-1234");
+        assert_eq!(response.verification_uri, "https://github.com/login/device");
+        assert_eq!(response.expires_in, Some(900));
+
+        let status = wait_for_message(&server, "Signed in to GitHub Copilot").await;
+        assert!(status.signed_in);
+        assert_eq!(status.user.as_deref(), Some("octocat"));
+        assert!(server.refresh_auth().await.unwrap());
+
+        server.sign_out().await.unwrap();
+        let status = server.get_status().await;
+        assert!(!status.signed_in);
+        assert_eq!(status.user, None);
+        assert!(!server.refresh_auth().await.unwrap());
+
+        assert_eq!(
+            methods(&received),
+            vec!["initialize", "checkStatus", "signInInitiate", "signInConfirm", "checkStatus", "signOut", "checkStatus"]
+        );
+        let confirm = received
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(method, _)| method == "signInConfirm")
+            .map(|(_, params)| params.clone())
+            .unwrap();
+        assert_eq!(confirm["userCode"], "ABCD-1234");
+    }
+
+    #[tokio::test]
+    async fn reports_an_account_without_copilot_access() {
+        let (server, _received) = scripted_server(Some("NotAuthorized"));
+        initialize(&server).await;
+
+        server.sign_in().await.unwrap();
+        let status = wait_for_message(&server, "not authorized").await;
+        assert!(!status.signed_in);
+        assert_eq!(status.user, None);
+    }
+
+    #[tokio::test]
+    async fn reports_a_failed_confirmation() {
+        let (server, _received) = scripted_server(None);
+        initialize(&server).await;
+
+        server.sign_in().await.unwrap();
+        let status = wait_for_message(&server, "Sign in failed").await;
+        assert!(status.message.contains("device code expired"));
+        assert!(!status.signed_in);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_sign_in_before_initialize() {
+        let (server, received) = scripted_server(Some("OK"));
+        assert!(server.sign_in().await.is_err());
+        assert!(server.sign_out().await.is_err());
+        assert!(methods(&received).is_empty());
+    }
+
+    #[tokio::test]
+    async fn cycles_through_alternatives_only_while_enabled() {
+        let (server, received) = scripted_server(Some("OK"));
+        initialize(&server).await;
+
+        let position = Position { line: 0, character: 0 };
+        let list = server
+            .get_completions("file:///work/main.rs".to_string(), String::new(), position, 1, None, true)
+            .await
+            .unwrap();
+        assert_eq!(list.items.len(), 2);
+
+        let selection = server.cycle_completion(1).await.unwrap();
+        assert_eq!((selection.index, selection.total), (1, 2));
+        assert_eq!(selection.item.insert_text, "let b = 2;");
+        let selection = server.cycle_completion(1).await.unwrap();
+        assert_eq!(selection.index, 0);
+        let requests = methods(&received)
+            .into_iter()
+            .filter(|method| method == "textDocument/inlineCompletion")
+            .count();
+        assert_eq!(requests, 2);
+
+        server.config.write().await.disabled_languages = vec!["rust".to_string()];
+        assert!(server.cycle_completion(1).await.is_err());
+        server.config.write().await.disabled_languages.clear();
+        server.config.write().await.enabled = false;
+        assert!(server.cycle_completion(1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn telemetry_failures_dont_undo_acceptance() {
+        let (server, _received) = scripted_server(Some("OK"));
+        initialize(&server).await;
+        let position = Position { line: 0, character: 0 };
+        server
+            .get_completions("file:///work/main.rs".to_string(), String::new(), position, 1, None, true)
+            .await
+            .unwrap();
+
+        // The notification can't go out, but the word is still accepted
+        *server.initialized.lock().await = false;
+        let partial = server.accept_partial_completion("word").await.unwrap();
+        assert_eq!(partial.text, "let");
+        assert_eq!(partial.remaining, " a = 1;");
+        *server.initialized.lock().await = true;
+
+        // The stub doesn't know the acceptance command, and the session is still closed
+        server.notify_accepted("a").await.unwrap();
+        assert!(server.completion_session.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn nothing_reaches_the_server_with_telemetry_off() {
+        let (server, received) = scripted_server(Some("OK"));
+        initialize(&server).await;
+        server.config.write().await.telemetry = false;
+
+        let position = Position { line: 0, character: 0 };
+        let list = server
+            .get_completions("file:///work/main.rs".to_string(), String::new(), position, 1, None, true)
+            .await
+            .unwrap();
+        let first = list.items[0].id.clone().unwrap();
+        server.notify_shown(&first).await.unwrap();
+        server.accept_partial_completion("word").await.unwrap();
+        server.notify_accepted(&first).await.unwrap();
+        server.notify_rejected("unknown").await.unwrap();
+
+        // A round trip, so anything sent before it has been read by the stub
+        server.refresh_auth().await.unwrap();
+        assert_eq!(
+            methods(&received),
+            vec!["initialize", "textDocument/didOpen", "textDocument/inlineCompletion", "checkStatus"]
+        );
+    }
+
+    #[tokio::test]
+    async fn excluded_documents_never_reach_the_server() {
+        let (server, received) = scripted_server(Some("OK"));
+        initialize(&server).await;
+
+        let position = Position { line: 0, character: 0 };
+        for uri in ["file:///work/.env", "file:///my%20project/.env", "file:///work/.env.local"] {
+            let list = server
+                .get_completions(uri.to_string(), "SECRET=1".to_string(), position.clone(), 1, None, true)
+                .await
+                .unwrap();
+            assert!(list.items.is_empty(), "{}", uri);
+        }
+        assert_eq!(methods(&received), vec!["initialize"]);
+    }
+
+    #[test]
+    fn paths_are_decoded_before_matching() {
+        let config = CopilotConfig::default();
+        assert!(!config.allows_document("file:///a%20b/.env", "plaintext"));
+        assert!(!config.allows_document("file:///keys/server%2Epem", "plaintext"));
+        assert!(config.allows_document("file:///a%20b/main.rs", "rust"));
+        assert!(config.allows_document("untitled:buffer-1", "plaintext"));
+
+        assert_eq!(uri_path("file:///a%20b/%E2%9C%93.rs").as_deref(), Some("/a b/✓.rs"));
+        assert_eq!(uri_path("file:///100%/x%2").as_deref(), Some("/100%/x%2"));
+        assert_eq!(uri_path("untitled:buffer-1"), None);
+    }
+
+    // Each step is only reached when the ones before it find nothing
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn finds_the_server_in_order_of_preference() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("vuno-copilot-{}", uuid::Uuid::new_v4()));
+        let dir = |name: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        };
+        let (override_dir, on_path, npm, npx, empty) = (dir("override"), dir("bin"), dir("npm"), dir("npx"), dir("empty"));
+        let prefix = root.join("prefix");
+        let package = global_npm_candidates(&prefix).remove(0);
+        std::fs::create_dir_all(package.parent().unwrap()).unwrap();
+
+        for file in [override_dir.join("server"), on_path.join("copilot-language-server"), package.clone(), npx.join("npx")] {
+            std::fs::write(file, "").unwrap();
+        }
+        let npm_script = npm.join("npm");
+        std::fs::write(&npm_script, format!("#!/bin/sh\necho {}\n", prefix.display())).unwrap();
+        std::fs::set_permissions(&npm_script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let search = |dirs: &[&PathBuf]| std::env::join_paths(dirs).unwrap();
+        let everything = search(&[&on_path, &npm, &npx]);
+
+        let configured = override_dir.join("server").display().to_string();
+        let found = locate_server(Some(configured), &everything).await.unwrap();
+        assert_eq!(found.program, override_dir.join("server"));
+        let missing = override_dir.join("missing").display().to_string();
+        assert!(locate_server(Some(missing), &everything).await.unwrap_err().contains("Configured"));
+
+        let found = locate_server(None, &everything).await.unwrap();
+        assert_eq!(found.program, on_path.join("copilot-language-server"));
+
+        let found = locate_server(None, &search(&[&npm, &npx])).await.unwrap();
+        assert_eq!(found.program, package);
+
+        let found = locate_server(None, &search(&[&npx])).await.unwrap();
+        assert_eq!(found.program, npx.join("npx"));
+        assert_eq!(found.args, vec!["--yes", "@github/copilot-language-server"]);
+
+        let error = locate_server(None, &search(&[&empty])).await.unwrap_err();
+        let tried: Vec<&str> = error.lines().skip(1).map(str::trim).collect();
+        assert_eq!(tried.len(), 4);
+        assert_eq!(tried[0], "PATH (copilot-language-server)");
+        assert!(tried[1].starts_with("node_modules"));
+        assert_eq!(tried[2], "global npm prefix (npm not found or failed)");
+        assert!(tried[3].starts_with("npx --yes @github/copilot-language-server"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    // A real process that answers every request with an empty result, records what it was
+    // sent and exits with status 3 when told to
+    const CRASHING_SERVER: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+while read -r header; do
+  length=$(printf '%s' "$header" | tr -d '\r' | sed -n 's/^Content-Length: *//p')
+  read -r blank
+  body=$(dd bs=1 count="$length" 2>/dev/null)
+  printf '%s\n' "$body" >> "$dir/received"
+  case "$body" in *'"method":"exit"'*) exit 3 ;; esac
+  id=$(printf '%s' "$body" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  if [ -n "$id" ]; then
+    reply="{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{}}"
+    printf 'Content-Length: %s\r\n\r\n%s' "${#reply}" "$reply"
+  fi
+done
+"#;
+
+    async fn wait_for(server: &CopilotServer, what: &str, done: impl Fn(&CopilotStatus, &[String]) -> bool) {
+        for _ in 0..200 {
+            if done(&server.get_status().await, &server.get_log().await) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("timed out waiting for {}: {:?}", what, server.get_status().await.message);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restarts_a_crashed_server_with_backoff_and_reopens_documents() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("vuno-copilot-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let script = root.join("server");
+        std::fs::write(&script, CRASHING_SERVER).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let server = CopilotServer::new();
+        server.config.write().await.server_path = Some(script.display().to_string());
+        server.keep_running.store(true, Ordering::SeqCst);
+        server.launch().await.unwrap();
+        assert_eq!(server.get_status().await.status, "Normal");
+
+        server.documents.lock().await.insert(
+            "file:///work/main.rs".to_string(),
+            OpenDocument { version: 4, language_id: "rust".to_string(), text: "fn main() {}".to_string() },
+        );
+        server.rpc.notify("exit", serde_json::Value::Null).await.unwrap();
+
+        wait_for(&server, "the exit to be noticed", |status, _| status.status == "Error").await;
+        let status = server.get_status().await;
+        assert!(status.message.contains("exited unexpectedly"), "{}", status.message);
+        assert!(status.message.contains('3'), "{}", status.message);
+        let log = server.get_log().await;
+        assert!(log.iter().any(|line| line.contains("Restarting Copilot server in 1s (attempt 1)")), "{:?}", log);
+
+        wait_for(&server, "the restart", |status, _| status.status == "Normal").await;
+        // The stub logs what it reads on its own schedule, so wait for the sign-in check that follows the replay
+        let mut received = String::new();
+        for _ in 0..200 {
+            received = std::fs::read_to_string(root.join("received")).unwrap();
+            if received.matches("checkStatus").count() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let reopened: Vec<serde_json::Value> = received
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|message| message["method"] == "textDocument/didOpen")
+            .collect();
+        assert_eq!(reopened.len(), 1, "{}", received);
+        let document = &reopened[0]["params"]["textDocument"];
+        assert_eq!(document["uri"], "file:///work/main.rs");
+        assert_eq!(document["text"], "fn main() {}");
+        assert_eq!(document["languageId"], "rust");
+
+        // The attempt counter only resets after a stable minute, so a second crash waits longer
+        server.rpc.notify("exit", serde_json::Value::Null).await.unwrap();
+        wait_for(&server, "the second backoff", |_, log| {
+            log.iter().any(|line| line.contains("Restarting Copilot server in 2s (attempt 2)"))
+        })
+        .await;
+
+        server.stop().await.unwrap();
+        assert_eq!(server.get_status().await.status, "Inactive");
+        let _ = std::fs::remove_dir_all(root);
+    }
 }
@@ -0,0 +1,1174 @@
+// بسم الله الرحمن الرحيم
+// Debug Adapter Protocol client
+
+use crate::buffer::BufferManager;
+use crate::framing::{read_frame, write_frame};
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::{oneshot, Mutex};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+type AdapterReader = Box<dyn AsyncRead + Send + Unpin>;
+type AdapterWriter = Box<dyn AsyncWrite + Send + Unpin>;
+// Where adapter events go; the frontend in the app, a channel in tests
+type EventSink = Arc<dyn Fn(DapEvent) + Send + Sync>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugLaunchConfig {
+    pub adapter: String, // "lldb", "debugpy", "delve", "custom"
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub stop_on_entry: bool,
+    // Overrides the adapter executable, required for "custom"
+    pub adapter_command: Option<String>,
+    #[serde(default)]
+    pub adapter_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceBreakpoint {
+    pub line: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Breakpoint {
+    pub id: Option<i64>,
+    pub verified: bool,
+    pub line: Option<u32>,
+    pub message: Option<String>,
+}
+
+// What one session made of a file's breakpoints; a failing session doesn't hide the others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionBreakpoints {
+    pub session_id: String,
+    pub breakpoints: Vec<Breakpoint>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thread {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    pub name: Option<String>,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StackFrame {
+    pub id: i64,
+    pub name: String,
+    pub source: Option<Source>,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scope {
+    pub name: String,
+    pub variables_reference: i64,
+    #[serde(default)]
+    pub expensive: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Variable {
+    pub name: String,
+    pub value: String,
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+    #[serde(default)]
+    pub variables_reference: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugSessionInfo {
+    pub id: String,
+    pub adapter: String,
+    pub program: String,
+    pub state: String, // "initializing", "running", "stopped", "terminated"
+    pub stopped_thread_id: Option<i64>,
+    pub stop_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DapEvent {
+    pub session_id: String,
+    pub event: String,
+    pub body: serde_json::Value,
+}
+
+// How to launch and talk to a particular debug adapter
+struct AdapterSpec {
+    adapter_id: String,
+    command: String,
+    args: Vec<String>,
+    // Adapters such as delve only speak DAP over a socket they print on stdout
+    tcp: bool,
+}
+
+impl AdapterSpec {
+    fn resolve(config: &DebugLaunchConfig) -> Result<Self, String> {
+        let python = if cfg!(target_os = "windows") { "python" } else { "python3" };
+
+        let (adapter_id, command, args, tcp) = match config.adapter.as_str() {
+            "lldb" | "lldb-dap" => ("lldb-dap", "lldb-dap", vec![], false),
+            "debugpy" | "python" => ("debugpy", python, vec!["-m", "debugpy.adapter"], false),
+            "delve" | "go" => ("go", "dlv", vec!["dap", "--listen", "127.0.0.1:0"], true),
+            "custom" => ("custom", "", vec![], false),
+            other => return Err(format!("No debug adapter configured for: {}", other)),
+        };
+
+        let command = match &config.adapter_command {
+            Some(command) => command.clone(),
+            None if command.is_empty() => {
+                return Err("A custom debug adapter requires an adapter command".to_string())
+            }
+            None => command.to_string(),
+        };
+
+        let args = if config.adapter_args.is_empty() {
+            args.into_iter().map(String::from).collect()
+        } else {
+            config.adapter_args.clone()
+        };
+
+        Ok(Self {
+            adapter_id: adapter_id.to_string(),
+            command,
+            args,
+            tcp,
+        })
+    }
+
+    fn launch_arguments(&self, config: &DebugLaunchConfig) -> serde_json::Value {
+        let mut arguments = serde_json::json!({
+            "name": "vuno",
+            "type": self.adapter_id,
+            "request": "launch",
+            "program": config.program,
+            "args": config.args,
+            "env": config.env,
+            "stopOnEntry": config.stop_on_entry,
+        });
+
+        if let Some(cwd) = &config.cwd {
+            arguments["cwd"] = serde_json::json!(cwd);
+        }
+
+        match self.adapter_id.as_str() {
+            "debugpy" => arguments["console"] = serde_json::json!("internalConsole"),
+            "go" => arguments["mode"] = serde_json::json!("debug"),
+            _ => {}
+        }
+
+        arguments
+    }
+
+    async fn spawn(&self) -> Result<(Child, AdapterReader, AdapterWriter), String> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to start debug adapter '{}': {}", self.command, e))?;
+
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log::debug!("debug adapter stderr: {}", line);
+                }
+            });
+        }
+
+        let stdout = child.stdout.take().ok_or("Failed to get debug adapter stdout")?;
+
+        if self.tcp {
+            let address = wait_for_listen_address(stdout).await?;
+            let stream = TcpStream::connect(&address)
+                .await
+                .map_err(|e| format!("Failed to connect to debug adapter at {}: {}", address, e))?;
+            let (reader, writer) = stream.into_split();
+            let reader: AdapterReader = Box::new(reader);
+            let writer: AdapterWriter = Box::new(writer);
+            Ok((child, reader, writer))
+        } else {
+            let stdin = child.stdin.take().ok_or("Failed to get debug adapter stdin")?;
+            let reader: AdapterReader = Box::new(stdout);
+            let writer: AdapterWriter = Box::new(stdin);
+            Ok((child, reader, writer))
+        }
+    }
+}
+
+async fn wait_for_listen_address(stdout: ChildStdout) -> Result<String, String> {
+    let mut lines = BufReader::new(stdout).lines();
+
+    let address = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some((_, address)) = line.split_once("listening at:") {
+                return Some(address.trim().to_string());
+            }
+        }
+        None
+    })
+    .await
+    .map_err(|_| "Timed out waiting for debug adapter to listen".to_string())?
+    .ok_or_else(|| "Debug adapter exited before listening".to_string())?;
+
+    // Keep draining stdout so the adapter never blocks on a full pipe
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            log::debug!("debug adapter stdout: {}", line);
+        }
+    });
+
+    Ok(address)
+}
+
+fn parse_list<T: DeserializeOwned>(body: &serde_json::Value, field: &str) -> Result<Vec<T>, String> {
+    match body.get(field) {
+        Some(value) => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Failed to parse '{}' from debug adapter: {}", field, e)),
+        None => Ok(Vec::new()),
+    }
+}
+
+struct DebugSession {
+    info: RwLock<DebugSessionInfo>,
+    writer: Mutex<AdapterWriter>,
+    process: Mutex<Option<Child>>,
+    pending: parking_lot::Mutex<HashMap<i64, oneshot::Sender<serde_json::Value>>>,
+    initialized: parking_lot::Mutex<Option<oneshot::Sender<()>>>,
+    capabilities: RwLock<serde_json::Value>,
+    seq: AtomicI64,
+}
+
+impl DebugSession {
+    fn new(info: DebugSessionInfo, writer: AdapterWriter, process: Option<Child>) -> (Arc<Self>, oneshot::Receiver<()>) {
+        let (initialized_tx, initialized_rx) = oneshot::channel();
+        let session = Arc::new(Self {
+            info: RwLock::new(info),
+            writer: Mutex::new(writer),
+            process: Mutex::new(process),
+            pending: parking_lot::Mutex::new(HashMap::new()),
+            initialized: parking_lot::Mutex::new(Some(initialized_tx)),
+            capabilities: RwLock::new(serde_json::Value::Null),
+            seq: AtomicI64::new(1),
+        });
+        (session, initialized_rx)
+    }
+
+    fn id(&self) -> String {
+        self.info.read().id.clone()
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.info.read().state == "terminated"
+    }
+
+    async fn send<T: Serialize>(&self, message: &T) -> Result<(), String> {
+        let mut writer = self.writer.lock().await;
+        write_frame(&mut *writer, message).await
+    }
+
+    async fn send_request(
+        &self,
+        command: &str,
+        arguments: serde_json::Value,
+    ) -> Result<(i64, oneshot::Receiver<serde_json::Value>), String> {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().insert(seq, tx);
+
+        let request = serde_json::json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+
+        if let Err(e) = self.send(&request).await {
+            self.pending.lock().remove(&seq);
+            return Err(e);
+        }
+
+        Ok((seq, rx))
+    }
+
+    async fn await_response(
+        &self,
+        command: &str,
+        seq: i64,
+        rx: oneshot::Receiver<serde_json::Value>,
+        limit: Duration,
+    ) -> Result<serde_json::Value, String> {
+        let response = match tokio::time::timeout(limit, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => return Err("Debug adapter connection closed".to_string()),
+            Err(_) => {
+                self.pending.lock().remove(&seq);
+                return Err(format!("Debug adapter did not answer '{}' in time", command));
+            }
+        };
+
+        if response.get("success").and_then(|s| s.as_bool()).unwrap_or(false) {
+            return Ok(response.get("body").cloned().unwrap_or(serde_json::Value::Null));
+        }
+
+        let message = response
+            .get("body")
+            .and_then(|body| body.get("error"))
+            .and_then(|error| error.get("format"))
+            .and_then(|format| format.as_str())
+            .or_else(|| response.get("message").and_then(|m| m.as_str()))
+            .unwrap_or("unknown error");
+
+        Err(format!("Debug adapter rejected '{}': {}", command, message))
+    }
+
+    async fn request(&self, command: &str, arguments: serde_json::Value) -> Result<serde_json::Value, String> {
+        let (seq, rx) = self.send_request(command, arguments).await?;
+        self.await_response(command, seq, rx, REQUEST_TIMEOUT).await
+    }
+
+    async fn set_breakpoints(&self, path: &Path, breakpoints: &[SourceBreakpoint]) -> Result<Vec<Breakpoint>, String> {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string());
+        let body = self
+            .request(
+                "setBreakpoints",
+                serde_json::json!({
+                    "source": {
+                        "name": name,
+                        "path": path.to_string_lossy(),
+                    },
+                    "breakpoints": breakpoints,
+                    "sourceModified": false,
+                }),
+            )
+            .await?;
+
+        parse_list(&body, "breakpoints")
+    }
+
+    async fn handle_message(&self, message: serde_json::Value, sessions: &SessionMap, emit: &EventSink) {
+        match message.get("type").and_then(|t| t.as_str()) {
+            Some("response") => {
+                let request_seq = message.get("request_seq").and_then(|s| s.as_i64());
+                if let Some(tx) = request_seq.and_then(|seq| self.pending.lock().remove(&seq)) {
+                    let _ = tx.send(message);
+                }
+            }
+            Some("event") => {
+                let event = message
+                    .get("event")
+                    .and_then(|e| e.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                let body = message.get("body").cloned().unwrap_or(serde_json::Value::Null);
+
+                self.apply_event(&event, &body);
+                // Gone from the list before the webview hears of it
+                if event == "terminated" || event == "exited" {
+                    sessions.write().remove(&self.id());
+                }
+
+                emit(DapEvent {
+                    session_id: self.id(),
+                    event,
+                    body,
+                });
+            }
+            Some("request") => {
+                // Reverse requests (runInTerminal, startDebugging) are not supported yet
+                let response = serde_json::json!({
+                    "seq": self.seq.fetch_add(1, Ordering::SeqCst),
+                    "type": "response",
+                    "request_seq": message.get("seq"),
+                    "command": message.get("command"),
+                    "success": false,
+                    "message": "Not supported by vuno",
+                });
+                let _ = self.send(&response).await;
+            }
+            _ => log::warn!("Ignoring malformed debug adapter message: {}", message),
+        }
+    }
+
+    fn apply_event(&self, event: &str, body: &serde_json::Value) {
+        match event {
+            "initialized" => {
+                if let Some(tx) = self.initialized.lock().take() {
+                    let _ = tx.send(());
+                }
+            }
+            "stopped" => {
+                let mut info = self.info.write();
+                info.state = "stopped".to_string();
+                info.stopped_thread_id = body.get("threadId").and_then(|t| t.as_i64());
+                info.stop_reason = body.get("reason").and_then(|r| r.as_str()).map(String::from);
+            }
+            "continued" => self.mark_running(),
+            "terminated" | "exited" => {
+                self.info.write().state = "terminated".to_string();
+            }
+            _ => {}
+        }
+    }
+
+    fn mark_running(&self) {
+        let mut info = self.info.write();
+        if info.state != "terminated" {
+            info.state = "running".to_string();
+            info.stopped_thread_id = None;
+            info.stop_reason = None;
+        }
+    }
+
+    fn mark_terminated(&self, sessions: &SessionMap, emit: &EventSink) {
+        sessions.write().remove(&self.id());
+        let was_terminated = {
+            let mut info = self.info.write();
+            let was_terminated = info.state == "terminated";
+            info.state = "terminated".to_string();
+            was_terminated
+        };
+
+        // Dropping the senders fails every request still waiting on the adapter
+        self.pending.lock().clear();
+
+        if !was_terminated {
+            emit(DapEvent {
+                session_id: self.id(),
+                event: "terminated".to_string(),
+                body: serde_json::Value::Null,
+            });
+        }
+    }
+
+    async fn shutdown(&self) {
+        if !self.is_terminated() {
+            let _ = self
+                .request("disconnect", serde_json::json!({ "terminateDebuggee": true }))
+                .await;
+        }
+
+        if let Some(mut child) = self.process.lock().await.take() {
+            let _ = child.kill().await;
+        }
+
+        self.info.write().state = "terminated".to_string();
+        self.pending.lock().clear();
+    }
+}
+
+fn spawn_reader(session: Arc<DebugSession>, reader: AdapterReader, sessions: SessionMap, emit: EventSink) {
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(Some(message)) => session.handle_message(message, &sessions, &emit).await,
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Debug adapter stream error: {}", e);
+                    break;
+                }
+            }
+        }
+        session.mark_terminated(&sessions, &emit);
+    });
+}
+
+// Shared with each session's reader, which drops the session once the adapter ends it
+type SessionMap = Arc<RwLock<HashMap<String, Arc<DebugSession>>>>;
+
+pub struct DapManager {
+    sessions: SessionMap,
+    // Breakpoints are kept per buffer path so they survive across sessions
+    breakpoints: RwLock<HashMap<PathBuf, Vec<SourceBreakpoint>>>,
+}
+
+impl DapManager {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            breakpoints: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn session(&self, session_id: &str) -> Result<Arc<DebugSession>, String> {
+        self.sessions
+            .read()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| format!("Debug session {} not found", session_id))
+    }
+
+    pub async fn start_session(&self, config: DebugLaunchConfig, app_handle: AppHandle) -> Result<String, String> {
+        let spec = AdapterSpec::resolve(&config)?;
+        let (child, reader, writer) = spec.spawn().await?;
+        let emit: EventSink = Arc::new(move |event| {
+            let _ = app_handle.emit_all("dap_event", event);
+        });
+        self.open_session(&spec, &config, Some(child), reader, writer, emit).await
+    }
+
+    // Everything after the adapter is running, whatever transport it's reached over
+    async fn open_session(
+        &self,
+        spec: &AdapterSpec,
+        config: &DebugLaunchConfig,
+        process: Option<Child>,
+        reader: AdapterReader,
+        writer: AdapterWriter,
+        emit: EventSink,
+    ) -> Result<String, String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let info = DebugSessionInfo {
+            id: id.clone(),
+            adapter: spec.adapter_id.clone(),
+            program: config.program.clone(),
+            state: "initializing".to_string(),
+            stopped_thread_id: None,
+            stop_reason: None,
+        };
+        let (session, initialized_rx) = DebugSession::new(info, writer, process);
+
+        spawn_reader(session.clone(), reader, self.sessions.clone(), emit);
+        self.sessions.write().insert(id.clone(), session.clone());
+
+        if let Err(e) = self.configure_session(&session, spec, config, initialized_rx).await {
+            self.sessions.write().remove(&id);
+            session.shutdown().await;
+            return Err(e);
+        }
+
+        Ok(id)
+    }
+
+    async fn configure_session(
+        &self,
+        session: &DebugSession,
+        spec: &AdapterSpec,
+        config: &DebugLaunchConfig,
+        initialized_rx: oneshot::Receiver<()>,
+    ) -> Result<(), String> {
+        let capabilities = session
+            .request(
+                "initialize",
+                serde_json::json!({
+                    "clientID": "vuno",
+                    "clientName": "Vuno",
+                    "adapterID": spec.adapter_id,
+                    "pathFormat": "path",
+                    "linesStartAt1": true,
+                    "columnsStartAt1": true,
+                    "supportsVariableType": true,
+                    "supportsRunInTerminalRequest": false,
+                }),
+            )
+            .await?;
+        *session.capabilities.write() = capabilities;
+
+        // Some adapters only answer launch after configurationDone, so don't wait for it yet
+        let (launch_seq, launch_rx) = session
+            .send_request("launch", spec.launch_arguments(config))
+            .await?;
+
+        match tokio::time::timeout(LAUNCH_TIMEOUT, initialized_rx).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return Err("Debug adapter exited before initializing".to_string()),
+            Err(_) => return Err("Debug adapter never sent the initialized event".to_string()),
+        }
+
+        let breakpoints = self.breakpoints.read().clone();
+        for (path, source_breakpoints) in breakpoints {
+            if let Err(e) = session.set_breakpoints(&path, &source_breakpoints).await {
+                log::warn!("Failed to set breakpoints for {}: {}", path.display(), e);
+            }
+        }
+
+        let supports_configuration_done = session
+            .capabilities
+            .read()
+            .get("supportsConfigurationDoneRequest")
+            .and_then(|s| s.as_bool())
+            .unwrap_or(false);
+        if supports_configuration_done {
+            session.request("configurationDone", serde_json::json!({})).await?;
+        }
+
+        session
+            .await_response("launch", launch_seq, launch_rx, LAUNCH_TIMEOUT)
+            .await?;
+
+        let mut info = session.info.write();
+        if info.state == "initializing" {
+            info.state = "running".to_string();
+        }
+
+        Ok(())
+    }
+
+    pub async fn stop_session(&self, session_id: &str) -> Result<(), String> {
+        let session = self
+            .sessions
+            .write()
+            .remove(session_id)
+            .ok_or_else(|| format!("Debug session {} not found", session_id))?;
+
+        session.shutdown().await;
+        Ok(())
+    }
+
+    pub fn list_sessions(&self) -> Vec<DebugSessionInfo> {
+        self.sessions
+            .read()
+            .values()
+            .map(|session| session.info.read().clone())
+            .collect()
+    }
+
+    pub fn get_breakpoints(&self, path: &Path) -> Vec<SourceBreakpoint> {
+        self.breakpoints.read().get(path).cloned().unwrap_or_default()
+    }
+
+    // Stored for sessions started later, and sent to each live session; without one, nothing can
+    // verify them yet and the list is empty
+    pub async fn set_breakpoints(&self, path: PathBuf, breakpoints: Vec<SourceBreakpoint>) -> Vec<SessionBreakpoints> {
+        {
+            let mut stored = self.breakpoints.write();
+            if breakpoints.is_empty() {
+                stored.remove(&path);
+            } else {
+                stored.insert(path.clone(), breakpoints.clone());
+            }
+        }
+
+        let sessions: Vec<Arc<DebugSession>> = self
+            .sessions
+            .read()
+            .values()
+            .filter(|session| !session.is_terminated())
+            .cloned()
+            .collect();
+
+        let mut results = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            let (breakpoints, error) = match session.set_breakpoints(&path, &breakpoints).await {
+                Ok(verified) => (verified, None),
+                Err(e) => (Vec::new(), Some(e)),
+            };
+            results.push(SessionBreakpoints {
+                session_id: session.id(),
+                breakpoints,
+                error,
+            });
+        }
+        results
+    }
+
+    pub async fn control(&self, session_id: &str, command: &str, thread_id: i64) -> Result<(), String> {
+        let session = self.session(session_id)?;
+        if command == "pause" {
+            return session
+                .request(command, serde_json::json!({ "threadId": thread_id }))
+                .await
+                .map(|_| ());
+        }
+
+        // Running from before the request goes out, so a stopped event that beats the response
+        // isn't overwritten by it
+        let previous = session.info.read().clone();
+        session.mark_running();
+        if let Err(e) = session
+            .request(command, serde_json::json!({ "threadId": thread_id }))
+            .await
+        {
+            let mut info = session.info.write();
+            if info.state == "running" {
+                info.state = previous.state;
+                info.stopped_thread_id = previous.stopped_thread_id;
+                info.stop_reason = previous.stop_reason;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn threads(&self, session_id: &str) -> Result<Vec<Thread>, String> {
+        let session = self.session(session_id)?;
+        let body = session.request("threads", serde_json::json!({})).await?;
+        parse_list(&body, "threads")
+    }
+
+    pub async fn stack_trace(&self, session_id: &str, thread_id: i64) -> Result<Vec<StackFrame>, String> {
+        let session = self.session(session_id)?;
+        let body = session
+            .request("stackTrace", serde_json::json!({ "threadId": thread_id }))
+            .await?;
+        parse_list(&body, "stackFrames")
+    }
+
+    pub async fn scopes(&self, session_id: &str, frame_id: i64) -> Result<Vec<Scope>, String> {
+        let session = self.session(session_id)?;
+        let body = session
+            .request("scopes", serde_json::json!({ "frameId": frame_id }))
+            .await?;
+        parse_list(&body, "scopes")
+    }
+
+    pub async fn variables(&self, session_id: &str, variables_reference: i64) -> Result<Vec<Variable>, String> {
+        let session = self.session(session_id)?;
+        let body = session
+            .request(
+                "variables",
+                serde_json::json!({ "variablesReference": variables_reference }),
+            )
+            .await?;
+        parse_list(&body, "variables")
+    }
+
+    pub async fn evaluate(&self, session_id: &str, expression: &str, frame_id: Option<i64>) -> Result<Variable, String> {
+        let session = self.session(session_id)?;
+        let mut arguments = serde_json::json!({
+            "expression": expression,
+            "context": "repl",
+        });
+        // Adapters take a missing frameId as the global scope, but reject a null one
+        if let Some(frame_id) = frame_id {
+            arguments["frameId"] = frame_id.into();
+        }
+        let body = session.request("evaluate", arguments).await?;
+
+        Ok(Variable {
+            name: expression.to_string(),
+            value: body.get("result").and_then(|r| r.as_str()).unwrap_or_default().to_string(),
+            type_name: body.get("type").and_then(|t| t.as_str()).map(String::from),
+            variables_reference: body.get("variablesReference").and_then(|v| v.as_i64()).unwrap_or(0),
+        })
+    }
+}
+
+fn buffer_path(buffer_manager: &BufferManager, buffer_id: usize) -> Result<PathBuf, String> {
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    buffer
+        .path
+        .ok_or_else(|| format!("Buffer {} has no file path", buffer_id))
+}
+
+// Tauri commands
+#[tauri::command]
+pub async fn dap_start_session(
+    config: DebugLaunchConfig,
+    app_handle: AppHandle,
+    dap_manager: tauri::State<'_, DapManager>,
+) -> Result<String, String> {
+    dap_manager.start_session(config, app_handle).await
+}
+
+#[tauri::command]
+pub async fn dap_stop_session(session_id: String, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.stop_session(&session_id).await
+}
+
+#[tauri::command]
+pub fn dap_list_sessions(dap_manager: tauri::State<'_, DapManager>) -> Result<Vec<DebugSessionInfo>, String> {
+    Ok(dap_manager.list_sessions())
+}
+
+#[tauri::command]
+pub async fn dap_set_breakpoints(
+    buffer_id: usize,
+    breakpoints: Vec<SourceBreakpoint>,
+    dap_manager: tauri::State<'_, DapManager>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<Vec<SessionBreakpoints>, String> {
+    let path = buffer_path(&buffer_manager, buffer_id)?;
+    Ok(dap_manager.set_breakpoints(path, breakpoints).await)
+}
+
+#[tauri::command]
+pub fn dap_get_breakpoints(
+    buffer_id: usize,
+    dap_manager: tauri::State<'_, DapManager>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<Vec<SourceBreakpoint>, String> {
+    let path = buffer_path(&buffer_manager, buffer_id)?;
+    Ok(dap_manager.get_breakpoints(&path))
+}
+
+#[tauri::command]
+pub async fn dap_continue(session_id: String, thread_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.control(&session_id, "continue", thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_next(session_id: String, thread_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.control(&session_id, "next", thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_step_in(session_id: String, thread_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.control(&session_id, "stepIn", thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_step_out(session_id: String, thread_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.control(&session_id, "stepOut", thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_pause(session_id: String, thread_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<(), String> {
+    dap_manager.control(&session_id, "pause", thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_threads(session_id: String, dap_manager: tauri::State<'_, DapManager>) -> Result<Vec<Thread>, String> {
+    dap_manager.threads(&session_id).await
+}
+
+#[tauri::command]
+pub async fn dap_stack_trace(
+    session_id: String,
+    thread_id: i64,
+    dap_manager: tauri::State<'_, DapManager>,
+) -> Result<Vec<StackFrame>, String> {
+    dap_manager.stack_trace(&session_id, thread_id).await
+}
+
+#[tauri::command]
+pub async fn dap_scopes(session_id: String, frame_id: i64, dap_manager: tauri::State<'_, DapManager>) -> Result<Vec<Scope>, String> {
+    dap_manager.scopes(&session_id, frame_id).await
+}
+
+#[tauri::command]
+pub async fn dap_variables(
+    session_id: String,
+    variables_reference: i64,
+    dap_manager: tauri::State<'_, DapManager>,
+) -> Result<Vec<Variable>, String> {
+    dap_manager.variables(&session_id, variables_reference).await
+}
+
+#[tauri::command]
+pub async fn dap_evaluate(
+    session_id: String,
+    expression: String,
+    frame_id: Option<i64>,
+    dap_manager: tauri::State<'_, DapManager>,
+) -> Result<Variable, String> {
+    dap_manager.evaluate(&session_id, &expression, frame_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, split, AsyncWrite};
+    use tokio::sync::mpsc;
+
+    async fn reply<W: AsyncWrite + Unpin>(writer: &mut W, seq: &mut i64, request: &serde_json::Value, success: bool, body: serde_json::Value) {
+        *seq += 1;
+        let response = serde_json::json!({
+            "seq": *seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": success,
+            "message": if success { serde_json::Value::Null } else { "rejected by the fake adapter".into() },
+            "body": body,
+        });
+        write_frame(writer, &response).await.unwrap();
+    }
+
+    async fn event<W: AsyncWrite + Unpin>(writer: &mut W, seq: &mut i64, event: &str, body: serde_json::Value) {
+        *seq += 1;
+        let message = serde_json::json!({ "seq": *seq, "type": "event", "event": event, "body": body });
+        write_frame(writer, &message).await.unwrap();
+    }
+
+    // Plays an adapter over an in-memory pipe, failing the commands in `reject`. Like debugpy, it
+    // sends initialized on launch and only answers launch once configuration is done.
+    fn fake_adapter(reject: &'static [&'static str]) -> (AdapterReader, AdapterWriter) {
+        let (client, adapter) = duplex(4096);
+        let (client_reader, client_writer) = split(client);
+
+        tokio::spawn(async move {
+            let (reader, mut writer) = split(adapter);
+            let mut reader = BufReader::new(reader);
+            let mut seq = 0;
+            let mut launch = None;
+
+            while let Ok(Some(request)) = read_frame(&mut reader).await {
+                let command = request["command"].as_str().unwrap_or_default().to_string();
+                let success = !reject.contains(&command.as_str());
+                match command.as_str() {
+                    "initialize" => {
+                        let body = serde_json::json!({ "supportsConfigurationDoneRequest": true });
+                        reply(&mut writer, &mut seq, &request, success, body).await;
+                    }
+                    "launch" => {
+                        launch = Some(request);
+                        event(&mut writer, &mut seq, "initialized", serde_json::Value::Null).await;
+                    }
+                    "setBreakpoints" => {
+                        let breakpoints: Vec<serde_json::Value> = request["arguments"]["breakpoints"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .enumerate()
+                            .map(|(id, bp)| serde_json::json!({ "id": id, "verified": true, "line": bp["line"] }))
+                            .collect();
+                        let body = serde_json::json!({ "breakpoints": breakpoints });
+                        reply(&mut writer, &mut seq, &request, success, body).await;
+                    }
+                    "configurationDone" => {
+                        reply(&mut writer, &mut seq, &request, success, serde_json::Value::Null).await;
+                        if let Some(launch) = launch.take() {
+                            reply(&mut writer, &mut seq, &launch, true, serde_json::Value::Null).await;
+                        }
+                        let body = serde_json::json!({ "reason": "entry", "threadId": 1 });
+                        event(&mut writer, &mut seq, "stopped", body).await;
+                    }
+                    "threads" => {
+                        let body = serde_json::json!({ "threads": [{ "id": 1, "name": "MainThread" }] });
+                        reply(&mut writer, &mut seq, &request, success, body).await;
+                    }
+                    // Hits a breakpoint before it gets round to answering
+                    "continue" if success => {
+                        let body = serde_json::json!({ "reason": "breakpoint", "threadId": 1 });
+                        event(&mut writer, &mut seq, "stopped", body).await;
+                        reply(&mut writer, &mut seq, &request, success, serde_json::Value::Null).await;
+                    }
+                    "evaluate" => {
+                        let arguments = &request["arguments"];
+                        let result = match arguments.get("frameId") {
+                            Some(frame_id) => format!("frame {}", frame_id),
+                            None => "global".to_string(),
+                        };
+                        let body = serde_json::json!({ "result": result, "variablesReference": 0 });
+                        reply(&mut writer, &mut seq, &request, success, body).await;
+                    }
+                    "disconnect" | "terminate" => {
+                        reply(&mut writer, &mut seq, &request, success, serde_json::Value::Null).await;
+                        event(&mut writer, &mut seq, "terminated", serde_json::Value::Null).await;
+                        break;
+                    }
+                    _ => reply(&mut writer, &mut seq, &request, success, serde_json::Value::Null).await,
+                }
+            }
+        });
+
+        (Box::new(client_reader), Box::new(client_writer))
+    }
+
+    fn spec() -> AdapterSpec {
+        AdapterSpec {
+            adapter_id: "custom".to_string(),
+            command: "fake-adapter".to_string(),
+            args: Vec::new(),
+            tcp: false,
+        }
+    }
+
+    fn config() -> DebugLaunchConfig {
+        DebugLaunchConfig {
+            adapter: "custom".to_string(),
+            program: "main.py".to_string(),
+            args: Vec::new(),
+            cwd: None,
+            env: HashMap::new(),
+            stop_on_entry: true,
+            adapter_command: Some("fake-adapter".to_string()),
+            adapter_args: Vec::new(),
+        }
+    }
+
+    fn breakpoint(line: u32) -> SourceBreakpoint {
+        SourceBreakpoint {
+            line,
+            condition: None,
+            hit_condition: None,
+            log_message: None,
+        }
+    }
+
+    async fn open(manager: &DapManager, reject: &'static [&'static str]) -> (String, mpsc::UnboundedReceiver<DapEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let emit: EventSink = Arc::new(move |event| {
+            let _ = tx.send(event);
+        });
+        let (reader, writer) = fake_adapter(reject);
+        let id = manager.open_session(&spec(), &config(), None, reader, writer, emit).await.unwrap();
+        (id, rx)
+    }
+
+    async fn wait_for(events: &mut mpsc::UnboundedReceiver<DapEvent>, name: &str) -> DapEvent {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .expect("adapter event timed out")
+                .expect("event channel closed");
+            if event.event == name {
+                return event;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_a_session_against_an_adapter() {
+        let manager = DapManager::new();
+        let path = PathBuf::from("/project/main.py");
+
+        // Nothing to verify against yet, but the breakpoints are kept for the session
+        assert!(manager.set_breakpoints(path.clone(), vec![breakpoint(3)]).await.is_empty());
+
+        let (id, mut events) = open(&manager, &[]).await;
+        let stopped = wait_for(&mut events, "stopped").await;
+        assert_eq!(stopped.session_id, id);
+
+        let info = manager.list_sessions().into_iter().find(|info| info.id == id).unwrap();
+        assert_eq!(info.state, "stopped");
+        assert_eq!(info.stopped_thread_id, Some(1));
+        assert_eq!(info.stop_reason.as_deref(), Some("entry"));
+
+        let threads = manager.threads(&id).await.unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].name, "MainThread");
+
+        let results = manager.set_breakpoints(path.clone(), vec![breakpoint(3), breakpoint(7)]).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].session_id, id);
+        assert!(results[0].error.is_none());
+        let lines: Vec<Option<u32>> = results[0].breakpoints.iter().map(|bp| bp.line).collect();
+        assert_eq!(lines, vec![Some(3), Some(7)]);
+        assert!(results[0].breakpoints.iter().all(|bp| bp.verified));
+        assert_eq!(manager.get_breakpoints(&path).len(), 2);
+
+        manager.stop_session(&id).await.unwrap();
+        assert!(manager.list_sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_failing_session_does_not_hide_the_others() {
+        let manager = DapManager::new();
+        let (healthy, _healthy_events) = open(&manager, &[]).await;
+        let (failing, _failing_events) = open(&manager, &["setBreakpoints"]).await;
+
+        let results = manager.set_breakpoints(PathBuf::from("/project/main.py"), vec![breakpoint(5)]).await;
+        assert_eq!(results.len(), 2);
+
+        let healthy = results.iter().find(|result| result.session_id == healthy).unwrap();
+        assert!(healthy.error.is_none());
+        assert_eq!(healthy.breakpoints.len(), 1);
+
+        let failing = results.iter().find(|result| result.session_id == failing).unwrap();
+        assert!(failing.error.as_deref().unwrap().contains("rejected by the fake adapter"));
+        assert!(failing.breakpoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_stop_that_beats_the_continue_response_is_kept() {
+        let manager = DapManager::new();
+        let (id, mut events) = open(&manager, &[]).await;
+        wait_for(&mut events, "stopped").await;
+
+        manager.control(&id, "continue", 1).await.unwrap();
+        let info = manager.list_sessions().into_iter().find(|info| info.id == id).unwrap();
+        assert_eq!(info.state, "stopped");
+        assert_eq!(info.stop_reason.as_deref(), Some("breakpoint"));
+    }
+
+    #[tokio::test]
+    async fn a_rejected_step_leaves_the_session_stopped() {
+        let manager = DapManager::new();
+        let (id, mut events) = open(&manager, &["next"]).await;
+        wait_for(&mut events, "stopped").await;
+
+        assert!(manager.control(&id, "next", 1).await.is_err());
+        let info = manager.list_sessions().into_iter().find(|info| info.id == id).unwrap();
+        assert_eq!(info.state, "stopped");
+        assert_eq!(info.stopped_thread_id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn evaluates_without_a_frame_in_the_global_scope() {
+        let manager = DapManager::new();
+        let (id, _events) = open(&manager, &[]).await;
+
+        assert_eq!(manager.evaluate(&id, "x", None).await.unwrap().value, "global");
+        assert_eq!(manager.evaluate(&id, "x", Some(4)).await.unwrap().value, "frame 4");
+    }
+
+    #[tokio::test]
+    async fn sessions_the_adapter_ends_are_pruned() {
+        let manager = DapManager::new();
+        let (id, mut events) = open(&manager, &[]).await;
+        let (other, _other_events) = open(&manager, &[]).await;
+
+        manager.session(&id).unwrap().request("terminate", serde_json::json!({})).await.unwrap();
+        wait_for(&mut events, "terminated").await;
+
+        let ids: Vec<String> = manager.list_sessions().into_iter().map(|info| info.id).collect();
+        assert_eq!(ids, vec![other]);
+        assert!(manager.threads(&id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_to_open_when_the_adapter_rejects_initialize() {
+        let manager = DapManager::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let emit: EventSink = Arc::new(move |event| {
+            let _ = tx.send(event);
+        });
+        let (reader, writer) = fake_adapter(&["initialize"]);
+
+        let error = manager.open_session(&spec(), &config(), None, reader, writer, emit).await.unwrap_err();
+        assert!(error.contains("initialize"));
+        assert!(manager.list_sessions().is_empty());
+    }
+}
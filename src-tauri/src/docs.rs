@@ -0,0 +1,729 @@
+// بسم الله الرحمن الرحيم
+// Offline documentation search over rustdoc JSON, man pages, devdocs archives and markdown files
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::citations::{clean_text, find_markers};
+use crate::perplexity::{PerplexitySearchResponse, SearchResult};
+
+// Longer documents are cut; their opening is what searches usually need
+const MAX_BODY_BYTES: usize = 32 * 1024;
+const SNIPPET_CHARS: usize = 240;
+pub const DEFAULT_LIMIT: usize = 10;
+// Matches in titles count this many times over matches in the body
+const TITLE_WEIGHT: u32 = 3;
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+static HTML_BLOCK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<(script|style)[^>]*>.*?</(script|style)>").unwrap());
+static HTML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+static ROFF_ESCAPE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\f[BIRP1-4]|\\f\(..|\\\(..|\\[&|^]").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DocSource {
+    // A rustdoc JSON file, or a directory of them such as target/doc
+    Rustdoc { path: PathBuf },
+    // A man directory holding man1, man2, ... subdirectories
+    ManPages { path: PathBuf },
+    // An extracted devdocs doc set with index.json and db.json
+    Devdocs { path: PathBuf },
+    // Every .md file under a directory, split at headings
+    Markdown { path: PathBuf },
+}
+
+impl DocSource {
+    fn path(&self) -> &Path {
+        match self {
+            DocSource::Rustdoc { path }
+            | DocSource::ManPages { path }
+            | DocSource::Devdocs { path }
+            | DocSource::Markdown { path } => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DocEntry {
+    title: String,
+    // Where the entry can be opened: a file path with a #fragment, or a man:/rustdoc: reference
+    url: String,
+    body: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IndexFile {
+    sources: Vec<DocSource>,
+    docs: Vec<DocEntry>,
+    built_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocsStatus {
+    pub sources: Vec<DocSource>,
+    pub documents: usize,
+    pub built_at: Option<DateTime<Utc>>,
+}
+
+// Term frequencies per document, with title hits already weighted
+#[derive(Default)]
+struct Postings {
+    terms: HashMap<String, Vec<(u32, u32)>>,
+    lengths: Vec<u32>,
+    average_length: f64,
+}
+
+impl Postings {
+    fn build(docs: &[DocEntry]) -> Self {
+        let mut terms: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+        let mut lengths = Vec::with_capacity(docs.len());
+
+        for (id, doc) in docs.iter().enumerate() {
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            let mut length = 0;
+            for (text, weight) in [(&doc.title, TITLE_WEIGHT), (&doc.body, 1)] {
+                for token in tokenize(text) {
+                    *counts.entry(token).or_default() += weight;
+                    length += weight;
+                }
+            }
+            for (term, count) in counts {
+                terms.entry(term).or_default().push((id as u32, count));
+            }
+            lengths.push(length);
+        }
+
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().map(|&l| l as f64).sum::<f64>() / lengths.len() as f64
+        };
+        Self {
+            terms,
+            lengths,
+            average_length,
+        }
+    }
+
+    // BM25 over the query terms, best first
+    fn search(&self, query: &str, limit: usize) -> Vec<(usize, f64)> {
+        let total = self.lengths.len() as f64;
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+        for term in query_terms {
+            let Some(postings) = self.terms.get(&term) else {
+                continue;
+            };
+            let matching = postings.len() as f64;
+            let idf = (1.0 + (total - matching + 0.5) / (matching + 0.5)).ln();
+            for &(doc, count) in postings {
+                let count = count as f64;
+                let length = self.lengths[doc as usize] as f64;
+                let norm = 1.0 - BM25_B + BM25_B * length / self.average_length.max(1.0);
+                *scores.entry(doc).or_default() += idf * count * (BM25_K1 + 1.0) / (count + BM25_K1 * norm);
+            }
+        }
+
+        let mut ranked: Vec<(usize, f64)> = scores.into_iter().map(|(doc, score)| (doc as usize, score)).collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|token| token.chars().count() >= 2)
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+pub struct DocsIndex {
+    file: RwLock<IndexFile>,
+    postings: RwLock<Postings>,
+    index_path: PathBuf,
+    // Set once the saved index has been read; see load
+    loaded: OnceCell<()>,
+}
+
+impl DocsIndex {
+    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
+
+        Self {
+            file: RwLock::new(IndexFile::default()),
+            postings: RwLock::new(Postings::default()),
+            index_path: app_dir.join("docs_index.json"),
+            loaded: OnceCell::new(),
+        }
+    }
+
+    // Read the saved documents and rebuild their postings, which can take a while for a large index.
+    // Setup starts this in the background; anything needing the index waits for it here.
+    pub fn load(&self) {
+        self.loaded.get_or_init(|| {
+            let file: IndexFile = fs::read_to_string(&self.index_path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default();
+            *self.postings.write() = Postings::build(&file.docs);
+            *self.file.write() = file;
+        });
+    }
+
+    pub fn status(&self) -> DocsStatus {
+        self.load();
+        let file = self.file.read();
+        DocsStatus {
+            sources: file.sources.clone(),
+            documents: file.docs.len(),
+            built_at: file.built_at,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.load();
+        self.file.read().docs.is_empty()
+    }
+
+    pub fn set_sources(&self, sources: Vec<DocSource>) -> Result<(), String> {
+        // Saving before the documents are in would write over them
+        self.load();
+        let mut file = self.file.write();
+        file.sources = sources;
+        self.save(&file)
+    }
+
+    // Re-read every source; sources that fail are logged and skipped
+    pub fn rebuild(&self) -> Result<DocsStatus, String> {
+        self.load();
+        let sources = self.file.read().sources.clone();
+        let mut docs = Vec::new();
+        for source in &sources {
+            let before = docs.len();
+            if let Err(e) = read_source(source, &mut docs) {
+                log::warn!("Failed to index {}: {}", source.path().display(), e);
+            }
+            log::info!("Indexed {} entries from {}", docs.len() - before, source.path().display());
+        }
+
+        let postings = Postings::build(&docs);
+        {
+            // Swap both under the file lock so a search never pairs new documents with old postings
+            let mut file = self.file.write();
+            file.docs = docs;
+            file.built_at = Some(Utc::now());
+            *self.postings.write() = postings;
+            self.save(&file)?;
+        }
+        Ok(self.status())
+    }
+
+    // Results in the same shape as a web search, with an answer listing the best matches
+    pub fn search(&self, query: &str, limit: usize) -> PerplexitySearchResponse {
+        self.load();
+        let file = self.file.read();
+        let hits = self.postings.read().search(query, limit);
+        let terms = tokenize(query);
+
+        let results: Vec<SearchResult> = hits
+            .iter()
+            .filter_map(|&(doc, _)| file.docs.get(doc))
+            .map(|entry| {
+                SearchResult {
+                    title: entry.title.clone(),
+                    url: entry.url.clone(),
+                    snippet: snippet(&entry.body, &terms),
+                    date: None,
+                }
+            })
+            .collect();
+
+        let answer = if results.is_empty() {
+            format!("No local documentation matches \"{}\".", query.trim())
+        } else {
+            results
+                .iter()
+                .enumerate()
+                .map(|(index, result)| format!("- **{}**: {} [{}]", result.title, result.snippet, index + 1))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        PerplexitySearchResponse {
+            id: uuid::Uuid::new_v4().to_string(),
+            citation_markers: find_markers(&answer, results.len()),
+            answer,
+            results,
+            ..Default::default()
+        }
+    }
+
+    fn save(&self, file: &IndexFile) -> Result<(), String> {
+        let content = serde_json::to_string(file)
+            .map_err(|e| format!("Failed to serialize docs index: {}", e))?;
+        fs::write(&self.index_path, content).map_err(|e| format!("Failed to save docs index: {}", e))
+    }
+}
+
+// Text around the first query term found, on char boundaries
+fn snippet(body: &str, terms: &[String]) -> String {
+    let lower = body.to_lowercase();
+    // Lowercasing can shift byte offsets for some scripts; fall back to the start then
+    let found = terms
+        .iter()
+        .filter_map(|term| lower.find(term.as_str()))
+        .min()
+        .filter(|&found| lower.len() == body.len() && body.is_char_boundary(found))
+        .unwrap_or(0);
+
+    let start = body[..found]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CHARS / 3)
+        .map_or(0, |(index, _)| index);
+    let text: String = body[start..].chars().take(SNIPPET_CHARS).collect();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let ellipsis = if start + text.len() < body.len() { "…" } else { "" };
+    format!("{}{}{}", if start > 0 { "…" } else { "" }, text, ellipsis)
+}
+
+fn truncate_body(mut body: String) -> String {
+    if body.len() > MAX_BODY_BYTES {
+        let mut end = MAX_BODY_BYTES;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        body.truncate(end);
+    }
+    body
+}
+
+fn read_source(source: &DocSource, docs: &mut Vec<DocEntry>) -> Result<(), String> {
+    match source {
+        DocSource::Rustdoc { path } => {
+            if path.is_dir() {
+                for file in files_with_extension(path, "json", 1) {
+                    read_rustdoc(&file, docs)?;
+                }
+                Ok(())
+            } else {
+                read_rustdoc(path, docs)
+            }
+        }
+        DocSource::ManPages { path } => read_man_pages(path, docs),
+        DocSource::Devdocs { path } => read_devdocs(path, docs),
+        DocSource::Markdown { path } => {
+            for file in files_with_extension(path, "md", usize::MAX) {
+                read_markdown(&file, docs);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Files under `dir` up to `depth` levels down, skipping hidden and build directories
+fn files_with_extension(dir: &Path, extension: &str, depth: usize) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            if depth > 1 && !name.starts_with('.') && name != "node_modules" && name != "target" {
+                files.extend(files_with_extension(&path, extension, depth - 1));
+            }
+        } else if path.extension().and_then(|e| e.to_str()) == Some(extension) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+fn read_rustdoc(path: &Path, docs: &mut Vec<DocEntry>) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let json: serde_json::Value =
+        serde_json::from_str(&content).map_err(|e| format!("{} isn't rustdoc JSON: {}", path.display(), e))?;
+    let (Some(index), Some(paths)) = (
+        json.get("index").and_then(|i| i.as_object()),
+        json.get("paths").and_then(|p| p.as_object()),
+    ) else {
+        return Err(format!("{} isn't rustdoc JSON", path.display()));
+    };
+
+    for (id, item) in index {
+        let Some(name) = item.get("name").and_then(|n| n.as_str()) else {
+            continue;
+        };
+        // The index also lists items from dependencies; those belong to their own crate's docs
+        if item.get("crate_id").and_then(|c| c.as_u64()).unwrap_or(0) != 0 {
+            continue;
+        }
+        let item_docs = item.get("docs").and_then(|d| d.as_str()).unwrap_or("");
+
+        let full_path = paths
+            .get(id)
+            .and_then(|p| p.get("path"))
+            .and_then(|p| p.as_array())
+            .map(|parts| parts.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>().join("::"))
+            .unwrap_or_else(|| name.to_string());
+        // Older formats have "kind"; newer ones name the kind as the single key of "inner"
+        let kind = item
+            .get("kind")
+            .and_then(|k| k.as_str())
+            .map(|k| k.to_string())
+            .or_else(|| {
+                item.get("inner")
+                    .and_then(|i| i.as_object())
+                    .and_then(|i| i.keys().next().cloned())
+            })
+            .unwrap_or_default();
+
+        docs.push(DocEntry {
+            title: if kind.is_empty() {
+                full_path.clone()
+            } else {
+                format!("{} {}", kind, full_path)
+            },
+            url: format!("rustdoc:{}", full_path),
+            body: truncate_body(item_docs.to_string()),
+        });
+    }
+    Ok(())
+}
+
+fn read_man_pages(dir: &Path, docs: &mut Vec<DocEntry>) -> Result<(), String> {
+    let sections = fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for section in sections.flatten() {
+        let section_name = section.file_name().to_string_lossy().to_string();
+        if !section_name.starts_with("man") || !section.path().is_dir() {
+            continue;
+        }
+        let Ok(pages) = fs::read_dir(section.path()) else {
+            continue;
+        };
+        for page in pages.flatten() {
+            let path = page.path();
+            let file_name = page.file_name().to_string_lossy().to_string();
+            let stem = file_name.trim_end_matches(".gz");
+            let Some((name, number)) = stem.rsplit_once('.') else {
+                continue;
+            };
+
+            let source = match read_maybe_gzipped(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::debug!("Skipping man page {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            // Pages that only include another page (".so man1/foo.1") add nothing
+            if source.trim_start().starts_with(".so ") {
+                continue;
+            }
+
+            docs.push(DocEntry {
+                title: format!("{}({})", name, number),
+                url: format!("man:{}({})", name, number),
+                body: truncate_body(strip_roff(&source)),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn read_maybe_gzipped(path: &Path) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    if path.extension().and_then(|e| e.to_str()) != Some("gz") {
+        return Ok(String::from_utf8_lossy(&bytes).to_string());
+    }
+    let mut text = String::new();
+    GzDecoder::new(&bytes[..])
+        .read_to_string(&mut text)
+        .map_err(|e| e.to_string())?;
+    Ok(text)
+}
+
+// Just enough roff to get readable text: keep the arguments of font and heading macros, drop the rest
+fn strip_roff(source: &str) -> String {
+    let mut text = String::new();
+    for line in source.lines() {
+        let line = if let Some(request) = line.strip_prefix('.').or_else(|| line.strip_prefix('\'')) {
+            let (name, args) = request.split_once(char::is_whitespace).unwrap_or((request, ""));
+            match name {
+                "SH" | "SS" | "B" | "I" | "BR" | "BI" | "IR" | "IB" | "RB" | "RI" | "IP" | "TP" | "Nm" | "Nd" | "Sh" => {
+                    args.trim().trim_matches('"').to_string()
+                }
+                _ => continue,
+            }
+        } else {
+            line.to_string()
+        };
+
+        let line = ROFF_ESCAPE.replace_all(&line, "");
+        let line = line.replace("\\-", "-").replace("\\e", "\\").replace("\\ ", " ");
+        if !line.trim().is_empty() {
+            text.push_str(line.trim());
+            text.push('\n');
+        }
+    }
+    text
+}
+
+fn read_devdocs(dir: &Path, docs: &mut Vec<DocEntry>) -> Result<(), String> {
+    let read_json = |name: &str| -> Result<serde_json::Value, String> {
+        let path = dir.join(name);
+        let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    };
+    let index = read_json("index.json")?;
+    let db = read_json("db.json")?;
+    let set_name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    // Many entries point into the same page, so each page is stripped once
+    let mut pages: HashMap<String, String> = HashMap::new();
+    let entries = index.get("entries").and_then(|e| e.as_array()).cloned().unwrap_or_default();
+    for entry in entries {
+        let (Some(name), Some(path)) = (
+            entry.get("name").and_then(|n| n.as_str()),
+            entry.get("path").and_then(|p| p.as_str()),
+        ) else {
+            continue;
+        };
+        let (page, fragment) = path.split_once('#').unwrap_or((path, ""));
+        let Some(html) = db.get(page).and_then(|h| h.as_str()) else {
+            continue;
+        };
+
+        // Start at the fragment's anchor when there is one, so entries on long pages get their own text
+        let body = if fragment.is_empty() {
+            pages
+                .entry(page.to_string())
+                .or_insert_with(|| truncate_body(strip_html(html)))
+                .clone()
+        } else {
+            let start = html.find(&format!("id=\"{}\"", fragment)).unwrap_or(0);
+            let start = html[..start].rfind('<').unwrap_or(0);
+            let mut end = (start + MAX_BODY_BYTES).min(html.len());
+            while !html.is_char_boundary(end) {
+                end -= 1;
+            }
+            truncate_body(strip_html(&html[start..end]))
+        };
+
+        let kind = entry.get("type").and_then(|t| t.as_str()).unwrap_or("");
+        docs.push(DocEntry {
+            title: if kind.is_empty() {
+                name.to_string()
+            } else {
+                format!("{} ({})", name, kind)
+            },
+            url: format!("devdocs:{}/{}", set_name, path),
+            body,
+        });
+    }
+    Ok(())
+}
+
+fn strip_html(html: &str) -> String {
+    let without_blocks = HTML_BLOCK.replace_all(html, " ");
+    let text = HTML_TAG.replace_all(&without_blocks, " ");
+    clean_text(&text)
+}
+
+// One entry per heading, so a long README doesn't drown out a focused section
+fn read_markdown(path: &Path, docs: &mut Vec<DocEntry>) {
+    let Ok(content) = fs::read_to_string(path) else {
+        return;
+    };
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut title = file_name.clone();
+    let mut line_number = 1;
+    let mut body = String::new();
+    let mut in_code = false;
+    let mut push = |title: &str, line_number: usize, body: &mut String| {
+        if !body.trim().is_empty() {
+            docs.push(DocEntry {
+                title: format!("{} — {}", title, file_name),
+                url: format!("{}#L{}", path.display(), line_number),
+                body: truncate_body(std::mem::take(body)),
+            });
+        }
+        body.clear();
+    };
+
+    for (index, line) in content.lines().enumerate() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        // "#include" or "#tag" at the start of a line isn't a heading; the hashes need a space after them
+        let heading = line.trim_start_matches('#');
+        let is_heading = line.len() - heading.len() <= 6 && heading.starts_with([' ', '\t']);
+        if !in_code && line.starts_with('#') && is_heading {
+            push(&title, line_number, &mut body);
+            title = heading.trim().to_string();
+            line_number = index + 1;
+            continue;
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+    push(&title, line_number, &mut body);
+}
+
+#[tauri::command]
+pub fn docs_status(docs: tauri::State<'_, Arc<DocsIndex>>) -> DocsStatus {
+    docs.status()
+}
+
+#[tauri::command]
+pub fn docs_set_sources(sources: Vec<DocSource>, docs: tauri::State<'_, Arc<DocsIndex>>) -> Result<(), String> {
+    docs.set_sources(sources)
+}
+
+#[tauri::command]
+pub async fn docs_reindex(docs: tauri::State<'_, Arc<DocsIndex>>) -> Result<DocsStatus, String> {
+    let docs = docs.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || docs.rebuild())
+        .await
+        .map_err(|e| format!("Indexing failed: {}", e))?
+}
+
+#[tauri::command]
+pub fn search_docs(
+    query: String,
+    limit: Option<usize>,
+    docs: tauri::State<'_, Arc<DocsIndex>>,
+) -> Result<PerplexitySearchResponse, String> {
+    if query.trim().is_empty() {
+        return Err("Search query is empty".to_string());
+    }
+    Ok(docs.search(&query, limit.unwrap_or(DEFAULT_LIMIT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, body: &str) -> DocEntry {
+        DocEntry {
+            title: title.to_string(),
+            url: format!("test:{}", title),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn tokenize_lowercases_and_drops_single_characters() {
+        assert_eq!(
+            tokenize("Vec::with_capacity(n) — Größe a 42"),
+            vec!["vec", "with_capacity", "größe", "42"]
+        );
+        assert!(tokenize("a + b").is_empty());
+    }
+
+    #[test]
+    fn search_ranks_title_matches_and_rarer_terms_higher() {
+        let docs = vec![
+            entry("HashMap", "A hash map implemented with quadratic probing."),
+            entry("Vec", "A contiguous growable array type. Unlike a hash map it keeps order."),
+            entry("BTreeMap", "An ordered map based on a B-Tree."),
+        ];
+        let postings = Postings::build(&docs);
+
+        let ranked: Vec<usize> = postings.search("hashmap", 10).into_iter().map(|(doc, _)| doc).collect();
+        assert_eq!(ranked, vec![0]);
+
+        // "hash" appears in two bodies, but the shorter document about it ranks first
+        let ranked: Vec<usize> = postings.search("hash map", 10).into_iter().map(|(doc, _)| doc).collect();
+        assert_eq!(ranked[0], 0);
+        assert_eq!(ranked.len(), 3);
+
+        assert_eq!(postings.search("hash map", 1).len(), 1);
+        assert!(postings.search("nothing here", 10).is_empty());
+    }
+
+    #[test]
+    fn strip_roff_keeps_text_and_drops_requests() {
+        let source = ".TH LS 1\n.SH NAME\nls \\- list directory contents\n.SH SYNOPSIS\n\\fBls\\fR [\\fIOPTION\\fR]...\n.br\n.B \"-a\"\n";
+        assert_eq!(strip_roff(source), "NAME\nls - list directory contents\nSYNOPSIS\nls [OPTION]...\n-a\n");
+    }
+
+    #[test]
+    fn markdown_splits_only_at_real_headings() {
+        let dir = std::env::temp_dir().join(format!("vuno-docs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("guide.md");
+        fs::write(
+            &path,
+            "Intro text\n# Setup\nInstall it.\n#include <stdio.h>\n#hashtag\n```\n# not a heading\n```\n## Usage\nRun it.\n",
+        )
+        .unwrap();
+
+        let mut docs = Vec::new();
+        read_markdown(&path, &mut docs);
+        let _ = fs::remove_dir_all(&dir);
+
+        let titles: Vec<&str> = docs.iter().map(|doc| doc.title.as_str()).collect();
+        assert_eq!(titles, vec!["guide.md — guide.md", "Setup — guide.md", "Usage — guide.md"]);
+        assert!(docs[1].body.contains("#include <stdio.h>"));
+        assert!(docs[1].body.contains("# not a heading"));
+        assert!(docs[2].url.ends_with("guide.md#L9"));
+    }
+
+    #[test]
+    fn the_saved_index_is_read_on_first_use() {
+        let dir = std::env::temp_dir().join(format!("vuno-docs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let index_path = dir.join("docs_index.json");
+        let saved = IndexFile {
+            sources: vec![DocSource::Markdown { path: dir.clone() }],
+            docs: vec![entry("Setup", "Install the editor.")],
+            built_at: None,
+        };
+        fs::write(&index_path, serde_json::to_string(&saved).unwrap()).unwrap();
+
+        let index = DocsIndex {
+            file: RwLock::new(IndexFile::default()),
+            postings: RwLock::new(Postings::default()),
+            index_path: index_path.clone(),
+            loaded: OnceCell::new(),
+        };
+        // Setting sources first must not save over the documents that haven't been read yet
+        index.set_sources(saved.sources.clone()).unwrap();
+        assert_eq!(index.status().documents, 1);
+        assert_eq!(index.search("install", DEFAULT_LIMIT).results.len(), 1);
+
+        let reread: IndexFile = serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+        assert_eq!(reread.docs.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,141 @@
+// بسم الله الرحمن الرحيم
+// Content-Length framed transport shared by the debug adapter and Copilot clients
+
+use serde::Serialize;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Read a single framed JSON message. Returns `Ok(None)` once the peer closes the stream.
+pub async fn read_frame<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<serde_json::Value>, String> {
+    let mut content_length: Option<usize> = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| format!("Failed to read message header: {}", e))?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            // Blank line terminates the header block; ignore stray blank lines before it
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                let length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| format!("Invalid Content-Length header: {}", e))?;
+                content_length = Some(length);
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length.unwrap_or(0)];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| format!("Failed to read message body: {}", e))?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse message body: {}", e))
+}
+
+/// Serialize and write a single framed JSON message.
+pub async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+) -> Result<(), String> {
+    let content = serde_json::to_string(message)
+        .map_err(|e| format!("Failed to serialize message: {}", e))?;
+
+    let header = format!("Content-Length: {}\r\n\r\n", content.len());
+    let full_message = format!("{}{}", header, content);
+
+    writer
+        .write_all(full_message.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write message: {}", e))?;
+
+    writer
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to flush message: {}", e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, BufReader};
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[tokio::test]
+    async fn round_trips_a_message() {
+        let (mut writer, reader) = duplex(1024);
+        let mut reader = BufReader::new(reader);
+        let message = serde_json::json!({ "seq": 1, "type": "request", "command": "initialize" });
+
+        write_frame(&mut writer, &message).await.unwrap();
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(message));
+    }
+
+    #[tokio::test]
+    async fn reads_a_frame_split_across_writes() {
+        // A pipe this small hands the frame over a few bytes at a time, splitting the header too
+        let (mut writer, reader) = duplex(3);
+        let mut reader = BufReader::new(reader);
+        let message = serde_json::json!({ "text": "naïve café", "lines": [1, 2, 3] });
+
+        let sent = message.clone();
+        let writing = tokio::spawn(async move { write_frame(&mut writer, &sent).await });
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(message));
+        writing.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_frames_merged_into_one_chunk() {
+        let input = format!("{}{}", frame(r#"{"seq":1}"#), frame(r#"{"seq":2,"body":"é"}"#));
+        let mut reader = input.as_bytes();
+
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(serde_json::json!({ "seq": 1 })));
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap(),
+            Some(serde_json::json!({ "seq": 2, "body": "é" }))
+        );
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn skips_other_headers_and_stray_blank_lines() {
+        let input = "\r\ncontent-length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}";
+        let mut reader = input.as_bytes();
+
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(serde_json::json!({})));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_bad_length_or_truncated_body() {
+        let mut reader = "Content-Length: many\r\n\r\n{}".as_bytes();
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let mut reader = "Content-Length: 10\r\n\r\n{}".as_bytes();
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+}
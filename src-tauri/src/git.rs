@@ -0,0 +1,992 @@
+// بسم الله الرحمن الرحيم
+// Git integration through the git CLI, parsed into structured results
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+// Separators for --format output; neither appears in commit metadata
+pub(crate) const FIELD: char = '\u{1f}';
+pub(crate) const RECORD: char = '\u{1e}';
+const LOG_FORMAT: &str = "--format=%H%x1f%h%x1f%P%x1f%an%x1f%ae%x1f%aI%x1f%cI%x1f%s%x1f%b%x1e";
+pub(crate) const DEFAULT_LOG_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    Unmodified,
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Copied,
+    TypeChanged,
+    Unmerged,
+    Untracked,
+    Ignored,
+}
+
+impl FileState {
+    pub(crate) fn from_code(code: char) -> Self {
+        match code {
+            'M' => FileState::Modified,
+            'A' => FileState::Added,
+            'D' => FileState::Deleted,
+            'R' => FileState::Renamed,
+            'C' => FileState::Copied,
+            'T' => FileState::TypeChanged,
+            'U' => FileState::Unmerged,
+            _ => FileState::Unmodified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStatus {
+    // Relative to the repository root
+    pub path: String,
+    // Where a renamed or copied file came from
+    pub original_path: Option<String>,
+    // State in the index (staged) and in the working tree (unstaged)
+    pub index: FileState,
+    pub worktree: FileState,
+    pub conflicted: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepoStatus {
+    pub root: String,
+    // None when HEAD is detached
+    pub branch: Option<String>,
+    // None before the first commit
+    pub head: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+    pub files: Vec<FileStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub short_hash: String,
+    pub parents: Vec<String>,
+    pub author_name: String,
+    pub author_email: String,
+    pub author_date: DateTime<FixedOffset>,
+    pub commit_date: DateTime<FixedOffset>,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub is_remote: bool,
+    pub is_current: bool,
+    pub commit: String,
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+    // The upstream is configured but no longer exists on the remote
+    pub upstream_gone: bool,
+    pub last_commit_date: Option<DateTime<FixedOffset>>,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub content: String,
+    // 1-based line numbers on each side; None on the side the line doesn't exist
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    // Text after the second @@, usually the enclosing function
+    pub header: String,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub old_path: Option<String>,
+    pub state: FileState,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<DiffHunk>,
+}
+
+// One ref a push touched, from `git push --porcelain`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefUpdate {
+    // " " fast-forward, "+" forced, "-" deleted, "*" new, "!" rejected, "=" up to date
+    pub flag: String,
+    pub local_ref: String,
+    pub remote_ref: String,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushResult {
+    pub remote: String,
+    pub updates: Vec<RefUpdate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullResult {
+    pub head_before: Option<String>,
+    pub head_after: Option<String>,
+    // Commits the pull brought in, newest first
+    pub commits: Vec<CommitInfo>,
+}
+
+pub(crate) async fn run_git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    run_git_with_input(dir, args, None).await
+}
+
+// Like run_git, with `input` written to git's stdin
+pub(crate) async fn run_git_with_input(dir: &Path, args: &[&str], input: Option<&str>) -> Result<String, String> {
+    let mut child = Command::new("git")
+        .args(["-c", "core.quotepath=false", "-c", "color.ui=false"])
+        .args(args)
+        .current_dir(dir)
+        // Never block on a credential prompt nobody can see, and keep messages parseable
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("LC_ALL", "C")
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git: {}. Is it installed?", e))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write to git: {}", e))?;
+        // Dropping stdin closes it so git sees the end of the input
+    }
+
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr
+            .lines()
+            .map(|line| line.trim_start_matches("fatal: ").trim_start_matches("error: "))
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Err(if message.is_empty() {
+            format!("git {} failed", args.first().unwrap_or(&""))
+        } else {
+            message
+        })
+    }
+}
+
+// Remotes, branches and revisions from the frontend sit where git also reads options, so a value
+// like --upload-pack=... or --output=... would run or write something instead of naming a ref
+pub(crate) fn not_an_option<'a>(value: &'a str, what: &str) -> Result<&'a str, String> {
+    if value.starts_with('-') {
+        Err(format!("Invalid {}: {}", what, value))
+    } else {
+        Ok(value)
+    }
+}
+
+pub(crate) async fn repo_root(path: &Path) -> Result<PathBuf, String> {
+    let dir = if path.is_file() {
+        path.parent().unwrap_or(path)
+    } else {
+        path
+    };
+    let root = run_git(dir, &["rev-parse", "--show-toplevel"]).await?;
+    Ok(PathBuf::from(root.trim()))
+}
+
+pub async fn status(repo: &Path) -> Result<RepoStatus, String> {
+    let root = repo_root(repo).await?;
+    let output = run_git(&root, &["status", "--porcelain=v2", "--branch", "-z"]).await?;
+    let mut status = parse_status(&output);
+    status.root = root.display().to_string();
+    Ok(status)
+}
+
+fn parse_status(output: &str) -> RepoStatus {
+    let mut status = RepoStatus::default();
+    let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+
+    while let Some(entry) = entries.next() {
+        if let Some(header) = entry.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" if value != "(initial)" => status.head = Some(value.to_string()),
+                "branch.head" if value != "(detached)" => status.branch = Some(value.to_string()),
+                "branch.upstream" => status.upstream = Some(value.to_string()),
+                "branch.ab" => {
+                    for count in value.split_whitespace() {
+                        if let Some(ahead) = count.strip_prefix('+') {
+                            status.ahead = ahead.parse().unwrap_or(0);
+                        } else if let Some(behind) = count.strip_prefix('-') {
+                            status.behind = behind.parse().unwrap_or(0);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let kind = entry.chars().next().unwrap_or(' ');
+        // Ordinary entries have 8 fields before the path, renames 9, unmerged 10
+        let file = match kind {
+            '1' | '2' | 'u' => {
+                let fields = match kind {
+                    '1' => 8,
+                    '2' => 9,
+                    _ => 10,
+                };
+                let parts: Vec<&str> = entry.splitn(fields + 1, ' ').collect();
+                let Some(path) = parts.get(fields) else {
+                    continue;
+                };
+                let mut codes = parts.get(1).copied().unwrap_or("..").chars();
+                let index = FileState::from_code(codes.next().unwrap_or('.'));
+                let worktree = FileState::from_code(codes.next().unwrap_or('.'));
+                // With -z the original path of a rename is the next entry
+                let original_path = if kind == '2' {
+                    entries.next().map(|p| p.to_string())
+                } else {
+                    None
+                };
+                FileStatus {
+                    path: path.to_string(),
+                    original_path,
+                    index: if kind == 'u' { FileState::Unmerged } else { index },
+                    worktree: if kind == 'u' { FileState::Unmerged } else { worktree },
+                    conflicted: kind == 'u',
+                }
+            }
+            '?' | '!' => FileStatus {
+                path: entry[2..].to_string(),
+                original_path: None,
+                index: FileState::Unmodified,
+                worktree: if kind == '?' {
+                    FileState::Untracked
+                } else {
+                    FileState::Ignored
+                },
+                conflicted: false,
+            },
+            _ => continue,
+        };
+        status.files.push(file);
+    }
+    status
+}
+
+pub async fn log(repo: &Path, revision: Option<&str>, path: Option<&str>, limit: usize) -> Result<Vec<CommitInfo>, String> {
+    let limit = format!("--max-count={}", limit);
+    let mut args = vec!["log", LOG_FORMAT, limit.as_str()];
+    if let Some(revision) = revision {
+        args.push("--end-of-options");
+        args.push(not_an_option(revision, "revision")?);
+    }
+    if let Some(path) = path {
+        args.push("--follow");
+        args.push("--");
+        args.push(path);
+    }
+
+    match run_git(repo, &args).await {
+        Ok(output) => Ok(parse_log(&output)),
+        // A repository without commits has no log rather than an error
+        Err(e) if e.contains("does not have any commits") => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn parse_log(output: &str) -> Vec<CommitInfo> {
+    output
+        .split(RECORD)
+        .filter_map(|record| {
+            let fields: Vec<&str> = record.trim_start_matches('\n').splitn(9, FIELD).collect();
+            if fields.len() < 9 {
+                return None;
+            }
+            Some(CommitInfo {
+                hash: fields[0].to_string(),
+                short_hash: fields[1].to_string(),
+                parents: fields[2].split_whitespace().map(|p| p.to_string()).collect(),
+                author_name: fields[3].to_string(),
+                author_email: fields[4].to_string(),
+                author_date: DateTime::parse_from_rfc3339(fields[5]).ok()?,
+                commit_date: DateTime::parse_from_rfc3339(fields[6]).ok()?,
+                subject: fields[7].to_string(),
+                body: fields[8].trim_end().to_string(),
+            })
+        })
+        .collect()
+}
+
+async fn head(repo: &Path) -> Option<String> {
+    run_git(repo, &["rev-parse", "--verify", "--quiet", "HEAD"])
+        .await
+        .ok()
+        .map(|hash| hash.trim().to_string())
+}
+
+pub async fn branches(repo: &Path) -> Result<Vec<BranchInfo>, String> {
+    let format = "--format=%(refname)%1f%(refname:short)%1f%(objectname)%1f%(upstream:short)%1f%(upstream:track,nobracket)%1f%(HEAD)%1f%(committerdate:iso-strict)%1f%(contents:subject)";
+    let output = run_git(repo, &["for-each-ref", format, "refs/heads", "refs/remotes"]).await?;
+
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(8, FIELD).collect();
+            if fields.len() < 8 || fields[0].ends_with("/HEAD") {
+                return None;
+            }
+
+            let mut ahead = 0;
+            let mut behind = 0;
+            for part in fields[4].split(", ") {
+                if let Some(count) = part.strip_prefix("ahead ") {
+                    ahead = count.parse().unwrap_or(0);
+                } else if let Some(count) = part.strip_prefix("behind ") {
+                    behind = count.parse().unwrap_or(0);
+                }
+            }
+
+            Some(BranchInfo {
+                name: fields[1].to_string(),
+                is_remote: fields[0].starts_with("refs/remotes/"),
+                is_current: fields[5] == "*",
+                commit: fields[2].to_string(),
+                upstream: Some(fields[3].to_string()).filter(|u| !u.is_empty()),
+                ahead,
+                behind,
+                upstream_gone: fields[4] == "gone",
+                last_commit_date: DateTime::parse_from_rfc3339(fields[6]).ok(),
+                subject: fields[7].to_string(),
+            })
+        })
+        .collect())
+}
+
+pub async fn diff(repo: &Path, path: Option<&str>, staged: bool) -> Result<Vec<FileDiff>, String> {
+    // Explicit prefixes so diff.noprefix or diff.mnemonicPrefix can't change what parse_diff sees
+    let mut args = vec!["diff", "--no-ext-diff", "--find-renames", "--src-prefix=a/", "--dst-prefix=b/"];
+    if staged {
+        args.push("--cached");
+    }
+    if let Some(path) = path {
+        args.push("--");
+        args.push(path);
+    }
+    Ok(parse_diff(&run_git(repo, &args).await?))
+}
+
+pub(crate) fn parse_diff(output: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut old_line = 0;
+    let mut new_line = 0;
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            // Good enough until the ---/+++ lines give exact paths
+            let path = header
+                .rsplit_once(" b/")
+                .map(|(_, path)| path)
+                .unwrap_or(header)
+                .to_string();
+            files.push(FileDiff {
+                path,
+                old_path: None,
+                state: FileState::Modified,
+                binary: false,
+                additions: 0,
+                deletions: 0,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+
+        if let Some(hunk) = file.hunks.last_mut().filter(|_| !line.starts_with("@@")) {
+            let (kind, content) = match line.chars().next() {
+                Some('+') => (DiffLineKind::Added, &line[1..]),
+                Some('-') => (DiffLineKind::Removed, &line[1..]),
+                // "\ No newline at end of file"
+                Some('\\') => continue,
+                // Empty context lines sometimes lose their leading space
+                _ => (DiffLineKind::Context, line.get(1..).unwrap_or("")),
+            };
+            let (old, new) = match kind {
+                DiffLineKind::Added => {
+                    file.additions += 1;
+                    new_line += 1;
+                    (None, Some(new_line))
+                }
+                DiffLineKind::Removed => {
+                    file.deletions += 1;
+                    old_line += 1;
+                    (Some(old_line), None)
+                }
+                DiffLineKind::Context => {
+                    old_line += 1;
+                    new_line += 1;
+                    (Some(old_line), Some(new_line))
+                }
+            };
+            hunk.lines.push(DiffLine {
+                kind,
+                content: content.to_string(),
+                old_line: old,
+                new_line: new,
+            });
+            continue;
+        }
+
+        if let Some(range) = line.strip_prefix("@@ ") {
+            let Some((ranges, header)) = range.split_once(" @@") else {
+                continue;
+            };
+            let mut parts = ranges.split_whitespace();
+            let (old_start, old_lines) = parse_range(parts.next().unwrap_or("-0"));
+            let (new_start, new_lines) = parse_range(parts.next().unwrap_or("+0"));
+            // Counters hold the line before the next one to be numbered
+            old_line = old_start.saturating_sub(1);
+            new_line = new_start.saturating_sub(1);
+            file.hunks.push(DiffHunk {
+                old_start,
+                old_lines,
+                new_start,
+                new_lines,
+                header: header.trim().to_string(),
+                lines: Vec::new(),
+            });
+        } else if line.starts_with("new file mode") {
+            file.state = FileState::Added;
+        } else if line.starts_with("deleted file mode") {
+            file.state = FileState::Deleted;
+        } else if let Some(from) = line.strip_prefix("rename from ") {
+            file.state = FileState::Renamed;
+            file.old_path = Some(from.to_string());
+        } else if let Some(from) = line.strip_prefix("copy from ") {
+            file.state = FileState::Copied;
+            file.old_path = Some(from.to_string());
+        } else if let Some(to) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            file.path = to.to_string();
+        } else if line.starts_with("Binary files ") {
+            file.binary = true;
+        } else if let Some(path) = line.strip_prefix("+++ b/") {
+            file.path = path.to_string();
+        } else if let Some(path) = line.strip_prefix("--- a/") {
+            if file.state == FileState::Deleted {
+                file.path = path.to_string();
+            }
+        }
+    }
+    files
+}
+
+// "-12,3" or "+4" into (start, count)
+fn parse_range(range: &str) -> (usize, usize) {
+    let range = range.trim_start_matches(['-', '+']);
+    match range.split_once(',') {
+        Some((start, count)) => (start.parse().unwrap_or(0), count.parse().unwrap_or(0)),
+        None => (range.parse().unwrap_or(0), 1),
+    }
+}
+
+#[tauri::command]
+pub async fn git_status(repo_path: String) -> Result<RepoStatus, String> {
+    status(Path::new(&repo_path)).await
+}
+
+#[tauri::command]
+pub async fn git_add(repo_path: String, paths: Vec<String>) -> Result<RepoStatus, String> {
+    let repo = Path::new(&repo_path);
+    let mut args = vec!["add"];
+    if paths.is_empty() {
+        args.push("--all");
+    } else {
+        args.push("--");
+        args.extend(paths.iter().map(|p| p.as_str()));
+    }
+    run_git(repo, &args).await?;
+    status(repo).await
+}
+
+#[tauri::command]
+pub async fn git_commit(repo_path: String, message: String, amend: Option<bool>) -> Result<CommitInfo, String> {
+    if message.trim().is_empty() {
+        return Err("Commit message is empty".to_string());
+    }
+    let repo = Path::new(&repo_path);
+    let mut args = vec!["commit", "--message", message.as_str()];
+    if amend.unwrap_or(false) {
+        args.push("--amend");
+    }
+    run_git(repo, &args).await?;
+
+    log(repo, Some("HEAD"), None, 1)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| "Commit succeeded but HEAD couldn't be read".to_string())
+}
+
+#[tauri::command]
+pub async fn git_push(
+    repo_path: String,
+    remote: Option<String>,
+    branch: Option<String>,
+    set_upstream: Option<bool>,
+    force: Option<bool>,
+) -> Result<PushResult, String> {
+    let repo = Path::new(&repo_path);
+    let remote = remote.unwrap_or_else(|| "origin".to_string());
+    let mut args = vec!["push", "--porcelain"];
+    if set_upstream.unwrap_or(false) {
+        args.push("--set-upstream");
+    }
+    if force.unwrap_or(false) {
+        // Refuses to overwrite commits we haven't seen
+        args.push("--force-with-lease");
+    }
+    args.push("--end-of-options");
+    args.push(not_an_option(&remote, "remote")?);
+    if let Some(branch) = &branch {
+        args.push(not_an_option(branch, "branch")?);
+    }
+
+    let output = run_git(repo, &args).await?;
+    let updates = output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let flag = parts.next()?;
+            let refs = parts.next()?;
+            let (local_ref, remote_ref) = refs.split_once(':')?;
+            Some(RefUpdate {
+                flag: flag.to_string(),
+                local_ref: local_ref.to_string(),
+                remote_ref: remote_ref.to_string(),
+                summary: parts.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect();
+
+    Ok(PushResult { remote, updates })
+}
+
+#[tauri::command]
+pub async fn git_pull(repo_path: String, remote: Option<String>, branch: Option<String>, rebase: Option<bool>) -> Result<PullResult, String> {
+    let repo = Path::new(&repo_path);
+    let mut args = vec!["pull", if rebase.unwrap_or(false) { "--rebase" } else { "--no-rebase" }];
+    if let Some(remote) = &remote {
+        args.push("--end-of-options");
+        args.push(not_an_option(remote, "remote")?);
+        if let Some(branch) = &branch {
+            args.push(not_an_option(branch, "branch")?);
+        }
+    }
+
+    let head_before = head(repo).await;
+    run_git(repo, &args).await?;
+
+    let head_after = head(repo).await;
+    let commits = match (&head_before, &head_after) {
+        (Some(before), Some(after)) if before != after => {
+            let range = format!("{}..{}", before, after);
+            log(repo, Some(&range), None, DEFAULT_LOG_LIMIT).await?
+        }
+        (None, Some(_)) => log(repo, Some("HEAD"), None, DEFAULT_LOG_LIMIT).await?,
+        _ => Vec::new(),
+    };
+
+    Ok(PullResult {
+        head_before,
+        head_after,
+        commits,
+    })
+}
+
+#[tauri::command]
+pub async fn git_branch_list(repo_path: String) -> Result<Vec<BranchInfo>, String> {
+    branches(Path::new(&repo_path)).await
+}
+
+#[tauri::command]
+pub async fn git_checkout(repo_path: String, branch: String, create: Option<bool>) -> Result<RepoStatus, String> {
+    let repo = Path::new(&repo_path);
+    let mut args = vec!["checkout"];
+    if create.unwrap_or(false) {
+        args.push("-b");
+    }
+    // Checked rather than put behind --end-of-options, which older git takes for a ref here
+    args.push(not_an_option(&branch, "branch")?);
+    // Keeps a branch name from being read as a path
+    args.push("--");
+    run_git(repo, &args).await?;
+    status(repo).await
+}
+
+#[tauri::command]
+pub async fn git_diff(repo_path: String, path: Option<String>, staged: Option<bool>) -> Result<Vec<FileDiff>, String> {
+    diff(Path::new(&repo_path), path.as_deref(), staged.unwrap_or(false)).await
+}
+
+#[tauri::command]
+pub async fn git_log(
+    repo_path: String,
+    revision: Option<String>,
+    path: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<CommitInfo>, String> {
+    log(
+        Path::new(&repo_path),
+        revision.as_deref(),
+        path.as_deref(),
+        limit.unwrap_or(DEFAULT_LOG_LIMIT),
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn git_init(repo_path: String, initial_branch: Option<String>) -> Result<RepoStatus, String> {
+    let repo = Path::new(&repo_path);
+    std::fs::create_dir_all(repo).map_err(|e| format!("Failed to create {}: {}", repo_path, e))?;
+    let mut args = vec!["init"];
+    let branch_arg = initial_branch.map(|branch| format!("--initial-branch={}", branch));
+    if let Some(branch_arg) = &branch_arg {
+        args.push(branch_arg);
+    }
+    run_git(repo, &args).await?;
+    status(repo).await
+}
+
+#[tauri::command]
+pub async fn git_clone(url: String, repo_path: String, branch: Option<String>) -> Result<RepoStatus, String> {
+    let target = Path::new(&repo_path);
+    let parent = target
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Invalid clone target: {}", repo_path))?;
+
+    let mut args = vec!["clone"];
+    if let Some(branch) = &branch {
+        args.push("--branch");
+        args.push(branch);
+    }
+    args.push("--");
+    args.push(&url);
+    args.push(&name);
+    run_git(parent, &args).await?;
+    status(target).await
+}
+
+// A throwaway repository for the git tests, removed again when the test ends
+#[cfg(test)]
+pub(crate) mod test_repo {
+    use super::run_git;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    pub(crate) struct TempRepo(PathBuf);
+
+    impl TempRepo {
+        pub(crate) async fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("vuno-git-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let repo = Self(dir);
+            repo.git(&["init", "--initial-branch=main"]).await;
+            repo.git(&["config", "user.name", "Test Author"]).await;
+            repo.git(&["config", "user.email", "author@example.com"]).await;
+            repo.git(&["config", "commit.gpgsign", "false"]).await;
+            repo
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+
+        pub(crate) fn write(&self, path: &str, content: &str) {
+            fs::write(self.0.join(path), content).unwrap();
+        }
+
+        pub(crate) async fn git(&self, args: &[&str]) -> String {
+            run_git(&self.0, args).await.unwrap()
+        }
+
+        pub(crate) async fn commit(&self, message: &str) {
+            self.git(&["add", "--all"]).await;
+            self.git(&["commit", "--message", message]).await;
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_repo::TempRepo;
+    use super::*;
+    use std::fs;
+
+    fn file<'a>(status: &'a RepoStatus, path: &str) -> &'a FileStatus {
+        status
+            .files
+            .iter()
+            .find(|file| file.path == path)
+            .unwrap_or_else(|| panic!("{} missing from status", path))
+    }
+
+    #[tokio::test]
+    async fn status_before_the_first_commit() {
+        let repo = TempRepo::new().await;
+        repo.write("new.txt", "hello\n");
+
+        let status = status(repo.path()).await.unwrap();
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.head, None);
+        assert_eq!(file(&status, "new.txt").worktree, FileState::Untracked);
+    }
+
+    #[tokio::test]
+    async fn status_reports_staged_renames_and_unstaged_edits() {
+        let repo = TempRepo::new().await;
+        repo.write("old name.txt", "one\ntwo\nthree\n");
+        repo.write("edited.txt", "before\n");
+        repo.commit("Initial").await;
+
+        repo.git(&["mv", "old name.txt", "new name.txt"]).await;
+        repo.write("edited.txt", "after\n");
+        repo.write("untracked.txt", "new\n");
+
+        let status = status(repo.path()).await.unwrap();
+        assert!(status.head.is_some());
+        assert_eq!(status.files.len(), 3);
+
+        let renamed = file(&status, "new name.txt");
+        assert_eq!(renamed.index, FileState::Renamed);
+        assert_eq!(renamed.worktree, FileState::Unmodified);
+        assert_eq!(renamed.original_path.as_deref(), Some("old name.txt"));
+
+        let edited = file(&status, "edited.txt");
+        assert_eq!(edited.index, FileState::Unmodified);
+        assert_eq!(edited.worktree, FileState::Modified);
+        assert!(!edited.conflicted);
+
+        assert_eq!(file(&status, "untracked.txt").worktree, FileState::Untracked);
+    }
+
+    #[tokio::test]
+    async fn status_reports_merge_conflicts() {
+        let repo = TempRepo::new().await;
+        repo.write("shared.txt", "base\n");
+        repo.commit("Base").await;
+
+        repo.git(&["checkout", "-b", "feature"]).await;
+        repo.write("shared.txt", "theirs\n");
+        repo.commit("Theirs").await;
+
+        repo.git(&["checkout", "main"]).await;
+        repo.write("shared.txt", "ours\n");
+        repo.commit("Ours").await;
+
+        assert!(run_git(repo.path(), &["merge", "feature"]).await.is_err());
+
+        let status = status(repo.path()).await.unwrap();
+        let conflicted = file(&status, "shared.txt");
+        assert!(conflicted.conflicted);
+        assert_eq!(conflicted.index, FileState::Unmerged);
+        assert_eq!(conflicted.worktree, FileState::Unmerged);
+    }
+
+    #[tokio::test]
+    async fn log_parses_commits_newest_first() {
+        let repo = TempRepo::new().await;
+        assert!(log(repo.path(), None, None, 10).await.unwrap().is_empty());
+
+        repo.write("a.txt", "one\n");
+        repo.commit("First").await;
+        repo.write("a.txt", "two\n");
+        repo.commit("Second\n\nWith a body\nover two lines").await;
+
+        let commits = log(repo.path(), None, None, 10).await.unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].subject, "Second");
+        assert_eq!(commits[0].body, "With a body\nover two lines");
+        assert_eq!(commits[0].author_name, "Test Author");
+        assert_eq!(commits[0].author_email, "author@example.com");
+        assert_eq!(commits[0].parents, vec![commits[1].hash.clone()]);
+        assert!(commits[0].hash.starts_with(&commits[0].short_hash));
+        assert_eq!(commits[1].subject, "First");
+        assert!(commits[1].parents.is_empty());
+        assert_eq!(commits[1].body, "");
+
+        let range = format!("{}..HEAD", commits[1].hash);
+        let since_first = log(repo.path(), Some(&range), None, 10).await.unwrap();
+        assert_eq!(since_first.len(), 1);
+        assert_eq!(since_first[0].subject, "Second");
+
+        assert_eq!(log(repo.path(), None, None, 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn diff_parses_hunks_with_line_numbers() {
+        let repo = TempRepo::new().await;
+        repo.write("code.txt", "one\ntwo\nthree\nfour\n");
+        repo.write("gone.txt", "bye\n");
+        repo.commit("Initial").await;
+
+        // Either setting would otherwise drop or change the a/ and b/ prefixes
+        repo.git(&["config", "diff.noprefix", "true"]).await;
+        repo.git(&["config", "diff.mnemonicPrefix", "true"]).await;
+
+        repo.write("code.txt", "one\n2\nthree\nfour\nfive\n");
+        fs::remove_file(repo.path().join("gone.txt")).unwrap();
+
+        let diffs = diff(repo.path(), None, false).await.unwrap();
+        assert_eq!(diffs.len(), 2);
+
+        let code = diffs.iter().find(|d| d.path == "code.txt").unwrap();
+        assert_eq!(code.state, FileState::Modified);
+        assert_eq!((code.additions, code.deletions), (2, 1));
+        assert_eq!(code.hunks.len(), 1);
+        let hunk = &code.hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (1, 4, 1, 5));
+
+        let removed = hunk.lines.iter().find(|l| l.kind == DiffLineKind::Removed).unwrap();
+        assert_eq!((removed.content.as_str(), removed.old_line, removed.new_line), ("two", Some(2), None));
+        let added: Vec<(&str, Option<usize>)> = hunk
+            .lines
+            .iter()
+            .filter(|l| l.kind == DiffLineKind::Added)
+            .map(|l| (l.content.as_str(), l.new_line))
+            .collect();
+        assert_eq!(added, vec![("2", Some(2)), ("five", Some(5))]);
+        let last = hunk.lines.last().unwrap();
+        assert_eq!((last.old_line, last.new_line), (None, Some(5)));
+
+        let gone = diffs.iter().find(|d| d.path == "gone.txt").unwrap();
+        assert_eq!(gone.state, FileState::Deleted);
+        assert_eq!(gone.deletions, 1);
+    }
+
+    #[tokio::test]
+    async fn staged_diff_reports_renames_and_new_files() {
+        let repo = TempRepo::new().await;
+        repo.write("before.txt", "same\ncontent\nhere\n");
+        repo.commit("Initial").await;
+
+        repo.git(&["mv", "before.txt", "after.txt"]).await;
+        repo.write("added.txt", "new\n");
+        repo.git(&["add", "added.txt"]).await;
+
+        assert!(diff(repo.path(), None, false).await.unwrap().is_empty());
+        let diffs = diff(repo.path(), None, true).await.unwrap();
+
+        let renamed = diffs.iter().find(|d| d.path == "after.txt").unwrap();
+        assert_eq!(renamed.state, FileState::Renamed);
+        assert_eq!(renamed.old_path.as_deref(), Some("before.txt"));
+        assert!(renamed.hunks.is_empty());
+
+        let added = diffs.iter().find(|d| d.path == "added.txt").unwrap();
+        assert_eq!(added.state, FileState::Added);
+        assert_eq!(added.additions, 1);
+
+        let only_added = diff(repo.path(), Some("added.txt"), true).await.unwrap();
+        assert_eq!(only_added.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn branches_lists_local_branches_and_the_current_one() {
+        let repo = TempRepo::new().await;
+        repo.write("a.txt", "one\n");
+        repo.commit("First").await;
+        repo.git(&["branch", "feature"]).await;
+        repo.write("a.txt", "two\n");
+        repo.commit("Second").await;
+
+        let branches = branches(repo.path()).await.unwrap();
+        assert_eq!(branches.len(), 2);
+
+        let main = branches.iter().find(|b| b.name == "main").unwrap();
+        assert!(main.is_current);
+        assert!(!main.is_remote);
+        assert_eq!(main.subject, "Second");
+        assert_eq!(main.upstream, None);
+        assert!(main.last_commit_date.is_some());
+
+        let feature = branches.iter().find(|b| b.name == "feature").unwrap();
+        assert!(!feature.is_current);
+        assert_eq!(feature.subject, "First");
+        assert_ne!(feature.commit, main.commit);
+    }
+
+    #[tokio::test]
+    async fn refuses_refs_that_look_like_options() {
+        let repo = TempRepo::new().await;
+        repo.write("a.txt", "one\n");
+        repo.commit("First").await;
+        let repo_path = repo.path().display().to_string();
+        let marker = repo.path().join("written-by-git");
+
+        let output = format!("--output={}", marker.display());
+        assert!(log(repo.path(), Some(&output), None, 10).await.is_err());
+        assert!(git_checkout(repo_path.clone(), "--orphan=evil".to_string(), None).await.is_err());
+        assert!(git_checkout(repo_path.clone(), "-f".to_string(), Some(true)).await.is_err());
+
+        let upload_pack = format!("--upload-pack=touch {}", marker.display());
+        assert!(git_pull(repo_path.clone(), Some(upload_pack), None, None).await.is_err());
+        let receive_pack = format!("--receive-pack=touch {}", marker.display());
+        assert!(git_push(repo_path.clone(), Some(receive_pack), None, None, None).await.is_err());
+        assert!(git_push(repo_path, None, Some("--all".to_string()), None, None).await.is_err());
+
+        assert!(!marker.exists());
+        let status = status(repo.path()).await.unwrap();
+        assert_eq!(status.branch.as_deref(), Some("main"));
+    }
+}
@@ -0,0 +1,579 @@
+// بسم الله الرحمن الرحيم
+// Git gutter: changed lines in open buffers against the index or HEAD, with per-hunk stage and revert
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::buffer::BufferManager;
+use crate::git::{repo_root, run_git, run_git_with_input};
+
+// Past this many changed lines a region is reported as one block instead of diffed line by line
+const MAX_EDIT_DISTANCE: isize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffBase {
+    Index,
+    #[default]
+    Head,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GutterHunk {
+    pub kind: HunkKind,
+    // 1-based buffer lines; a deletion has no lines and the removed text sat just above `start_line`
+    pub start_line: usize,
+    pub line_count: usize,
+    pub base_start_line: usize,
+    // The base's version of the lines, to show what was changed or removed
+    pub base_lines: Vec<String>,
+}
+
+impl GutterHunk {
+    fn contains(&self, line: usize) -> bool {
+        if self.line_count == 0 {
+            line == self.start_line || line + 1 == self.start_line
+        } else {
+            line >= self.start_line && line < self.start_line + self.line_count
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferDiff {
+    pub buffer_id: usize,
+    // Relative to the repository root
+    pub path: String,
+    pub base: DiffBase,
+    // False when the file isn't in the base yet, so every line shows as added
+    pub tracked: bool,
+    pub hunks: Vec<GutterHunk>,
+}
+
+struct TrackedBuffer {
+    root: PathBuf,
+    path: String,
+    base: DiffBase,
+    tracked: bool,
+    // Shared so a re-diff can run without holding the lock
+    base_lines: Arc<Vec<String>>,
+    hunks: Vec<GutterHunk>,
+}
+
+impl TrackedBuffer {
+    fn to_diff(&self, buffer_id: usize) -> BufferDiff {
+        BufferDiff {
+            buffer_id,
+            path: self.path.clone(),
+            base: self.base,
+            tracked: self.tracked,
+            hunks: self.hunks.clone(),
+        }
+    }
+}
+
+// The base text is read from git once per buffer; edits only re-diff against that copy
+pub struct GitGutter {
+    buffers: RwLock<HashMap<usize, TrackedBuffer>>,
+}
+
+impl GitGutter {
+    pub fn new() -> Self {
+        Self {
+            buffers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Start tracking a buffer, or re-read its base after a commit or checkout
+    pub async fn track(&self, buffer_id: usize, base: DiffBase, buffers: &BufferManager) -> Result<BufferDiff, String> {
+        let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+        let file = buffer.path.ok_or_else(|| "Buffer has no file path".to_string())?;
+        let root = repo_root(file.parent().unwrap_or(&file)).await?;
+        let path = relative_path(&root, &file)?;
+        let (tracked, base_lines) = read_base(&root, &path, base).await;
+
+        self.buffers.write().insert(
+            buffer_id,
+            TrackedBuffer {
+                root,
+                path,
+                base,
+                tracked,
+                base_lines: Arc::new(base_lines),
+                hunks: Vec::new(),
+            },
+        );
+        self.update(buffer_id, buffers)
+            .map(|(diff, _)| diff)
+            .ok_or_else(|| format!("Buffer {} not found", buffer_id))
+    }
+
+    // Re-diff after an edit; None when the buffer isn't tracked or its hunks are unchanged
+    pub fn refresh(&self, buffer_id: usize, buffers: &BufferManager) -> Option<BufferDiff> {
+        if !self.buffers.read().contains_key(&buffer_id) {
+            return None;
+        }
+        self.update(buffer_id, buffers)
+            .and_then(|(diff, changed)| changed.then_some(diff))
+    }
+
+    fn update(&self, buffer_id: usize, buffers: &BufferManager) -> Option<(BufferDiff, bool)> {
+        let Some(buffer) = buffers.get_buffer(buffer_id) else {
+            // Closed
+            self.buffers.write().remove(&buffer_id);
+            return None;
+        };
+        let lines: Vec<&str> = buffer.content.lines().collect();
+
+        let base_lines = self.buffers.read().get(&buffer_id)?.base_lines.clone();
+        let hunks = compute_hunks(&base_lines, &lines);
+
+        let mut tracked = self.buffers.write();
+        let entry = tracked.get_mut(&buffer_id)?;
+        // The base was re-read meanwhile, and whoever did that diffs against it
+        if !Arc::ptr_eq(&entry.base_lines, &base_lines) {
+            return Some((entry.to_diff(buffer_id), false));
+        }
+        let changed = hunks != entry.hunks;
+        entry.hunks = hunks;
+        Some((entry.to_diff(buffer_id), changed))
+    }
+
+    fn location(&self, buffer_id: usize) -> Result<(PathBuf, String, DiffBase), String> {
+        self.buffers
+            .read()
+            .get(&buffer_id)
+            .map(|entry| (entry.root.clone(), entry.path.clone(), entry.base))
+            .ok_or_else(|| format!("Buffer {} has no git diff; request one first", buffer_id))
+    }
+
+    // Write the buffer's version of the hunk at `line` into the index, leaving the rest of the file unstaged
+    pub async fn stage_hunk(&self, buffer_id: usize, line: usize, buffers: &BufferManager) -> Result<BufferDiff, String> {
+        let (root, path, base) = self.location(buffer_id)?;
+        let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+        let lines: Vec<&str> = buffer.content.lines().collect();
+
+        // Staging always goes against the index, whichever base the gutter shows
+        let index_text = run_git(&root, &["show", &format!(":{}", path)]).await.ok();
+        let index_lines: Vec<String> = index_text.as_deref().unwrap_or("").lines().map(str::to_string).collect();
+        let hunk = find_hunk(compute_hunks(&index_lines, &lines), line)
+            .ok_or_else(|| format!("No unstaged change at line {}", line))?;
+
+        let base_start = hunk.base_start_line - 1;
+        let new_start = hunk.start_line - 1;
+        let staged: Vec<&str> = index_lines[..base_start]
+            .iter()
+            .map(String::as_str)
+            .chain(lines[new_start..new_start + hunk.line_count].iter().copied())
+            .chain(index_lines[base_start + hunk.base_lines.len()..].iter().map(String::as_str))
+            .collect();
+
+        // Keep the index's line endings and final newline
+        let reference = index_text.as_deref().filter(|text| !text.is_empty()).unwrap_or(&buffer.content);
+        let eol = line_ending(reference);
+        let mut content = staged.join(eol);
+        if !content.is_empty() && reference.ends_with('\n') {
+            content.push_str(eol);
+        }
+
+        let blob = run_git_with_input(&root, &["hash-object", "-w", "--stdin"], Some(&content)).await?;
+        let mode = run_git(&root, &["ls-files", "--stage", "--", &path])
+            .await
+            .ok()
+            .and_then(|output| output.split_whitespace().next().map(str::to_string))
+            .unwrap_or_else(|| "100644".to_string());
+        let cacheinfo = format!("{},{},{}", mode, blob.trim(), path);
+        run_git(&root, &["update-index", "--add", "--cacheinfo", &cacheinfo]).await?;
+
+        if base == DiffBase::Index {
+            return self.track(buffer_id, base, buffers).await;
+        }
+        self.update(buffer_id, buffers)
+            .map(|(diff, _)| diff)
+            .ok_or_else(|| format!("Buffer {} not found", buffer_id))
+    }
+
+    // Put the base's version of the hunk at `line` back into the buffer, as an ordinary undoable edit
+    pub fn revert_hunk(&self, buffer_id: usize, line: usize, buffers: &BufferManager) -> Result<BufferDiff, String> {
+        let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+        let content = &buffer.content;
+        let lines: Vec<&str> = content.lines().collect();
+
+        let hunks = {
+            let tracked = self.buffers.read();
+            let entry = tracked
+                .get(&buffer_id)
+                .ok_or_else(|| format!("Buffer {} has no git diff; request one first", buffer_id))?;
+            compute_hunks(&entry.base_lines, &lines)
+        };
+        let hunk = find_hunk(hunks, line).ok_or_else(|| format!("No change at line {}", line))?;
+
+        let offsets = line_offsets(content);
+        let mut start = offsets[hunk.start_line - 1];
+        let end = offsets[hunk.start_line - 1 + hunk.line_count];
+        let eol = line_ending(content);
+
+        // The buffer's last line may have no newline, so text replacing or following it can't either
+        let text = if end == content.len() && !content.is_empty() && !content.ends_with('\n') {
+            if hunk.base_lines.is_empty() {
+                // Added lines at the end go with the line break before them
+                if content[..start].ends_with(eol) {
+                    start -= eol.len();
+                }
+                String::new()
+            } else if hunk.line_count == 0 {
+                format!("{}{}", eol, hunk.base_lines.join(eol))
+            } else {
+                hunk.base_lines.join(eol)
+            }
+        } else {
+            hunk.base_lines.iter().map(|line| format!("{}{}", line, eol)).collect()
+        };
+
+        // apply_edit notifies the gutter, which re-diffs
+        buffers.apply_edit(buffer_id, start, end, &text)?;
+        self.buffers
+            .read()
+            .get(&buffer_id)
+            .map(|entry| entry.to_diff(buffer_id))
+            .ok_or_else(|| format!("Buffer {} not found", buffer_id))
+    }
+}
+
+pub(crate) fn relative_path(root: &Path, file: &Path) -> Result<String, String> {
+    // The root comes back from git resolved, so compare resolved paths
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let file = match (file.parent().and_then(|dir| dir.canonicalize().ok()), file.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => file.to_path_buf(),
+    };
+    let relative = file
+        .strip_prefix(&root)
+        .map_err(|_| format!("{} is outside the repository", file.display()))?;
+    Ok(relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+// None of the file in the base (new or untracked) reads as an empty base
+async fn read_base(root: &Path, path: &str, base: DiffBase) -> (bool, Vec<String>) {
+    let spec = match base {
+        DiffBase::Index => format!(":{}", path),
+        DiffBase::Head => format!("HEAD:{}", path),
+    };
+    match run_git(root, &["show", &spec]).await {
+        Ok(text) => (true, text.lines().map(str::to_string).collect()),
+        Err(_) => (false, Vec::new()),
+    }
+}
+
+fn find_hunk(hunks: Vec<GutterHunk>, line: usize) -> Option<GutterHunk> {
+    hunks.into_iter().find(|hunk| hunk.contains(line))
+}
+
+fn line_ending(text: &str) -> &'static str {
+    if text.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// Byte offset where each line starts, as str::lines splits them, plus the end of the text
+fn line_offsets(content: &str) -> Vec<usize> {
+    let mut offsets: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|&offset| offset < content.len())
+        .collect();
+    offsets.push(content.len());
+    offsets
+}
+
+fn line_hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Line endings are ignored, so a CRLF checkout of an LF file doesn't show every line as modified
+pub(crate) fn compute_hunks(base: &[String], lines: &[&str]) -> Vec<GutterHunk> {
+    let old: Vec<u64> = base.iter().map(|line| line_hash(line)).collect();
+    let new: Vec<u64> = lines.iter().map(|line| line_hash(line)).collect();
+
+    // An edit usually touches a few lines, so only the stretch between the unchanged head and tail is diffed
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // Nothing to line up when one side is empty, as for a new or emptied file: it's all one hunk
+    let matches = if a.is_empty() || b.is_empty() {
+        Vec::new()
+    } else {
+        matching_lines(a, b).unwrap_or_default()
+    };
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for (match_x, match_y) in matches.into_iter().chain(std::iter::once((a.len(), b.len()))) {
+        if match_x > x || match_y > y {
+            let (old_start, new_start) = (prefix + x, prefix + y);
+            let (old_count, new_count) = (match_x - x, match_y - y);
+            hunks.push(GutterHunk {
+                kind: if old_count == 0 {
+                    HunkKind::Added
+                } else if new_count == 0 {
+                    HunkKind::Deleted
+                } else {
+                    HunkKind::Modified
+                },
+                start_line: new_start + 1,
+                line_count: new_count,
+                base_start_line: old_start + 1,
+                base_lines: base[old_start..old_start + old_count].to_vec(),
+            });
+        }
+        x = match_x + 1;
+        y = match_y + 1;
+    }
+    hunks
+}
+
+// Myers' O(ND) diff, giving the (old, new) index pairs of lines kept in a shortest edit script.
+// None when the sequences differ by more than MAX_EDIT_DISTANCE.
+fn matching_lines(a: &[u64], b: &[u64]) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // Before round d only diagonals -(d-1)..=(d-1) have been reached, so that's all that's kept
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=(n + m).min(MAX_EDIT_DISTANCE) {
+        trace.push(if d == 0 {
+            Vec::new()
+        } else {
+            v[(offset - d + 1) as usize..=(offset + d - 1) as usize].to_vec()
+        });
+
+        let mut k = -d;
+        while k <= d {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+            k += 2;
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let at = |k: isize| v[(k + d - 1) as usize];
+            let k = x - y;
+            let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+            let prev_x = at(prev_k);
+            (prev_x, prev_x - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    matches.reverse();
+    matches
+}
+
+#[tauri::command]
+pub async fn git_buffer_diff(
+    buffer_id: usize,
+    base: Option<DiffBase>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    gutter: tauri::State<'_, GitGutter>,
+) -> Result<BufferDiff, String> {
+    gutter.track(buffer_id, base.unwrap_or_default(), &buffer_manager).await
+}
+
+#[tauri::command]
+pub async fn git_stage_hunk(
+    buffer_id: usize,
+    line: usize,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    gutter: tauri::State<'_, GitGutter>,
+) -> Result<BufferDiff, String> {
+    gutter.stage_hunk(buffer_id, line, &buffer_manager).await
+}
+
+#[tauri::command]
+pub fn git_revert_hunk(
+    buffer_id: usize,
+    line: usize,
+    buffer_manager: tauri::State<'_, BufferManager>,
+    gutter: tauri::State<'_, GitGutter>,
+) -> Result<BufferDiff, String> {
+    gutter.revert_hunk(buffer_id, line, &buffer_manager)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::TempRepo;
+
+    #[test]
+    fn one_empty_side_is_a_single_hunk() {
+        let lines: Vec<String> = (0..5000).map(|n| format!("line {}", n)).collect();
+        let borrowed: Vec<&str> = lines.iter().map(String::as_str).collect();
+
+        let added = compute_hunks(&[], &borrowed);
+        assert_eq!(added.len(), 1);
+        assert_eq!((added[0].kind, added[0].start_line, added[0].line_count), (HunkKind::Added, 1, 5000));
+
+        let deleted = compute_hunks(&lines, &[]);
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].kind, HunkKind::Deleted);
+        assert_eq!(deleted[0].base_lines.len(), 5000);
+
+        assert!(compute_hunks(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn edits_in_the_middle_become_separate_hunks() {
+        let base: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|line| line.to_string()).collect();
+        let hunks = compute_hunks(&base, &["a", "B", "c", "e", "f"]);
+        let kinds: Vec<_> = hunks.iter().map(|hunk| (hunk.kind, hunk.start_line, hunk.line_count)).collect();
+        assert_eq!(
+            kinds,
+            vec![(HunkKind::Modified, 2, 1), (HunkKind::Deleted, 4, 0), (HunkKind::Added, 5, 1)]
+        );
+    }
+
+    // A buffer open on `path` in the repository, tracked against `base`
+    async fn open(repo: &TempRepo, path: &str, content: &str, base: DiffBase) -> (GitGutter, BufferManager, usize) {
+        let gutter = GitGutter::new();
+        let buffers = BufferManager::new();
+        let id = buffers.create_buffer(content.to_string(), Some(repo.path().join(path)));
+        gutter.track(id, base, &buffers).await.unwrap();
+        (gutter, buffers, id)
+    }
+
+    #[tokio::test]
+    async fn stages_one_hunk_keeping_line_endings_and_mode() {
+        let repo = TempRepo::new().await;
+        repo.write("run.sh", "a\r\nb\r\nc\r\nd\r\n");
+        repo.git(&["add", "run.sh"]).await;
+        repo.git(&["update-index", "--chmod=+x", "run.sh"]).await;
+        repo.git(&["commit", "--message", "Add script"]).await;
+
+        let (gutter, buffers, id) = open(&repo, "run.sh", "a\r\nB\r\nc\r\nD\r\n", DiffBase::Index).await;
+        let diff = gutter.stage_hunk(id, 2, &buffers).await.unwrap();
+
+        assert_eq!(repo.git(&["show", ":run.sh"]).await, "a\r\nB\r\nc\r\nd\r\n");
+        assert!(repo.git(&["ls-files", "--stage", "run.sh"]).await.starts_with("100755 "));
+        // Against the index, only the unstaged hunk is left
+        let left: Vec<(usize, usize)> = diff.hunks.iter().map(|hunk| (hunk.start_line, hunk.line_count)).collect();
+        assert_eq!(left, vec![(4, 1)]);
+        assert!(gutter.stage_hunk(id, 2, &buffers).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn staging_keeps_a_missing_final_newline() {
+        let repo = TempRepo::new().await;
+        repo.write("notes.txt", "x\ny\nz");
+        repo.commit("Add notes").await;
+
+        let (gutter, buffers, id) = open(&repo, "notes.txt", "x\ny\nz\nextra", DiffBase::Head).await;
+        let diff = gutter.stage_hunk(id, 4, &buffers).await.unwrap();
+        assert_eq!(repo.git(&["show", ":notes.txt"]).await, "x\ny\nz\nextra");
+        // Head is still the base, so the staged line still shows
+        assert_eq!(diff.hunks.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stages_a_new_file() {
+        let repo = TempRepo::new().await;
+        repo.write("README", "readme\n");
+        repo.commit("Initial").await;
+        repo.write("new.txt", "one\ntwo\n");
+
+        let (gutter, buffers, id) = open(&repo, "new.txt", "one\ntwo\n", DiffBase::Index).await;
+        let diff = gutter.stage_hunk(id, 1, &buffers).await.unwrap();
+        assert_eq!(repo.git(&["show", ":new.txt"]).await, "one\ntwo\n");
+        assert!(diff.hunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reverts_the_last_line_without_a_newline() {
+        let repo = TempRepo::new().await;
+        repo.write("list.txt", "a\nb\nc");
+        repo.commit("Add list").await;
+
+        let (gutter, buffers, id) = open(&repo, "list.txt", "a\nb\nC", DiffBase::Head).await;
+        gutter.revert_hunk(id, 3, &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nb\nc");
+
+        buffers.apply_edit(id, 5, 5, "\nd").unwrap();
+        gutter.revert_hunk(id, 4, &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nb\nc");
+
+        buffers.apply_edit(id, 3, 5, "").unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nb");
+        gutter.revert_hunk(id, 2, &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nb\nc");
+    }
+
+    #[tokio::test]
+    async fn reverts_a_deletion() {
+        let repo = TempRepo::new().await;
+        repo.write("list.txt", "a\nb\nc\nd\n");
+        repo.commit("Add list").await;
+
+        let (gutter, buffers, id) = open(&repo, "list.txt", "a\nd\n", DiffBase::Head).await;
+        gutter.revert_hunk(id, 2, &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nb\nc\nd\n");
+        assert!(gutter.refresh(id, &buffers).unwrap().hunks.is_empty());
+
+        // An undoable edit like any other
+        buffers.undo_edit(id).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\nd\n");
+    }
+}
@@ -0,0 +1,441 @@
+// بسم الله الرحمن الرحيم
+// Line blame and file history for buffers, and past revisions opened as read-only buffers
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::buffer::BufferManager;
+use crate::git::{parse_log, repo_root, run_git, CommitInfo, FileState, DEFAULT_LOG_LIMIT, FIELD, RECORD};
+use crate::git_gutter::{compute_hunks, relative_path};
+
+// Like git.rs's log format, but with the separator first so --name-status output trails each record
+const HISTORY_FORMAT: &str = "--format=%x1e%H%x1f%h%x1f%P%x1f%an%x1f%ae%x1f%aI%x1f%cI%x1f%s%x1f%b%x1f";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlameCommit {
+    pub hash: String,
+    pub short_hash: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub author_date: DateTime<FixedOffset>,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlameLine {
+    // 1-based line in the buffer
+    pub line: usize,
+    // None for lines added or changed since HEAD, saved or not
+    pub commit: Option<String>,
+    // The line's number in that commit's version of the file
+    pub original_line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferBlame {
+    pub buffer_id: usize,
+    // Relative to the repository root
+    pub path: String,
+    pub lines: Vec<BlameLine>,
+    // Keyed by hash, so each commit is sent once however many lines it owns
+    pub commits: HashMap<String, BlameCommit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRevision {
+    pub commit: CommitInfo,
+    // The file's path in this commit, which differs from today's across renames
+    pub path: String,
+    pub state: FileState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileHistory {
+    pub root: String,
+    pub path: String,
+    // Newest first
+    pub revisions: Vec<FileRevision>,
+}
+
+// A HEAD line's commit and its line number there, when blame covered it
+type BlamedLine = Option<(String, usize)>;
+
+// Where a buffer's file sits: the repository root and the path relative to it
+pub(crate) async fn buffer_location(buffer_id: usize, buffers: &BufferManager) -> Result<(std::path::PathBuf, String), String> {
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let file = buffer.path.ok_or_else(|| "Buffer has no file path".to_string())?;
+    let root = repo_root(file.parent().unwrap_or(&file)).await?;
+    let path = relative_path(&root, &file)?;
+    Ok((root, path))
+}
+
+// Blame is taken at HEAD, then carried over to the buffer through a diff of HEAD against its
+// current content, so unsaved edits shift the blamed lines instead of misattributing them
+pub async fn blame(buffer_id: usize, buffers: &BufferManager) -> Result<BufferBlame, String> {
+    let (root, path) = buffer_location(buffer_id, buffers).await?;
+
+    // A file that isn't committed yet has no HEAD version, and every line is new
+    let (head_lines, blamed, commits) = match run_git(&root, &["show", &format!("HEAD:{}", path)]).await {
+        Ok(head_text) => {
+            let output = run_git(&root, &["blame", "--porcelain", "HEAD", "--", &path]).await?;
+            let (blamed, commits) = parse_blame(&output);
+            (head_text.lines().map(str::to_string).collect(), blamed, commits)
+        }
+        Err(_) => (Vec::new(), Vec::new(), HashMap::new()),
+    };
+
+    let buffer = buffers.get_buffer(buffer_id).ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let buffer_lines: Vec<&str> = buffer.content.lines().collect();
+
+    // The HEAD line each buffer line came from
+    let mut origin: Vec<Option<usize>> = Vec::with_capacity(buffer_lines.len());
+    let mut head_line = 0;
+    for hunk in compute_hunks(&head_lines, &buffer_lines) {
+        while origin.len() < hunk.start_line - 1 {
+            origin.push(Some(head_line));
+            head_line += 1;
+        }
+        origin.resize(origin.len() + hunk.line_count, None);
+        head_line += hunk.base_lines.len();
+    }
+    while origin.len() < buffer_lines.len() {
+        origin.push(Some(head_line));
+        head_line += 1;
+    }
+
+    let lines = origin
+        .into_iter()
+        .enumerate()
+        .map(|(index, head_line)| {
+            let blamed = head_line.and_then(|head_line| blamed.get(head_line)).and_then(Option::as_ref);
+            BlameLine {
+                line: index + 1,
+                commit: blamed.map(|(hash, _)| hash.clone()),
+                original_line: blamed.map(|(_, original_line)| *original_line),
+            }
+        })
+        .collect();
+
+    Ok(BufferBlame {
+        buffer_id,
+        path,
+        lines,
+        commits,
+    })
+}
+
+// `git blame --porcelain` gives a header line per blamed line, commit details the first time a
+// commit appears, and the line's text after a tab. Returns (hash, original line) by final line.
+fn parse_blame(output: &str) -> (Vec<BlamedLine>, HashMap<String, BlameCommit>) {
+    let mut blamed: Vec<BlamedLine> = Vec::new();
+    let mut commits: HashMap<String, BlameCommit> = HashMap::new();
+    let mut details: HashMap<&str, &str> = HashMap::new();
+    let mut current: Option<(&str, usize, usize)> = None;
+
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            // The line's text ends the entry
+            let Some((hash, original, final_line)) = current.take() else {
+                continue;
+            };
+            if !commits.contains_key(hash) {
+                let author_date = details
+                    .get("author-time")
+                    .zip(details.get("author-tz"))
+                    .and_then(|(time, tz)| parse_timestamp(time, tz));
+                if let Some(author_date) = author_date {
+                    commits.insert(
+                        hash.to_string(),
+                        BlameCommit {
+                            hash: hash.to_string(),
+                            short_hash: hash.chars().take(7).collect(),
+                            author_name: details.get("author").unwrap_or(&"").to_string(),
+                            author_email: details
+                                .get("author-mail")
+                                .unwrap_or(&"")
+                                .trim_start_matches('<')
+                                .trim_end_matches('>')
+                                .to_string(),
+                            author_date,
+                            summary: details.get("summary").unwrap_or(&"").to_string(),
+                        },
+                    );
+                }
+            }
+            details.clear();
+
+            if final_line > 0 {
+                if blamed.len() < final_line {
+                    blamed.resize(final_line, None);
+                }
+                blamed[final_line - 1] = Some((hash.to_string(), original));
+            }
+        } else if current.is_none() {
+            let mut parts = line.split_whitespace();
+            let hash = parts.next();
+            let original = parts.next().and_then(|n| n.parse().ok());
+            let final_line = parts.next().and_then(|n| n.parse().ok());
+            if let (Some(hash), Some(original), Some(final_line)) = (hash, original, final_line) {
+                current = Some((hash, original, final_line));
+            }
+        } else if let Some((key, value)) = line.split_once(' ') {
+            details.insert(key, value);
+        }
+    }
+    (blamed, commits)
+}
+
+// Blame gives seconds since the epoch and a "+0530"-style zone
+fn parse_timestamp(time: &str, tz: &str) -> Option<DateTime<FixedOffset>> {
+    let seconds: i64 = time.parse().ok()?;
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let digits = tz.trim_start_matches(['+', '-']);
+    let hours: i32 = digits.get(..2)?.parse().ok()?;
+    let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+    let offset = FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?;
+    offset.timestamp_opt(seconds, 0).single()
+}
+
+pub async fn file_history(root: &Path, path: &str, limit: usize) -> Result<Vec<FileRevision>, String> {
+    let limit = format!("--max-count={}", limit);
+    let args = ["log", HISTORY_FORMAT, limit.as_str(), "--follow", "--name-status", "--", path];
+    let output = match run_git(root, &args).await {
+        Ok(output) => output,
+        Err(e) if e.contains("does not have any commits") => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(parse_history(&output, path))
+}
+
+fn parse_history(output: &str, path: &str) -> Vec<FileRevision> {
+    output
+        .split(RECORD)
+        .filter_map(|record| {
+            // The body is the last field; whatever follows its separator is the --name-status output
+            let (entry, changes) = record.rsplit_once(FIELD)?;
+            let commit = parse_log(entry).into_iter().next()?;
+            // "M\tpath", or "R100\told\tnew" for a rename
+            let change = changes.lines().find(|line| !line.trim().is_empty());
+            let (state, revision_path) = match change {
+                Some(change) => {
+                    let mut parts = change.split('\t');
+                    let code = parts.next().and_then(|code| code.chars().next()).unwrap_or('M');
+                    let revision_path = parts.next_back().unwrap_or(path).to_string();
+                    (FileState::from_code(code), revision_path)
+                }
+                None => (FileState::Modified, path.to_string()),
+            };
+            Some(FileRevision {
+                commit,
+                path: revision_path,
+                state,
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub async fn git_blame(buffer_id: usize, buffer_manager: tauri::State<'_, BufferManager>) -> Result<BufferBlame, String> {
+    blame(buffer_id, &buffer_manager).await
+}
+
+#[tauri::command]
+pub async fn git_file_history(
+    buffer_id: usize,
+    limit: Option<usize>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<FileHistory, String> {
+    let (root, path) = buffer_location(buffer_id, &buffer_manager).await?;
+    let revisions = file_history(&root, &path, limit.unwrap_or(DEFAULT_LOG_LIMIT)).await?;
+    Ok(FileHistory {
+        root: root.display().to_string(),
+        path,
+        revisions,
+    })
+}
+
+// Opens `path` as of `revision` in a new read-only buffer and returns its id
+pub async fn open_revision(root: &Path, revision: &str, path: &str, buffers: &BufferManager) -> Result<usize, String> {
+    // Resolved on its own first, so a revision can't be read as an option or carry a path of its own
+    let spec = format!("{}^{{commit}}", revision);
+    let hash = run_git(root, &["rev-parse", "--verify", "--quiet", "--end-of-options", &spec])
+        .await
+        .map_err(|_| format!("Unknown revision '{}'", revision))?;
+    let hash = hash.trim();
+    let content = run_git(root, &["show", &format!("{}:{}", hash, path)]).await?;
+
+    // Named apart from the working file, so the language server and Copilot, which key documents
+    // by path, don't take it for the open file itself; the language still comes from the real name
+    let file = root.join(path);
+    let short_hash = hash.get(..7).unwrap_or(hash);
+    let name = format!(
+        "{}@{}",
+        file.file_name().map(|name| name.to_string_lossy()).unwrap_or_default(),
+        short_hash
+    );
+    let language = BufferManager::detect_language(&Some(file.clone()), &content);
+    let id = buffers.create_read_only_buffer(content, Some(file.with_file_name(name)));
+    buffers.set_language(id, language)?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn git_open_revision(
+    repo_path: String,
+    revision: String,
+    path: String,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<usize, String> {
+    let root = repo_root(Path::new(&repo_path)).await?;
+    open_revision(&root, &revision, &path, &buffer_manager).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::TempRepo;
+
+    async fn repo_with_history() -> TempRepo {
+        let repo = TempRepo::new().await;
+        for (message, content) in [("first", "fn main() {}\n"), ("second", "fn main() { run(); }\n")] {
+            repo.write("main.rs", content);
+            repo.commit(message).await;
+        }
+        repo
+    }
+
+    // Three lines from a first commit, then one of them changed in a second
+    async fn repo_with_blame() -> (TempRepo, String, String) {
+        let repo = TempRepo::new().await;
+        repo.write("lib.rs", "one\ntwo\nthree\n");
+        repo.commit("Add lib").await;
+        let first = repo.git(&["rev-parse", "HEAD"]).await.trim().to_string();
+        repo.write("lib.rs", "one\nTWO\nthree\n");
+        repo.commit("Shout two").await;
+        let second = repo.git(&["rev-parse", "HEAD"]).await.trim().to_string();
+        (repo, first, second)
+    }
+
+    #[tokio::test]
+    async fn parses_porcelain_blame() {
+        let (repo, first, second) = repo_with_blame().await;
+        let output = repo.git(&["blame", "--porcelain", "HEAD", "--", "lib.rs"]).await;
+
+        let (blamed, commits) = parse_blame(&output);
+        assert_eq!(
+            blamed,
+            vec![Some((first.clone(), 1)), Some((second.clone(), 2)), Some((first.clone(), 3))]
+        );
+        assert_eq!(commits.len(), 2);
+
+        let commit = &commits[&second];
+        assert_eq!(commit.short_hash, second[..7]);
+        assert_eq!(commit.author_name, "Test Author");
+        assert_eq!(commit.author_email, "author@example.com");
+        assert_eq!(commit.summary, "Shout two");
+        assert_eq!(commits[&first].summary, "Add lib");
+    }
+
+    #[tokio::test]
+    async fn unsaved_lines_above_shift_the_blame_down() {
+        let (repo, first, second) = repo_with_blame().await;
+        let buffers = BufferManager::new();
+        let id = buffers.create_buffer(
+            "// new\n// also new\none\nTWO\nchanged\n".to_string(),
+            Some(repo.path().join("lib.rs")),
+        );
+
+        let blame = blame(id, &buffers).await.unwrap();
+        assert_eq!(blame.path, "lib.rs");
+        let lines: Vec<(usize, Option<&str>, Option<usize>)> = blame
+            .lines
+            .iter()
+            .map(|line| (line.line, line.commit.as_deref(), line.original_line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, None, None),
+                (2, None, None),
+                (3, Some(first.as_str()), Some(1)),
+                (4, Some(second.as_str()), Some(2)),
+                (5, None, None),
+            ]
+        );
+        assert_eq!(blame.commits.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn uncommitted_files_are_all_new() {
+        let repo = repo_with_history().await;
+        repo.write("new.rs", "fn new() {}\n");
+        let buffers = BufferManager::new();
+        let id = buffers.create_buffer("fn new() {}\n".to_string(), Some(repo.path().join("new.rs")));
+
+        let blame = blame(id, &buffers).await.unwrap();
+        assert_eq!(blame.lines.len(), 1);
+        assert_eq!(blame.lines[0].commit, None);
+        assert!(blame.commits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_follows_renames() {
+        let repo = TempRepo::new().await;
+        repo.write("old.rs", "fn a() {}\n");
+        repo.commit("Add old").await;
+        repo.write("old.rs", "fn a() {}\nfn b() {}\n");
+        repo.commit("Grow old").await;
+        repo.git(&["mv", "old.rs", "new.rs"]).await;
+        repo.commit("Rename to new").await;
+
+        let revisions = file_history(repo.path(), "new.rs", 10).await.unwrap();
+        let summary: Vec<(&str, &str, FileState)> = revisions
+            .iter()
+            .map(|revision| (revision.commit.subject.as_str(), revision.path.as_str(), revision.state))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Rename to new", "new.rs", FileState::Renamed),
+                ("Grow old", "old.rs", FileState::Modified),
+                ("Add old", "old.rs", FileState::Added),
+            ]
+        );
+        assert_eq!(revisions[2].commit.parents.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn history_of_a_repository_without_commits_is_empty() {
+        let repo = TempRepo::new().await;
+        repo.write("main.rs", "fn main() {}\n");
+        assert!(file_history(repo.path(), "main.rs", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn opens_a_past_revision_apart_from_the_working_file() {
+        let repo = repo_with_history().await;
+        let buffers = BufferManager::new();
+
+        let id = open_revision(repo.path(), "HEAD~1", "main.rs", &buffers).await.unwrap();
+        let buffer = buffers.get_buffer(id).unwrap();
+        assert_eq!(buffer.content, "fn main() {}\n");
+        assert!(buffer.read_only);
+        assert_eq!(buffer.language.as_deref(), Some("rust"));
+        let path = buffer.path.unwrap();
+        assert_ne!(path, repo.path().join("main.rs"));
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("main.rs@"));
+    }
+
+    #[tokio::test]
+    async fn rejects_revisions_that_arent_commits() {
+        let repo = repo_with_history().await;
+        let buffers = BufferManager::new();
+
+        for revision in ["--output=/tmp/x", "HEAD:main.rs", "no-such-branch"] {
+            assert!(open_revision(repo.path(), revision, "main.rs", &buffers).await.is_err(), "{}", revision);
+        }
+        assert!(buffers.list_buffers().is_empty());
+    }
+}
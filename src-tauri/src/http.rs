@@ -0,0 +1,489 @@
+// بسم الله الرحمن الرحيم
+// Shared HTTP client for model and search APIs: pooled connections, timeouts, retries and typed errors
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A Retry-After longer than this is a quota reset, not a hiccup worth waiting out
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub connect_timeout_secs: u64,
+    // Whole request for plain calls; time to first byte and between chunks when streaming
+    pub request_timeout_secs: u64,
+    pub max_retries: u32,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            request_timeout_secs: 120,
+            max_retries: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AiError {
+    // Missing, invalid or unauthorized API key
+    Auth(String),
+    // Rate limit or usage quota still exceeded after retrying
+    Quota(String),
+    // Couldn't connect, timed out or the connection dropped
+    Network(String),
+    // The provider refused to answer on content grounds
+    SafetyBlocked(String),
+    // The response couldn't be parsed
+    Malformed(String),
+    // Any other error status from the API
+    Api(String),
+    // A configured usage budget is used up; nothing was sent
+    BudgetExceeded(String),
+    // Bad request or provider setup, caught before anything was sent
+    Config(String),
+    Cancelled,
+}
+
+impl AiError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AiError::Auth(_) => "auth",
+            AiError::Quota(_) => "quota",
+            AiError::Network(_) => "network",
+            AiError::SafetyBlocked(_) => "safety_blocked",
+            AiError::Malformed(_) => "malformed",
+            AiError::Api(_) => "api",
+            AiError::BudgetExceeded(_) => "budget_exceeded",
+            AiError::Config(_) => "config",
+            AiError::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiError::Auth(message) => write!(f, "Authentication failed: {}", message),
+            AiError::Quota(message) => write!(f, "Rate limit or quota exceeded: {}", message),
+            AiError::Network(message) => write!(f, "Network error: {}", message),
+            AiError::SafetyBlocked(message) => write!(f, "Blocked by the provider's safety filters: {}", message),
+            AiError::Malformed(message) => write!(f, "Unexpected response: {}", message),
+            AiError::Api(message) => write!(f, "API error: {}", message),
+            AiError::BudgetExceeded(message) => write!(f, "Usage budget exceeded: {}", message),
+            AiError::Config(message) => write!(f, "{}", message),
+            AiError::Cancelled => write!(f, "Request cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AiError {}
+
+impl From<AiError> for String {
+    fn from(error: AiError) -> Self {
+        error.to_string()
+    }
+}
+
+impl From<reqwest::Error> for AiError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            AiError::Malformed(error.to_string())
+        } else {
+            AiError::Network(error.to_string())
+        }
+    }
+}
+
+struct HttpClient {
+    // reqwest::Client is a handle to a shared pool, so clones reuse connections
+    client: RwLock<reqwest::Client>,
+    settings: RwLock<HttpSettings>,
+}
+
+static HTTP: Lazy<HttpClient> = Lazy::new(|| {
+    let settings = HttpSettings::default();
+    HttpClient {
+        client: RwLock::new(build_client(&settings)),
+        settings: RwLock::new(settings),
+    }
+});
+
+fn build_client(settings: &HttpSettings) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_secs.max(1)))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to build HTTP client, using defaults: {}", e);
+            reqwest::Client::new()
+        })
+}
+
+pub fn configure(settings: &HttpSettings) {
+    let mut current = HTTP.settings.write();
+    if *current == *settings {
+        return;
+    }
+    // Only the connect timeout lives on the client; the rest is read per request
+    if current.connect_timeout_secs != settings.connect_timeout_secs {
+        *HTTP.client.write() = build_client(settings);
+    }
+    *current = settings.clone();
+}
+
+pub fn client() -> reqwest::Client {
+    HTTP.client.read().clone()
+}
+
+pub fn settings() -> HttpSettings {
+    HTTP.settings.read().clone()
+}
+
+// Send a request, retrying 429s, 5xx and dropped connections with backoff. A POST that timed out
+// may still have been acted on, so it's only retried when it never connected.
+// `build` is called once per attempt since a RequestBuilder can't be reused.
+pub async fn send<F>(build: F, streaming: bool) -> Result<Response, AiError>
+where
+    F: Fn(&reqwest::Client) -> RequestBuilder,
+{
+    let settings = settings();
+    let timeout = Duration::from_secs(settings.request_timeout_secs.max(1));
+    let mut attempt = 0;
+
+    loop {
+        let (client, request) = build(&client()).build_split();
+        let mut request = request.map_err(|e| AiError::Config(format!("Invalid request: {}", e)))?;
+        // A total timeout would cut off long streams, so those only time out waiting for headers
+        if !streaming {
+            *request.timeout_mut() = Some(timeout);
+        }
+        let idempotent = request.method().is_idempotent();
+
+        // None means the timeout fired before any response
+        let result: Result<Response, Option<reqwest::Error>> =
+            match tokio::time::timeout(timeout, client.execute(request)).await {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(e)) => Err(Some(e)),
+                Err(_) => Err(None),
+            };
+
+        let retry_delay = match &result {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) if is_retryable(response.status()) => Some(retry_after(response).unwrap_or_else(|| backoff(attempt))),
+            Ok(_) => None,
+            Err(Some(e)) if e.is_connect() => Some(backoff(attempt)),
+            Err(Some(e)) if idempotent && (e.is_timeout() || e.is_request()) => Some(backoff(attempt)),
+            Err(None) if idempotent => Some(backoff(attempt)),
+            Err(_) => None,
+        };
+
+        match retry_delay {
+            Some(delay) if attempt < settings.max_retries && delay <= MAX_RETRY_AFTER => {
+                attempt += 1;
+                log::debug!("Retrying request in {:?} (attempt {})", delay, attempt);
+                tokio::time::sleep(delay).await;
+            }
+            _ => {
+                return match result {
+                    Ok(response) => check_status(response).await,
+                    Err(Some(e)) => Err(e.into()),
+                    Err(None) => Err(AiError::Network(format!(
+                        "No response after {} seconds",
+                        timeout.as_secs()
+                    ))),
+                };
+            }
+        }
+    }
+}
+
+// Append the next piece of a streamed body to `buffer`; false once the body has ended.
+// A stream that goes quiet for the request timeout is treated as dropped.
+pub async fn read_chunk(response: &mut Response, buffer: &mut Vec<u8>) -> Result<bool, AiError> {
+    let timeout = Duration::from_secs(settings().request_timeout_secs.max(1));
+    match tokio::time::timeout(timeout, response.chunk()).await {
+        Ok(Ok(Some(chunk))) => {
+            buffer.extend_from_slice(&chunk);
+            Ok(true)
+        }
+        Ok(Ok(None)) => Ok(false),
+        Ok(Err(e)) => Err(AiError::Network(format!("Stream interrupted: {}", e))),
+        Err(_) => Err(AiError::Network(format!(
+            "Stream stalled for {} seconds",
+            timeout.as_secs()
+        ))),
+    }
+}
+
+pub async fn read_json(response: Response) -> Result<serde_json::Value, AiError> {
+    let text = response.text().await?;
+    serde_json::from_str(&text).map_err(|e| AiError::Malformed(format!("Invalid JSON: {}", e)))
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+// Exponential backoff with jitter so parallel requests don't retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 4);
+    delay + Duration::from_millis(jitter)
+}
+
+async fn check_status(response: Response) -> Result<Response, AiError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = error_message(&body).unwrap_or_else(|| {
+        if body.trim().is_empty() {
+            status.to_string()
+        } else {
+            format!("{}: {}", status, body.trim())
+        }
+    });
+    let lower = message.to_lowercase();
+
+    Err(match status.as_u16() {
+        401 | 403 => AiError::Auth(message),
+        429 => AiError::Quota(message),
+        // Gemini answers a bad key with 400 INVALID_ARGUMENT
+        400 if lower.contains("api key") => AiError::Auth(message),
+        400 if lower.contains("safety") || lower.contains("content filter") || lower.contains("content_filter") => {
+            AiError::SafetyBlocked(message)
+        }
+        _ => AiError::Api(message),
+    })
+}
+
+// The human-readable part of the usual {"error": {"message": ...}} shapes
+fn error_message(body: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    let error = json.get("error")?;
+    error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .or_else(|| error.as_str())
+        .map(|m| m.to_string())
+}
+
+// A scripted HTTP server for the provider tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // Small enough to cut stream lines, and the characters in them, across reads
+    const CHUNK: usize = 7;
+
+    pub(crate) struct Reply {
+        pub status: u16,
+        pub content_type: &'static str,
+        // Sent as well as the content type, e.g. Retry-After
+        pub headers: Vec<(&'static str, String)>,
+        pub body: String,
+    }
+
+    pub(crate) fn json_reply(body: serde_json::Value) -> Reply {
+        Reply {
+            status: 200,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    pub(crate) fn stream_reply(content_type: &'static str, body: &str) -> Reply {
+        Reply {
+            status: 200,
+            content_type,
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    pub(crate) struct Recorded {
+        pub path: String,
+        pub headers: HashMap<String, String>,
+        pub body: serde_json::Value,
+    }
+
+    // Answers one connection per scripted reply, then hands back what each request carried
+    pub(crate) async fn mock_server(replies: Vec<Reply>) -> (String, JoinHandle<Vec<Recorded>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let mut recorded = Vec::new();
+            for reply in replies {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).await.unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(':') else {
+                        break;
+                    };
+                    headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+                }
+
+                let length = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                recorded.push(Recorded {
+                    path,
+                    headers,
+                    body: serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+                });
+
+                // A client that stops reading early may hang up mid-reply, so write errors are fine
+                let stream = stream.get_mut();
+                let mut head = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n",
+                    reply.status, reply.content_type
+                );
+                for (name, value) in &reply.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str("\r\n");
+                let _ = stream.write_all(head.as_bytes()).await;
+                for piece in reply.body.as_bytes().chunks(CHUNK) {
+                    let _ = stream.write_all(format!("{:x}\r\n", piece.len()).as_bytes()).await;
+                    let _ = stream.write_all(piece).await;
+                    let _ = stream.write_all(b"\r\n").await;
+                    let _ = stream.flush().await;
+                    tokio::task::yield_now().await;
+                }
+                let _ = stream.write_all(b"0\r\n\r\n").await;
+            }
+            recorded
+        });
+
+        (base_url, server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{json_reply, mock_server, Reply};
+    use super::*;
+    use std::time::Instant;
+
+    fn error_reply(status: u16, retry_after: Option<&str>, body: serde_json::Value) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            headers: retry_after.map(|value| ("Retry-After", value.to_string())).into_iter().collect(),
+            body: body.to_string(),
+        }
+    }
+
+    async fn error_for(status: u16, body: serde_json::Value) -> AiError {
+        let (base_url, _server) = mock_server(vec![error_reply(status, None, body)]).await;
+        send(|client| client.get(&base_url), false).await.unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limits_and_server_errors() {
+        let (base_url, server) = mock_server(vec![
+            error_reply(503, Some("0"), serde_json::Value::Null),
+            error_reply(429, Some("0"), serde_json::Value::Null),
+            json_reply(serde_json::json!({ "ok": true })),
+        ])
+        .await;
+
+        let response = send(|client| client.post(&base_url).json(&serde_json::json!({})), false).await.unwrap();
+        assert_eq!(read_json(response).await.unwrap()["ok"], true);
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn waits_as_long_as_retry_after_asks() {
+        let (base_url, _server) = mock_server(vec![
+            error_reply(429, Some("1"), serde_json::Value::Null),
+            json_reply(serde_json::json!({})),
+        ])
+        .await;
+
+        let started = Instant::now();
+        send(|client| client.get(&base_url), false).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn a_long_retry_after_is_reported_as_quota() {
+        let body = serde_json::json!({ "error": { "message": "Daily quota used up" } });
+        let (base_url, server) = mock_server(vec![error_reply(429, Some("3600"), body)]).await;
+
+        match send(|client| client.get(&base_url), false).await {
+            Err(AiError::Quota(message)) => assert_eq!(message, "Daily quota used up"),
+            other => panic!("expected a quota error, got {:?}", other.map(|r| r.status())),
+        }
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn maps_error_statuses() {
+        let error = error_for(401, serde_json::json!({ "error": "bad key" })).await;
+        assert!(matches!(error, AiError::Auth(message) if message == "bad key"));
+
+        let error = error_for(400, serde_json::json!({ "error": { "message": "API key not valid" } })).await;
+        assert!(matches!(error, AiError::Auth(_)));
+
+        let error = error_for(400, serde_json::json!({ "error": { "message": "Blocked by safety settings" } })).await;
+        assert!(matches!(error, AiError::SafetyBlocked(_)));
+
+        let error = error_for(404, serde_json::json!({ "error": { "message": "No such model" } })).await;
+        assert!(matches!(error, AiError::Api(message) if message == "No such model"));
+    }
+
+    #[tokio::test]
+    async fn unparseable_bodies_are_malformed() {
+        let (base_url, _server) = mock_server(vec![Reply {
+            status: 200,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: "{\"truncated".to_string(),
+        }])
+        .await;
+
+        let response = send(|client| client.get(&base_url), false).await.unwrap();
+        assert!(matches!(read_json(response).await, Err(AiError::Malformed(_))));
+    }
+}
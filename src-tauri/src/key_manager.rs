@@ -18,14 +18,6 @@ pub struct KeyBinding {
     pub context: String, // "global", "editor", "command_bar", etc.
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct KeyEvent {
-    pub key: String,
-    pub modifiers: Vec<String>,
-    pub action: String, // "press", "release"
-    pub timestamp: u64,
-}
-
 #[derive(Clone)]
 pub struct KeyManager {
     bindings: Arc<RwLock<HashMap<String, KeyBinding>>>,
@@ -157,56 +149,6 @@ impl KeyManager {
 
     // With GlobalShortcutManager we map chords directly when registering
 
-    // Not used with GlobalShortcutManager
-
-    fn execute_action(&self, action: &str) {
-        if let Some(app_handle) = self.app_handle.lock().unwrap().as_ref() {
-            match action {
-                "toggle_command_bar" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "toggle_command_bar"
-                    }));
-                }
-                "toggle_help" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "toggle_help"
-                    }));
-                }
-                "escape" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "escape"
-                    }));
-                }
-                "save_file" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "save_file"
-                    }));
-                }
-                "new_file" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "new_file"
-                    }));
-                }
-                "open_file" => {
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": "open_file"
-                    }));
-                }
-                _ => {
-                    // Custom action
-                    let _ = app_handle.emit_all("key_action", serde_json::json!({
-                        "action": action
-                    }));
-                }
-            }
-        }
-    }
-
-    fn emit_key_event(&self, event: KeyEvent) {
-        if let Some(app_handle) = self.app_handle.lock().unwrap().as_ref() {
-            let _ = app_handle.emit_all("key_event", event);
-        }
-    }
     // Keycode to string no longer used
 
     pub fn register_default_bindings(&self) {
@@ -299,7 +241,7 @@ impl KeyManager {
         self.start_monitoring();
     }
 
-    fn binding_to_hotkey(keys: &Vec<String>) -> Option<String> {
+    fn binding_to_hotkey(keys: &[String]) -> Option<String> {
         if keys.is_empty() { return None; }
         let mut mods = Vec::new();
         let mut last = String::new();
@@ -315,7 +257,7 @@ impl KeyManager {
             else if mm == "alt" || mm == "option" { parts.push("Alt".into()); }
             else if mm == "shift" { parts.push("Shift".into()); }
         }
-        let key = if last.len() == 1 { last.to_uppercase() } else { last.to_uppercase() };
+        let key = last.to_uppercase();
         parts.push(key);
         Some(parts.join("+"))
     }
@@ -327,18 +269,6 @@ impl KeyManager {
     fn platform_cmd_ctrl(key: &str) -> String {
         if cfg!(target_os = "macos") { format!("Command+{}", key) } else { format!("Control+{}", key) }
     }
-
-    fn action_for_id(id: &str) -> Option<&'static str> {
-        match id {
-            "command_bar" | "command_bar_ctrl" => Some("toggle_command_bar"),
-            "help" => Some("toggle_help"),
-            "escape" => Some("escape"),
-            "save_file" | "save_file_ctrl" => Some("save_file"),
-            "new_file" | "new_file_ctrl" => Some("new_file"),
-            "open_file" | "open_file_ctrl" => Some("open_file"),
-            _ => None,
-        }
-    }
 }
 
 // Tauri commands
@@ -28,13 +28,6 @@ pub struct Position {
     pub character: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Location {
-    pub file_path: String,
-    pub line: u32,
-    pub column: u32,
-}
-
 pub struct LspManager {
     // Map of language -> server process info
     servers: Arc<RwLock<HashMap<String, ServerInfo>>>,
@@ -15,18 +15,34 @@ mod commands;
 mod hotkeys;
 mod key_manager;
 mod command_processor;
-<<<<<<< Updated upstream
-=======
 mod perplexity;
 mod git;
 mod lsp;
 mod copilot;
->>>>>>> Stashed changes
+mod framing;
+mod dap;
+mod ai;
+mod conversation;
+mod ai_context;
+mod ai_edits;
+mod secrets;
+mod http;
+mod usage;
+mod citations;
+mod docs;
+mod git_gutter;
+mod git_history;
+mod conflicts;
 
 use buffer::BufferManager;
 use api::ApiKeyStore;
 use config::FirstRunStore;
 use key_manager::KeyManager;
+use dap::DapManager;
+use ai::{AiProviderStore, AiStreamManager};
+use conversation::ConversationStore;
+use ai_edits::AiEditManager;
+use secrets::SecretStore;
 
 // Store CLI args for later use
 struct CliArgs {
@@ -69,6 +85,9 @@ fn main() {
     // Initialize key manager
     let key_manager = KeyManager::new();
     
+    // Initialize debug adapter manager
+    let dap_manager = DapManager::new();
+    
     // Get command line arguments
     let args: Vec<String> = env::args().collect();
     let file_to_open = if args.len() > 1 {
@@ -88,15 +107,59 @@ fn main() {
         .manage(cli_args)
         .manage(hotkey_manager.clone())
         .manage(key_manager.clone())
+        .manage(dap_manager)
+        .manage(lsp::LspManager::new())
+        .manage(AiStreamManager::new())
+        .manage(AiEditManager::new())
+        .manage(git_gutter::GitGutter::new())
         .setup(move |app| {
+            // Create secret store shared by everything that holds API keys
+            let secret_store = std::sync::Arc::new(SecretStore::new(&app.handle()));
+            app.manage(secret_store.clone());
+            
             // Create API key store
-            let api_key_store = ApiKeyStore::new(&app.handle());
+            let api_key_store = ApiKeyStore::new(&app.handle(), secret_store.clone());
             app.manage(api_key_store);
             
+            // Create Perplexity key store
+            let perplexity_key_store = perplexity::PerplexityKeyStore::new(&app.handle(), secret_store.clone());
+            app.manage(perplexity_key_store);
+            
+            // Create web search cache
+            let search_cache = perplexity::SearchCache::new(&app.handle());
+            app.manage(search_cache);
+            
+            // Create offline documentation index
+            let docs_index = std::sync::Arc::new(docs::DocsIndex::new(&app.handle()));
+            let loading = docs_index.clone();
+            tauri::async_runtime::spawn_blocking(move || loading.load());
+            app.manage(docs_index);
+            
+            // Create usage ledger shared by every paid AI request
+            let usage_ledger = std::sync::Arc::new(usage::UsageLedger::new(&app.handle()));
+            app.manage(usage_ledger.clone());
+            
+            // Create AI provider store
+            let ai_provider_store = AiProviderStore::new(&app.handle(), secret_store, usage_ledger);
+            app.manage(ai_provider_store);
+            
+            // Create conversation store
+            let conversation_store = ConversationStore::new(&app.handle());
+            app.manage(conversation_store);
+            
             // Create first run store
             let first_run_store = FirstRunStore::new(&app.handle());
             app.manage(first_run_store);
             
+            // Keep git gutters current as buffers are edited
+            let gutter_handle = app.handle();
+            app.state::<BufferManager>().on_change(move |buffer_id| {
+                let gutter = gutter_handle.state::<git_gutter::GitGutter>();
+                if let Some(diff) = gutter.refresh(buffer_id, gutter_handle.state::<BufferManager>().inner()) {
+                    let _ = gutter_handle.emit_all("git_gutter", diff);
+                }
+            });
+            
             // Get main window
             let main_window = app.get_window("main").unwrap();
             
@@ -138,10 +201,12 @@ fn main() {
             buffer::list_buffers,
             buffer::apply_edit,
             buffer::update_cursor_position,
+            buffer::update_selection,
             buffer::update_scroll_position,
             buffer::search_in_buffer,
             buffer::replace_in_buffer,
             buffer::get_edit_history,
+            buffer::undo_edit,
             buffer::save_file,
             buffer::update_buffer_content_command,
             buffer::close_buffer,
@@ -152,6 +217,44 @@ fn main() {
             api::set_api_key,
             api::send_chat_message,
             
+            // Secret commands
+            secrets::list_secrets,
+            secrets::set_secret,
+            secrets::delete_secret,
+            
+            // AI provider commands
+            ai::get_ai_providers,
+            ai::set_ai_provider,
+            ai::remove_ai_provider,
+            ai::set_default_ai_provider,
+            ai::set_ai_http_settings,
+            ai::ai_chat,
+            ai::ai_chat_stream,
+            ai::ai_cancel_stream,
+            
+            // AI usage commands
+            usage::get_usage_summary,
+            usage::get_usage_records,
+            usage::get_usage_settings,
+            usage::set_usage_settings,
+            
+            // Editor-context AI commands
+            ai_context::ai_get_editor_context,
+            ai_context::ai_editor_action,
+            ai_edits::ai_propose_edits,
+            ai_edits::ai_apply_edits,
+            ai_edits::ai_discard_edits,
+            
+            // Conversation commands
+            conversation::conversation_list,
+            conversation::conversation_get,
+            conversation::conversation_create,
+            conversation::conversation_update_settings,
+            conversation::conversation_fork,
+            conversation::conversation_delete,
+            conversation::conversation_resume,
+            conversation::conversation_send,
+            
             // Config commands
             config::get_command_suggestions,
             config::check_has_run_before,
@@ -173,12 +276,18 @@ fn main() {
             command_processor::get_enhanced_command_suggestions,
             command_processor::validate_command,
             
-<<<<<<< Updated upstream
-=======
             // Perplexity search
             perplexity::get_perplexity_key,
             perplexity::set_perplexity_key,
             perplexity::search_web,
+            perplexity::clear_search_cache,
+            perplexity::insert_search_answer,
+            
+            // Offline documentation commands
+            docs::docs_status,
+            docs::docs_set_sources,
+            docs::docs_reindex,
+            docs::search_docs,
             
             // Git commands
             git::git_status,
@@ -192,6 +301,16 @@ fn main() {
             git::git_log,
             git::git_init,
             git::git_clone,
+            git_gutter::git_buffer_diff,
+            git_gutter::git_stage_hunk,
+            git_gutter::git_revert_hunk,
+            git_history::git_blame,
+            git_history::git_file_history,
+            git_history::git_open_revision,
+            conflicts::get_conflicts,
+            conflicts::resolve_conflict,
+            conflicts::git_merge_view,
+            conflicts::git_mark_resolved,
             
             // LSP commands
             lsp::start_lsp_server,
@@ -205,13 +324,39 @@ fn main() {
             copilot::copilot_start_server,
             copilot::copilot_stop_server,
             copilot::copilot_get_status,
+            copilot::copilot_get_server_log,
+            copilot::copilot_get_config,
+            copilot::copilot_set_config,
             copilot::copilot_sign_in,
             copilot::copilot_sign_out,
+            copilot::copilot_check_auth,
             copilot::copilot_get_completions,
+            copilot::copilot_get_buffer_completions,
+            copilot::copilot_get_panel_completions,
+            copilot::copilot_cancel_panel_completions,
+            copilot::copilot_cycle_completion,
+            copilot::copilot_accept_partial_completion,
+            copilot::copilot_show_completion,
             copilot::copilot_accept_completion,
             copilot::copilot_reject_completion,
             
->>>>>>> Stashed changes
+            // Debug adapter commands
+            dap::dap_start_session,
+            dap::dap_stop_session,
+            dap::dap_list_sessions,
+            dap::dap_set_breakpoints,
+            dap::dap_get_breakpoints,
+            dap::dap_continue,
+            dap::dap_next,
+            dap::dap_step_in,
+            dap::dap_step_out,
+            dap::dap_pause,
+            dap::dap_threads,
+            dap::dap_stack_trace,
+            dap::dap_scopes,
+            dap::dap_variables,
+            dap::dap_evaluate,
+            
             // Hotkey commands
             hotkeys::register_hotkey,
             hotkeys::unregister_hotkey,
@@ -1,33 +1,123 @@
 // بسم الله الرحمن الرحيم
 
+use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use tauri;
-use reqwest;
+use std::sync::Arc;
+use tauri::{self, AppHandle, Manager};
 use serde::{Deserialize, Serialize};
-use serde_json;
+
+use crate::ai::{for_each_line, sse_json, AiStreamManager, TokenUsage};
+use crate::buffer::BufferManager;
+use crate::conversation::estimate_tokens;
+use crate::citations::{self, find_markers, needs_metadata, CitationMarker};
+use crate::docs::DocsIndex;
+use crate::http::{self, AiError};
+use crate::secrets::{SecretStore, DEFAULT_KEY_NAME};
+use crate::usage::UsageLedger;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub title: String,
     pub url: String,
     pub snippet: String,
+    // Publication date, when the provider knows it
+    #[serde(default)]
+    pub date: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchImage {
+    pub image_url: String,
+    pub origin_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PerplexitySearchResponse {
+    // Matches the "search_citations" event sent when page details arrive later
+    #[serde(default)]
+    pub id: String,
     pub results: Vec<SearchResult>,
     pub answer: String,
+    // Where the answer's [n] markers are and which result each points to
+    #[serde(default)]
+    pub citation_markers: Vec<CitationMarker>,
+    #[serde(default)]
+    pub related_questions: Vec<String>,
+    #[serde(default)]
+    pub images: Vec<SearchImage>,
+    // Served from the local cache rather than a new request
+    #[serde(default)]
+    pub cached: bool,
+}
+
+impl PerplexitySearchResponse {
+    // The answer as markdown, with [n] markers turned into footnotes linking to the sources
+    pub fn to_markdown(&self, query: Option<&str>) -> String {
+        let mut markdown = String::new();
+        if let Some(query) = query.map(str::trim).filter(|q| !q.is_empty()) {
+            markdown.push_str(&format!("### {}\n\n", query));
+        }
+
+        let mut markers = if self.citation_markers.is_empty() {
+            find_markers(&self.answer, self.results.len())
+        } else {
+            self.citation_markers.clone()
+        };
+        markers.sort_by_key(|marker| marker.start);
+
+        let mut last = 0;
+        for marker in &markers {
+            let Some(result) = marker.result else {
+                continue;
+            };
+            if marker.start < last || self.answer.get(marker.start..marker.end).is_none() {
+                continue;
+            }
+            markdown.push_str(&self.answer[last..marker.start]);
+            markdown.push_str(&format!("[^{}]", result + 1));
+            last = marker.end;
+        }
+        markdown.push_str(self.answer[last..].trim_end());
+        markdown.push('\n');
+
+        if !self.results.is_empty() {
+            markdown.push('\n');
+        }
+        for (index, result) in self.results.iter().enumerate() {
+            let title = if result.title.is_empty() { &result.url } else { &result.title };
+            markdown.push_str(&format!(
+                "[^{}]: [{}](<{}>)",
+                index + 1,
+                title.replace('[', "\\[").replace(']', "\\]"),
+                result.url
+            ));
+            let snippet = result.snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !snippet.is_empty() {
+                markdown.push_str(&format!(" — {}", snippet));
+            }
+            markdown.push('\n');
+        }
+        markdown
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InsertedAnswer {
+    pub buffer_id: usize,
+    // Byte range of the inserted markdown
+    pub start: usize,
+    pub end: usize,
 }
 
 pub struct PerplexityKeyStore {
-    key: RwLock<String>,
-    config_path: PathBuf,
+    secrets: Arc<SecretStore>,
 }
 
 impl PerplexityKeyStore {
-    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+    pub fn new(app_handle: &tauri::AppHandle, secrets: Arc<SecretStore>) -> Self {
         let app_dir = if cfg!(feature = "portable") {
             app_handle
                 .path_resolver()
@@ -42,32 +132,21 @@ impl PerplexityKeyStore {
         
         fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
         
-        let config_path = app_dir.join("perplexity_key.txt");
-        
-        let key = if config_path.exists() {
-            match fs::read_to_string(&config_path) {
-                Ok(key) => key.trim().to_string(),
-                Err(_) => String::new(),
-            }
-        } else {
-            String::new()
-        };
+        // Keys used to live in a plaintext file here
+        secrets.migrate_plaintext(&app_dir.join("perplexity_key.txt"), "perplexity");
         
         Self {
-            key: RwLock::new(key),
-            config_path,
+            secrets,
         }
     }
     
     pub fn get_key(&self) -> String {
-        self.key.read().clone()
+        self.secrets.get("perplexity", DEFAULT_KEY_NAME).unwrap_or_default()
     }
     
     pub fn set_key(&self, new_key: &str) -> Result<(), String> {
-        let mut key = self.key.write();
-        *key = new_key.to_string();
-        
-        fs::write(&self.config_path, new_key)
+        self.secrets
+            .set("perplexity", DEFAULT_KEY_NAME, new_key.trim())
             .map_err(|e| format!("Failed to save Perplexity API key: {}", e))
     }
 }
@@ -82,100 +161,916 @@ pub fn set_perplexity_key(state: tauri::State<'_, PerplexityKeyStore>, api_key:
     state.set_key(&api_key)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSource {
+    Web,
+    // The offline documentation index
+    Local,
+    // The web, falling back to local docs when there's no key or no network
+    Auto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub source: SearchSource,
+    pub model: String,
+    // "hour", "day", "week", "month" or "year"; None searches all time
+    pub recency: Option<String>,
+    pub include_domains: Vec<String>,
+    pub exclude_domains: Vec<String>,
+    pub related_questions: bool,
+    pub images: bool,
+    pub max_tokens: u32,
+    // Skip the cache and fetch a fresh answer
+    pub refresh: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            source: SearchSource::Web,
+            model: "llama-3.1-sonar-small-128k-online".to_string(),
+            recency: Some("month".to_string()),
+            include_domains: Vec::new(),
+            exclude_domains: Vec::new(),
+            related_questions: false,
+            images: false,
+            max_tokens: 1024,
+            refresh: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    fn validate(&self) -> Result<(), String> {
+        if self.model.trim().is_empty() {
+            return Err("Search model is empty".to_string());
+        }
+        if let Some(recency) = &self.recency {
+            if !["hour", "day", "week", "month", "year"].contains(&recency.as_str()) {
+                return Err(format!("Invalid recency filter: {}", recency));
+            }
+        }
+        if self.include_domains.len() + self.exclude_domains.len() > MAX_DOMAIN_FILTERS {
+            return Err(format!("At most {} domain filters are allowed", MAX_DOMAIN_FILTERS));
+        }
+        Ok(())
+    }
+
+    // Perplexity takes one list, with excluded domains prefixed by "-"
+    fn domain_filter(&self) -> Vec<String> {
+        let clean = |domain: &String| {
+            domain
+                .trim()
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .trim_end_matches('/')
+                .to_string()
+        };
+        self.include_domains
+            .iter()
+            .map(clean)
+            .chain(self.exclude_domains.iter().map(|d| format!("-{}", clean(d))))
+            .filter(|d| !d.is_empty() && d != "-")
+            .collect()
+    }
+
+    fn payload(&self, query: &str, stream: bool) -> serde_json::Value {
+        let mut payload = serde_json::json!({
+            "model": self.model,
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You are a helpful assistant that searches the web and provides accurate, concise answers with sources."
+                },
+                {
+                    "role": "user",
+                    "content": query
+                }
+            ],
+            "temperature": 0.2,
+            "max_tokens": self.max_tokens,
+            "top_p": 0.9,
+            "search_domain_filter": self.domain_filter(),
+            "return_images": self.images,
+            "return_related_questions": self.related_questions,
+            "stream": stream
+        });
+        if let Some(recency) = &self.recency {
+            payload["search_recency_filter"] = serde_json::json!(recency);
+        }
+        payload
+    }
+
+    // Options that change the answer; `refresh` doesn't
+    fn cache_key(&self, query: &str) -> String {
+        let mut domains = self.domain_filter();
+        domains.sort();
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            query.trim().to_lowercase(),
+            self.model,
+            self.recency.as_deref().unwrap_or(""),
+            domains.join(","),
+            self.related_questions,
+            self.images,
+            self.max_tokens
+        )
+    }
+}
+
+const MAX_DOMAIN_FILTERS: usize = 10;
+const CACHE_TTL_HOURS: i64 = 24;
+const CACHE_MAX_ENTRIES: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedSearch {
+    created_at: DateTime<Utc>,
+    response: PerplexitySearchResponse,
+}
+
+// Answers by query and options, kept for a day in search_cache.json
+pub struct SearchCache {
+    entries: RwLock<HashMap<String, CachedSearch>>,
+    path: PathBuf,
+}
+
+impl SearchCache {
+    // An empty cache under `dir`, for tests
+    #[cfg(test)]
+    fn in_dir(dir: &std::path::Path) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            path: dir.join("search_cache.json"),
+        }
+    }
+
+    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
+
+        let path = app_dir.join("search_cache.json");
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            entries: RwLock::new(entries),
+            path,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<PerplexitySearchResponse> {
+        let entries = self.entries.read();
+        let entry = entries.get(key)?;
+        (Utc::now() - entry.created_at < chrono::Duration::hours(CACHE_TTL_HOURS)).then(|| entry.response.clone())
+    }
+
+    fn insert(&self, key: String, response: &PerplexitySearchResponse) {
+        let mut entries = self.entries.write();
+        let expiry = Utc::now() - chrono::Duration::hours(CACHE_TTL_HOURS);
+        entries.retain(|_, entry| entry.created_at > expiry);
+        while entries.len() >= CACHE_MAX_ENTRIES {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            CachedSearch {
+                created_at: Utc::now(),
+                response: response.clone(),
+            },
+        );
+        self.save(&entries);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.write();
+        entries.clear();
+        self.save(&entries);
+    }
+
+    fn save(&self, entries: &HashMap<String, CachedSearch>) {
+        let result = serde_json::to_string(entries)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::warn!("Failed to save search cache: {}", e);
+        }
+    }
+}
+
+// Payload of "perplexity_stream"; the last event carries the full response or the error
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchStreamEvent {
+    pub request_id: String,
+    pub delta: String,
+    pub done: bool,
+    pub response: Option<PerplexitySearchResponse>,
+    pub error: Option<String>,
+}
+
+// Sources, related questions and images come at the top level of every chunk
+fn apply_metadata(json: &serde_json::Value, response: &mut PerplexitySearchResponse) {
+    // search_results carries titles and snippets; older models only send bare citation URLs
+    if let Some(sources) = json.get("search_results").and_then(|r| r.as_array()).filter(|r| !r.is_empty()) {
+        let text = |source: &serde_json::Value, field: &str| {
+            source
+                .get(field)
+                .and_then(|v| v.as_str())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        response.results = sources
+            .iter()
+            .filter_map(|source| {
+                let url = text(source, "url")?;
+                Some(SearchResult {
+                    title: text(source, "title").unwrap_or_else(|| url.clone()),
+                    snippet: text(source, "snippet").unwrap_or_default(),
+                    date: text(source, "date"),
+                    url,
+                })
+            })
+            .collect();
+    } else if let Some(citations) = json.get("citations").and_then(|c| c.as_array()) {
+        response.results = citations
+            .iter()
+            .filter_map(|citation| {
+                let url = citation.as_str()?.to_string();
+                Some(SearchResult {
+                    title: url.clone(),
+                    url,
+                    snippet: String::new(),
+                    date: None,
+                })
+            })
+            .collect();
+    }
+    if let Some(questions) = json.get("related_questions").and_then(|q| q.as_array()) {
+        response.related_questions = questions
+            .iter()
+            .filter_map(|q| q.as_str().map(|q| q.to_string()))
+            .collect();
+    }
+    if let Some(images) = json.get("images").and_then(|i| i.as_array()) {
+        response.images = images
+            .iter()
+            .filter_map(|image| {
+                Some(SearchImage {
+                    image_url: image.get("image_url")?.as_str()?.to_string(),
+                    origin_url: image
+                        .get("origin_url")
+                        .and_then(|u| u.as_str())
+                        .map(|u| u.to_string()),
+                })
+            })
+            .collect();
+    }
+}
+
+// False when the response didn't report usage
+fn record_usage(json: &serde_json::Value, model: &str, usage: &UsageLedger) -> bool {
+    let Some(tokens) = json.get("usage").filter(|u| !u.is_null()) else {
+        return false;
+    };
+    let tokens = TokenUsage {
+        input_tokens: tokens.get("prompt_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+        output_tokens: tokens.get("completion_tokens").and_then(|n| n.as_u64()).unwrap_or(0),
+    };
+    usage.record("perplexity", model, &tokens, false);
+    true
+}
+
+// The answer a stream has delivered, recorded as estimated usage if the stream fails or is
+// dropped on cancel before the usage totals arrive, as MeteredProvider does for chat streams
+struct PartialSearch<'a> {
+    usage: &'a UsageLedger,
+    model: &'a str,
+    payload: &'a serde_json::Value,
+    // None once the stream has finished and been recorded normally
+    answer: Option<String>,
+}
+
+impl PartialSearch<'_> {
+    fn push(&mut self, delta: &str) {
+        if let Some(answer) = self.answer.as_mut() {
+            answer.push_str(delta);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.answer = None;
+    }
+}
+
+impl Drop for PartialSearch<'_> {
+    fn drop(&mut self) {
+        // Nothing received means the request was refused or never answered
+        let Some(answer) = self.answer.take().filter(|answer| !answer.is_empty()) else {
+            return;
+        };
+        let input_tokens = self.payload["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|message| message["content"].as_str())
+            .map(|content| estimate_tokens(content) as u64)
+            .sum();
+        let tokens = TokenUsage {
+            input_tokens,
+            output_tokens: estimate_tokens(&answer) as u64,
+        };
+        self.usage.record("perplexity", self.model, &tokens, true);
+    }
+}
+
+async fn fetch(key: &str, query: &str, options: &SearchOptions, usage: &UsageLedger) -> Result<PerplexitySearchResponse, AiError> {
+    let payload = options.payload(query, false);
+    let response = http::send(|client| client.post(API_URL).bearer_auth(key).json(&payload), false).await?;
+    let json = http::read_json(response).await?;
+    record_usage(&json, &options.model, usage);
+
+    let mut response = PerplexitySearchResponse {
+        answer: json
+            .pointer("/choices/0/message/content")
+            .and_then(|content| content.as_str())
+            .unwrap_or("No answer received from Perplexity")
+            .to_string(),
+        ..Default::default()
+    };
+    apply_metadata(&json, &mut response);
+    finish(&mut response);
+    Ok(response)
+}
+
+async fn fetch_stream(
+    key: &str,
+    query: &str,
+    options: &SearchOptions,
+    usage: &UsageLedger,
+    on_delta: &(dyn Fn(&str) + Send + Sync),
+) -> Result<PerplexitySearchResponse, AiError> {
+    let payload = options.payload(query, true);
+    let response = http::send(|client| client.post(API_URL).bearer_auth(key).json(&payload), true).await?;
+
+    let mut result = PerplexitySearchResponse::default();
+    let mut last_chunk = None;
+    let mut partial = PartialSearch {
+        usage,
+        model: &options.model,
+        payload: &payload,
+        answer: Some(String::new()),
+    };
+    for_each_line(response, |line| {
+        if let Some(json) = sse_json(line)? {
+            if let Some(delta) = json.pointer("/choices/0/delta/content").and_then(|c| c.as_str()) {
+                if !delta.is_empty() {
+                    on_delta(delta);
+                    partial.push(delta);
+                    result.answer.push_str(delta);
+                }
+            }
+            apply_metadata(&json, &mut result);
+            last_chunk = Some(json);
+        }
+        Ok(true)
+    })
+    .await?;
+
+    // Usage totals arrive with the final chunk; without them the answer is estimated on drop
+    if last_chunk.is_some_and(|json| record_usage(&json, &options.model, usage)) {
+        partial.finish();
+    }
+    finish(&mut result);
+    Ok(result)
+}
+
+fn finish(response: &mut PerplexitySearchResponse) {
+    response.id = uuid::Uuid::new_v4().to_string();
+    response.citation_markers = find_markers(&response.answer, response.results.len());
+}
+
+const API_URL: &str = "https://api.perplexity.ai/chat/completions";
+
 #[tauri::command]
-pub async fn search_web(query: String, api_key: Option<String>, perplexity_store: tauri::State<'_, PerplexityKeyStore>) -> Result<PerplexitySearchResponse, String> {
+#[allow(clippy::too_many_arguments)]
+pub async fn search_web(
+    query: String,
+    api_key: Option<String>,
+    options: Option<SearchOptions>,
+    request_id: Option<String>, // stream the answer as "perplexity_stream" events under this id
+    app_handle: AppHandle,
+    perplexity_store: tauri::State<'_, PerplexityKeyStore>,
+    usage: tauri::State<'_, Arc<UsageLedger>>,
+    cache: tauri::State<'_, SearchCache>,
+    streams: tauri::State<'_, AiStreamManager>,
+    docs: tauri::State<'_, Arc<DocsIndex>>,
+) -> Result<PerplexitySearchResponse, String> {
+    if query.trim().is_empty() {
+        return Err("Search query is empty".to_string());
+    }
+
+    let options = options.unwrap_or_default();
+    options.validate()?;
+
     // Get API key from parameter or store
-    let key = if let Some(k) = api_key {
-        k
-    } else {
-        let stored_key = perplexity_store.get_key();
-        if stored_key.is_empty() {
-            return Err("No Perplexity API key provided".to_string());
-        }
-        stored_key
+    let key = match api_key.filter(|k| !k.is_empty()) {
+        Some(key) => key,
+        None => perplexity_store.get_key(),
     };
-    
+    let use_local = match options.source {
+        SearchSource::Local => true,
+        SearchSource::Auto => key.is_empty(),
+        SearchSource::Web => false,
+    };
+    if use_local {
+        return Ok(local_search(&docs, &query, request_id.as_deref(), &app_handle));
+    }
     if key.is_empty() {
-        return Err("Perplexity API key is empty".to_string());
+        return Err("No Perplexity API key provided".to_string());
     }
-    
-    if query.is_empty() {
-        return Err("Search query is empty".to_string());
+
+    let cache_key = options.cache_key(&query);
+    if !options.refresh {
+        if let Some(mut cached) = cache.get(&cache_key) {
+            cached.cached = true;
+            if let Some(request_id) = &request_id {
+                let _ = app_handle.emit_all(
+                    "perplexity_stream",
+                    SearchStreamEvent {
+                        request_id: request_id.clone(),
+                        delta: cached.answer.clone(),
+                        done: true,
+                        response: Some(cached.clone()),
+                        error: None,
+                    },
+                );
+            }
+            return Ok(cached);
+        }
     }
-    
-    let client = reqwest::Client::new();
-    let api_url = "https://api.perplexity.ai/chat/completions";
-    
-    let payload = serde_json::json!({
-        "model": "llama-3.1-sonar-small-128k-online",
-        "messages": [
-            {
-                "role": "system",
-                "content": "You are a helpful assistant that searches the web and provides accurate, concise answers with sources."
+
+    usage.check_budget()?;
+
+    let result = match &request_id {
+        Some(request_id) => {
+            let emit_delta = |delta: &str| {
+                let _ = app_handle.emit_all(
+                    "perplexity_stream",
+                    SearchStreamEvent {
+                        request_id: request_id.clone(),
+                        delta: delta.to_string(),
+                        done: false,
+                        response: None,
+                        error: None,
+                    },
+                );
+            };
+            let result = streams
+                .cancellable(request_id, fetch_stream(&key, &query, &options, &usage, &emit_delta))
+                .await;
+
+            let _ = app_handle.emit_all(
+                "perplexity_stream",
+                SearchStreamEvent {
+                    request_id: request_id.clone(),
+                    delta: String::new(),
+                    done: true,
+                    response: result.as_ref().ok().cloned(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                },
+            );
+            result
+        }
+        None => fetch(&key, &query, &options, &usage).await,
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(AiError::Network(e)) if options.source == SearchSource::Auto && !docs.is_empty() => {
+            log::info!("Web search unavailable ({}), searching local docs", e);
+            return Ok(local_search(&docs, &query, request_id.as_deref(), &app_handle));
+        }
+        Err(e) => return Err(format!("Perplexity: {}", e)),
+    };
+
+    cache.insert(cache_key.clone(), &result);
+
+    if result.results.iter().any(needs_metadata) {
+        spawn_citation_lookup(app_handle, cache_key, result.clone());
+    }
+    Ok(result)
+}
+
+// Local results are instant, so streaming callers get them as a single final event
+fn local_search(docs: &DocsIndex, query: &str, request_id: Option<&str>, app_handle: &AppHandle) -> PerplexitySearchResponse {
+    let response = docs.search(query, crate::docs::DEFAULT_LIMIT);
+    if let Some(request_id) = request_id {
+        let _ = app_handle.emit_all(
+            "perplexity_stream",
+            SearchStreamEvent {
+                request_id: request_id.to_string(),
+                delta: response.answer.clone(),
+                done: true,
+                response: Some(response.clone()),
+                error: None,
             },
-            {
-                "role": "user",
-                "content": query
-            }
-        ],
-        "temperature": 0.2,
-        "max_tokens": 1024,
-        "top_p": 0.9,
-        "search_domain_filter": [],
-        "return_images": false,
-        "return_related_questions": false,
-        "search_recency_filter": "month",
-        "stream": false
+        );
+    }
+    response
+}
+
+// Payload of "search_citations": the results of a search once page titles and descriptions are filled in
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchCitationsEvent {
+    pub search_id: String,
+    pub results: Vec<SearchResult>,
+}
+
+fn spawn_citation_lookup(app_handle: AppHandle, cache_key: String, mut response: PerplexitySearchResponse) {
+    tauri::async_runtime::spawn(async move {
+        let results = citations::fetch_metadata(response.results.clone()).await;
+        if results.iter().zip(&response.results).all(|(new, old)| new.title == old.title && new.snippet == old.snippet) {
+            return;
+        }
+
+        response.results = results;
+        app_handle.state::<SearchCache>().insert(cache_key, &response);
+        let _ = app_handle.emit_all(
+            "search_citations",
+            SearchCitationsEvent {
+                search_id: response.id.clone(),
+                results: response.results,
+            },
+        );
     });
-    
-    let response = client
-        .post(api_url)
-        .header("Authorization", format!("Bearer {}", key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("Perplexity API request error: {}", e))?;
-        
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Perplexity API error ({}): {}", status, error_text));
+}
+
+// Put a search answer into the editor: at the cursor of `buffer_id`, or in a new markdown buffer when none is given
+#[tauri::command]
+pub fn insert_search_answer(
+    response: PerplexitySearchResponse,
+    query: Option<String>,
+    buffer_id: Option<usize>,
+    buffer_manager: tauri::State<'_, BufferManager>,
+) -> Result<InsertedAnswer, String> {
+    insert_answer(&response, query.as_deref(), buffer_id, &buffer_manager)
+}
+
+fn insert_answer(
+    response: &PerplexitySearchResponse,
+    query: Option<&str>,
+    buffer_id: Option<usize>,
+    buffer_manager: &BufferManager,
+) -> Result<InsertedAnswer, String> {
+    let markdown = response.to_markdown(query);
+
+    let Some(buffer_id) = buffer_id else {
+        let buffer_id = buffer_manager.create_buffer(markdown.clone(), None);
+        buffer_manager.set_language(buffer_id, Some("markdown".to_string()))?;
+        return Ok(InsertedAnswer {
+            buffer_id,
+            start: 0,
+            end: markdown.len(),
+        });
+    };
+
+    let buffer = buffer_manager
+        .get_buffer(buffer_id)
+        .ok_or_else(|| format!("Buffer {} not found", buffer_id))?;
+    let content = &buffer.content;
+    let mut position = buffer.cursor_position.min(content.len());
+    while !content.is_char_boundary(position) {
+        position -= 1;
     }
-    
-    let response_json: serde_json::Value = response.json().await
-        .map_err(|e| format!("Failed to parse Perplexity response: {}", e))?;
-    
-    // Extract answer from response
-    let answer = response_json
-        .get("choices")
-        .and_then(|choices| choices.get(0))
-        .and_then(|choice| choice.get("message"))
-        .and_then(|message| message.get("content"))
-        .and_then(|content| content.as_str())
-        .unwrap_or("No answer received from Perplexity")
-        .to_string();
-    
-    // Extract citations if available
-    let citations = response_json
-        .get("citations")
-        .and_then(|c| c.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|citation| {
-                    let url = citation.as_str()?.to_string();
-                    Some(SearchResult {
-                        title: url.clone(),
-                        url: url.clone(),
-                        snippet: String::new(),
-                    })
-                })
-                .collect::<Vec<_>>()
-        })
-        .unwrap_or_default();
-    
-    Ok(PerplexitySearchResponse {
-        answer,
-        results: citations,
+
+    // Keep the block on its own lines, separated from surrounding text
+    let before = &content[..position];
+    let prefix = if before.is_empty() || before.ends_with("\n\n") {
+        ""
+    } else if before.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    let suffix = if content[position..].starts_with('\n') { "" } else { "\n" };
+    let text = format!("{}{}{}", prefix, markdown, suffix);
+
+    buffer_manager.apply_edit(buffer_id, position, position, &text)?;
+    let end = position + text.len();
+    buffer_manager.update_cursor_position(buffer_id, end)?;
+
+    Ok(InsertedAnswer {
+        buffer_id,
+        start: position + prefix.len(),
+        end: end - suffix.len(),
     })
 }
+
+#[tauri::command]
+pub fn clear_search_cache(cache: tauri::State<'_, SearchCache>) {
+    cache.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("vuno-search-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn answer(text: &str) -> PerplexitySearchResponse {
+        PerplexitySearchResponse {
+            answer: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validates_options() {
+        assert!(SearchOptions::default().validate().is_ok());
+
+        let options = SearchOptions { model: "  ".to_string(), ..Default::default() };
+        assert!(options.validate().is_err());
+
+        let options = SearchOptions { recency: Some("decade".to_string()), ..Default::default() };
+        assert_eq!(options.validate().unwrap_err(), "Invalid recency filter: decade");
+
+        let options = SearchOptions { recency: None, ..Default::default() };
+        assert!(options.validate().is_ok());
+
+        let domains: Vec<String> = (0..6).map(|n| format!("site{}.com", n)).collect();
+        let options = SearchOptions {
+            include_domains: domains.clone(),
+            exclude_domains: domains,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn excluded_domains_get_a_dash() {
+        let options = SearchOptions {
+            include_domains: vec!["https://docs.rs/".to_string(), " ".to_string()],
+            exclude_domains: vec!["http://pinterest.com".to_string(), "".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(options.domain_filter(), vec!["docs.rs", "-pinterest.com"]);
+        assert_eq!(options.payload("q", false)["search_domain_filter"], serde_json::json!(["docs.rs", "-pinterest.com"]));
+    }
+
+    #[test]
+    fn cache_keys_follow_the_options_that_change_the_answer() {
+        let options = SearchOptions {
+            include_domains: vec!["a.com".to_string(), "b.com".to_string()],
+            ..Default::default()
+        };
+        let key = options.cache_key("  Rust Traits ");
+        assert_eq!(key, options.cache_key("rust traits"));
+
+        let reordered = SearchOptions {
+            include_domains: vec!["b.com".to_string(), "a.com".to_string()],
+            refresh: true,
+            ..Default::default()
+        };
+        assert_eq!(key, reordered.cache_key("rust traits"));
+
+        let recent = SearchOptions { recency: Some("day".to_string()), ..options.clone() };
+        assert_ne!(key, recent.cache_key("rust traits"));
+        let excluded = SearchOptions {
+            include_domains: vec!["a.com".to_string()],
+            exclude_domains: vec!["b.com".to_string()],
+            ..Default::default()
+        };
+        assert_ne!(key, excluded.cache_key("rust traits"));
+    }
+
+    #[test]
+    fn cached_answers_expire() {
+        let dir = TempDir::new();
+        let cache = SearchCache::in_dir(&dir.0);
+        cache.insert("fresh".to_string(), &answer("new"));
+        assert_eq!(cache.get("fresh").unwrap().answer, "new");
+
+        cache.entries.write().insert(
+            "stale".to_string(),
+            CachedSearch {
+                created_at: Utc::now() - chrono::Duration::hours(CACHE_TTL_HOURS + 1),
+                response: answer("old"),
+            },
+        );
+        assert!(cache.get("stale").is_none());
+
+        // Expired entries are dropped on the next insert, and the cache is saved
+        cache.insert("another".to_string(), &answer("x"));
+        assert!(!cache.entries.read().contains_key("stale"));
+        let saved: HashMap<String, CachedSearch> =
+            serde_json::from_str(&fs::read_to_string(dir.0.join("search_cache.json")).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+    }
+
+    #[test]
+    fn a_full_cache_evicts_the_oldest_answer() {
+        let dir = TempDir::new();
+        let cache = SearchCache::in_dir(&dir.0);
+        {
+            let mut entries = cache.entries.write();
+            for n in 0..CACHE_MAX_ENTRIES {
+                entries.insert(
+                    format!("query {}", n),
+                    CachedSearch {
+                        created_at: Utc::now() - chrono::Duration::minutes(n as i64),
+                        response: answer("cached"),
+                    },
+                );
+            }
+        }
+
+        cache.insert("newest".to_string(), &answer("fresh"));
+        let entries = cache.entries.read();
+        assert_eq!(entries.len(), CACHE_MAX_ENTRIES);
+        assert!(entries.contains_key("newest"));
+        assert!(entries.contains_key("query 0"));
+        assert!(!entries.contains_key(&format!("query {}", CACHE_MAX_ENTRIES - 1)));
+    }
+
+    #[test]
+    fn search_results_win_over_bare_citations() {
+        let json = serde_json::json!({
+            "search_results": [
+                { "title": " The Book ", "url": "https://doc.rust-lang.org/book/", "snippet": "Learn Rust", "date": "2024-01-02" },
+                { "url": "https://example.com" },
+                { "title": "No URL" }
+            ],
+            "citations": ["https://ignored.example"],
+            "related_questions": ["What is a trait?"],
+            "images": [{ "image_url": "https://img.example/a.png" }, { "origin_url": "https://no-image.example" }]
+        });
+        let mut response = PerplexitySearchResponse::default();
+        apply_metadata(&json, &mut response);
+
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].title, "The Book");
+        assert_eq!(response.results[0].snippet, "Learn Rust");
+        assert_eq!(response.results[0].date.as_deref(), Some("2024-01-02"));
+        assert_eq!(response.results[1].title, "https://example.com");
+        assert_eq!(response.related_questions, vec!["What is a trait?"]);
+        assert_eq!(response.images.len(), 1);
+        assert_eq!(response.images[0].origin_url, None);
+    }
+
+    #[test]
+    fn bare_citations_become_results() {
+        let json = serde_json::json!({ "search_results": [], "citations": ["https://a.example", 7, "https://b.example"] });
+        let mut response = PerplexitySearchResponse::default();
+        apply_metadata(&json, &mut response);
+
+        let urls: Vec<&str> = response.results.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://a.example", "https://b.example"]);
+        assert_eq!(response.results[0].title, "https://a.example");
+        assert!(response.results[0].snippet.is_empty());
+    }
+
+    fn partial<'a>(usage: &'a UsageLedger, payload: &'a serde_json::Value) -> PartialSearch<'a> {
+        PartialSearch {
+            usage,
+            model: "sonar",
+            payload,
+            answer: Some(String::new()),
+        }
+    }
+
+    #[test]
+    fn an_unfinished_stream_records_an_estimate() {
+        let dir = TempDir::new();
+        let usage = UsageLedger::in_dir(&dir.0);
+        let payload = SearchOptions::default().payload("What is the answer to everything?", true);
+
+        {
+            let mut partial = partial(&usage, &payload);
+            partial.push("Forty-two, ");
+            partial.push("give or take.");
+        }
+        let records = usage.records(None, 10);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].estimated);
+        assert!(records[0].input_tokens > estimate_tokens("What is the answer to everything?") as u64);
+        assert_eq!(records[0].output_tokens, estimate_tokens("Forty-two, give or take.") as u64);
+
+        // Finished streams were recorded from their reported usage; empty ones were never answered
+        {
+            let mut partial = partial(&usage, &payload);
+            partial.push("Done.");
+            partial.finish();
+        }
+        drop(partial(&usage, &payload));
+        assert_eq!(usage.records(None, 10).len(), 1);
+    }
+
+    fn sourced(answer: &str, titles: &[&str]) -> PerplexitySearchResponse {
+        let results = titles
+            .iter()
+            .enumerate()
+            .map(|(index, title)| SearchResult {
+                title: title.to_string(),
+                url: format!("https://site{}.example/page", index + 1),
+                snippet: "A  short\nsummary".to_string(),
+                date: None,
+            })
+            .collect();
+        let mut response = PerplexitySearchResponse {
+            answer: answer.to_string(),
+            results,
+            ..Default::default()
+        };
+        finish(&mut response);
+        response
+    }
+
+    #[test]
+    fn markers_become_footnotes() {
+        let response = sourced("Rust is fast[1] and safe[2][9].\n\n", &["Speed [benchmarks]", ""]);
+        assert_eq!(
+            response.to_markdown(Some("  Is Rust good? ")),
+            "### Is Rust good?\n\n\
+             Rust is fast[^1] and safe[^2][9].\n\n\
+             [^1]: [Speed \\[benchmarks\\]](<https://site1.example/page>) — A short summary\n\
+             [^2]: [https://site2.example/page](<https://site2.example/page>) — A short summary\n"
+        );
+    }
+
+    #[test]
+    fn answers_without_sources_have_no_footnotes() {
+        let response = sourced("Nothing to cite [1].", &[]);
+        assert_eq!(response.to_markdown(None), "Nothing to cite [1].\n");
+    }
+
+    #[test]
+    fn answers_are_inserted_on_their_own_lines() {
+        let buffers = BufferManager::new();
+        let response = sourced("Forty-two.", &[]);
+
+        let id = buffers.create_buffer("before\nafter".to_string(), None);
+        buffers.update_cursor_position(id, "before".len()).unwrap();
+        let inserted = insert_answer(&response, None, Some(id), &buffers).unwrap();
+        let content = buffers.get_buffer(id).unwrap().content;
+        assert_eq!(content, "before\n\nForty-two.\n\nafter");
+        assert_eq!(&content[inserted.start..inserted.end], "Forty-two.\n");
+        assert_eq!(buffers.get_buffer(id).unwrap().cursor_position, inserted.end);
+
+        // Already on a blank line, at the end of the buffer
+        let id = buffers.create_buffer("intro\n\n".to_string(), None);
+        buffers.update_cursor_position(id, "intro\n\n".len()).unwrap();
+        insert_answer(&response, None, Some(id), &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "intro\n\nForty-two.\n\n");
+
+        // Right after a line break, before one
+        let id = buffers.create_buffer("a\n\nb".to_string(), None);
+        buffers.update_cursor_position(id, "a\n".len()).unwrap();
+        insert_answer(&response, None, Some(id), &buffers).unwrap();
+        assert_eq!(buffers.get_buffer(id).unwrap().content, "a\n\nForty-two.\n\nb");
+    }
+
+    #[test]
+    fn answers_without_a_buffer_open_a_markdown_one() {
+        let buffers = BufferManager::new();
+        let inserted = insert_answer(&sourced("Forty-two.", &[]), Some("Answer?"), None, &buffers).unwrap();
+        let buffer = buffers.get_buffer(inserted.buffer_id).unwrap();
+        assert_eq!(buffer.content, "### Answer?\n\nForty-two.\n");
+        assert_eq!(buffer.language.as_deref(), Some("markdown"));
+        assert_eq!((inserted.start, inserted.end), (0, buffer.content.len()));
+    }
+}
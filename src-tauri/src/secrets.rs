@@ -0,0 +1,455 @@
+// بسم الله الرحمن الرحيم
+// API key storage in the OS keyring, with an obfuscated file when no keyring is available
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use parking_lot::{Mutex, RwLock};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const KEYRING_SERVICE: &str = "vuno";
+pub const DEFAULT_KEY_NAME: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretBackend {
+    Keyring,
+    EncryptedFile,
+}
+
+// Names of stored keys per provider; never holds the values themselves
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SecretIndex {
+    keys: BTreeMap<String, BTreeMap<String, SecretBackend>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretInfo {
+    pub provider: String,
+    pub name: String,
+    pub backend: SecretBackend,
+}
+
+pub struct SecretStore {
+    index: RwLock<SecretIndex>,
+    // Values already read this session, so the keyring isn't asked on every request
+    cache: RwLock<HashMap<String, String>>,
+    keyring_available: bool,
+    index_path: PathBuf,
+    // The vault is rewritten whole, so reads and read-modify-writes of it go one at a time
+    vault_lock: Mutex<()>,
+    vault_path: PathBuf,
+    vault_key_path: PathBuf,
+}
+
+impl SecretStore {
+    // A store under `dir` that only ever uses the vault, for tests
+    #[cfg(test)]
+    pub(crate) fn in_dir(dir: &Path) -> Self {
+        Self {
+            index: RwLock::new(SecretIndex::default()),
+            cache: RwLock::new(HashMap::new()),
+            keyring_available: false,
+            index_path: dir.join("secrets.json"),
+            vault_lock: Mutex::new(()),
+            vault_path: dir.join("secrets.vault"),
+            vault_key_path: dir.join("secrets.key"),
+        }
+    }
+
+    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
+
+        let index_path = app_dir.join("secrets.json");
+        let index = fs::read_to_string(&index_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        // Portable builds keep everything next to the executable
+        let keyring_available = !cfg!(feature = "portable") && probe_keyring();
+        if !keyring_available {
+            log::info!("No OS keyring available; API keys go to an obfuscated file");
+        }
+
+        Self {
+            index: RwLock::new(index),
+            cache: RwLock::new(HashMap::new()),
+            keyring_available,
+            index_path,
+            vault_lock: Mutex::new(()),
+            vault_path: app_dir.join("secrets.vault"),
+            vault_key_path: app_dir.join("secrets.key"),
+        }
+    }
+
+    pub fn get(&self, provider: &str, name: &str) -> Option<String> {
+        let account = account(provider, name);
+        if let Some(value) = self.cache.read().get(&account) {
+            return Some(value.clone());
+        }
+
+        let backend = *self.index.read().keys.get(provider)?.get(name)?;
+        let value = match backend {
+            SecretBackend::Keyring => keyring::Entry::new(KEYRING_SERVICE, &account)
+                .and_then(|entry| entry.get_password())
+                .map_err(|e| log::warn!("Failed to read {} from the keyring: {}", account, e))
+                .ok()?,
+            SecretBackend::EncryptedFile => self
+                .read_vault_locked()
+                .map_err(|e| log::warn!("Failed to read the secrets file: {}", e))
+                .ok()?
+                .remove(&account)?,
+        };
+
+        self.cache.write().insert(account, value.clone());
+        Some(value)
+    }
+
+    pub fn set(&self, provider: &str, name: &str, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return self.delete(provider, name);
+        }
+
+        let account = account(provider, name);
+        let backend = if self.keyring_available {
+            match keyring::Entry::new(KEYRING_SERVICE, &account).and_then(|entry| entry.set_password(value)) {
+                Ok(()) => SecretBackend::Keyring,
+                Err(e) => {
+                    log::warn!("Keyring rejected {}, using the secrets file: {}", account, e);
+                    self.write_vault_entry(&account, Some(value))?;
+                    SecretBackend::EncryptedFile
+                }
+            }
+        } else {
+            self.write_vault_entry(&account, Some(value))?;
+            SecretBackend::EncryptedFile
+        };
+
+        // Don't leave a stale copy behind in the other backend
+        let previous = self.update_index(provider, name, Some(backend))?;
+        match previous {
+            Some(SecretBackend::EncryptedFile) if backend == SecretBackend::Keyring => {
+                self.write_vault_entry(&account, None)?;
+            }
+            Some(SecretBackend::Keyring) if backend == SecretBackend::EncryptedFile => {
+                let _ = keyring::Entry::new(KEYRING_SERVICE, &account).and_then(|entry| entry.delete_password());
+            }
+            _ => {}
+        }
+
+        self.cache.write().insert(account, value.to_string());
+        Ok(())
+    }
+
+    pub fn delete(&self, provider: &str, name: &str) -> Result<(), String> {
+        let account = account(provider, name);
+        self.cache.write().remove(&account);
+
+        match self.update_index(provider, name, None)? {
+            Some(SecretBackend::Keyring) => keyring::Entry::new(KEYRING_SERVICE, &account)
+                .and_then(|entry| entry.delete_password())
+                .map_err(|e| format!("Failed to remove key from the keyring: {}", e)),
+            Some(SecretBackend::EncryptedFile) => self.write_vault_entry(&account, None),
+            None => Ok(()),
+        }
+    }
+
+    pub fn list(&self, provider: Option<&str>) -> Vec<SecretInfo> {
+        self.index
+            .read()
+            .keys
+            .iter()
+            .filter(|(p, _)| provider.is_none() || provider == Some(p.as_str()))
+            .flat_map(|(provider, names)| {
+                names.iter().map(move |(name, backend)| SecretInfo {
+                    provider: provider.clone(),
+                    name: name.clone(),
+                    backend: *backend,
+                })
+            })
+            .collect()
+    }
+
+    // Move a key out of an old plaintext file, then delete the file
+    pub fn migrate_plaintext(&self, path: &Path, provider: &str) {
+        let Ok(content) = fs::read_to_string(path) else {
+            return;
+        };
+
+        let key = content.trim();
+        if !key.is_empty() && self.get(provider, DEFAULT_KEY_NAME).is_none() {
+            if let Err(e) = self.set(provider, DEFAULT_KEY_NAME, key) {
+                log::warn!("Couldn't migrate {}: {}", path.display(), e);
+                return;
+            }
+        }
+
+        if let Err(e) = fs::remove_file(path) {
+            log::warn!("Migrated {} but couldn't delete it: {}", path.display(), e);
+        } else {
+            log::info!("Moved the {} API key out of {}", provider, path.display());
+        }
+    }
+
+    fn update_index(&self, provider: &str, name: &str, backend: Option<SecretBackend>) -> Result<Option<SecretBackend>, String> {
+        let mut index = self.index.write();
+        let names = index.keys.entry(provider.to_string()).or_default();
+        let previous = match backend {
+            Some(backend) => names.insert(name.to_string(), backend),
+            None => names.remove(name),
+        };
+        if names.is_empty() {
+            index.keys.remove(provider);
+        }
+
+        let content = serde_json::to_string_pretty(&*index)
+            .map_err(|e| format!("Failed to serialize secrets index: {}", e))?;
+        write_private(&self.index_path, content.as_bytes())?;
+        Ok(previous)
+    }
+
+    // The key lives beside the vault, so this only keeps API keys out of plain sight (a stray grep,
+    // a synced or backed-up vault on its own). Anyone who can read the config directory can read
+    // them; the OS keyring is what actually protects them.
+    fn vault_cipher(&self) -> Result<Aes256Gcm, String> {
+        let key = match fs::read(&self.vault_key_path) {
+            Ok(key) if key.len() == 32 => key,
+            Ok(_) => {
+                return Err(format!(
+                    "{} is damaged, so the stored API keys can't be read",
+                    self.vault_key_path.display()
+                ))
+            }
+            // A new key is only safe when there's no vault that needed the old one
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !self.vault_path.exists() => {
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                write_private(&self.vault_key_path, &key)?;
+                key
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "{} is missing, so the stored API keys in {} can't be read",
+                    self.vault_key_path.display(),
+                    self.vault_path.display()
+                ))
+            }
+            Err(e) => return Err(format!("Failed to read {}: {}", self.vault_key_path.display(), e)),
+        };
+        Aes256Gcm::new_from_slice(&key).map_err(|e| format!("Invalid secrets key: {}", e))
+    }
+
+    fn read_vault_locked(&self) -> Result<HashMap<String, String>, String> {
+        let _vault = self.vault_lock.lock();
+        self.read_vault()
+    }
+
+    // Callers hold vault_lock
+    fn read_vault(&self) -> Result<HashMap<String, String>, String> {
+        if !self.vault_path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let cipher = self.vault_cipher()?;
+        let encoded = fs::read_to_string(&self.vault_path)
+            .map_err(|e| format!("Failed to read secrets file: {}", e))?;
+        let data = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| format!("Corrupt secrets file: {}", e))?;
+        if data.len() < 12 {
+            return Err("Corrupt secrets file".to_string());
+        }
+
+        let (nonce, ciphertext) = data.split_at(12);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Failed to decrypt secrets file".to_string())?;
+        serde_json::from_slice(&plaintext).map_err(|e| format!("Corrupt secrets file: {}", e))
+    }
+
+    fn write_vault_entry(&self, account: &str, value: Option<&str>) -> Result<(), String> {
+        let _vault = self.vault_lock.lock();
+        let cipher = self.vault_cipher()?;
+        let mut vault = self.read_vault()?;
+        match value {
+            Some(value) => vault.insert(account.to_string(), value.to_string()),
+            None => vault.remove(account),
+        };
+
+        let plaintext = serde_json::to_vec(&vault)
+            .map_err(|e| format!("Failed to serialize secrets: {}", e))?;
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| "Failed to encrypt secrets".to_string())?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        write_private(&self.vault_path, BASE64.encode(data).as_bytes())
+    }
+}
+
+fn account(provider: &str, name: &str) -> String {
+    format!("{}:{}", provider, name)
+}
+
+// Headless Linux sessions often have no Secret Service running
+fn probe_keyring() -> bool {
+    match keyring::Entry::new(KEYRING_SERVICE, "probe").and_then(|entry| entry.get_password()) {
+        Ok(_) | Err(keyring::Error::NoEntry) => true,
+        Err(e) => {
+            log::debug!("Keyring probe failed: {}", e);
+            false
+        }
+    }
+}
+
+// Write a file only the current user can read
+fn write_private(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    // mode() only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = file.set_permissions(fs::Permissions::from_mode(0o600));
+    }
+    file.write_all(data)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[tauri::command]
+pub fn list_secrets(provider: Option<String>, secrets: tauri::State<'_, Arc<SecretStore>>) -> Vec<SecretInfo> {
+    secrets.list(provider.as_deref())
+}
+
+#[tauri::command]
+pub fn set_secret(
+    provider: String,
+    name: Option<String>,
+    value: String,
+    secrets: tauri::State<'_, Arc<SecretStore>>,
+) -> Result<(), String> {
+    secrets.set(&provider, name.as_deref().unwrap_or(DEFAULT_KEY_NAME), &value)
+}
+
+#[tauri::command]
+pub fn delete_secret(
+    provider: String,
+    name: Option<String>,
+    secrets: tauri::State<'_, Arc<SecretStore>>,
+) -> Result<(), String> {
+    secrets.delete(&provider, name.as_deref().unwrap_or(DEFAULT_KEY_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A store in a throwaway directory that only ever uses the vault
+    struct TempStore {
+        dir: PathBuf,
+        store: Arc<SecretStore>,
+    }
+
+    impl TempStore {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("vuno-secrets-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let store = Arc::new(SecretStore::in_dir(&dir));
+            Self { dir, store }
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn keys_round_trip_through_the_vault() {
+        let temp = TempStore::new();
+        let store = &temp.store;
+        store.set("openai", DEFAULT_KEY_NAME, "sk-first").unwrap();
+        store.set("gemini", "work", "g-second").unwrap();
+
+        store.cache.write().clear();
+        assert_eq!(store.get("openai", DEFAULT_KEY_NAME).as_deref(), Some("sk-first"));
+        assert_eq!(store.get("gemini", "work").as_deref(), Some("g-second"));
+        assert!(!fs::read_to_string(&store.vault_path).unwrap().contains("sk-first"));
+
+        store.delete("openai", DEFAULT_KEY_NAME).unwrap();
+        store.cache.write().clear();
+        assert_eq!(store.get("openai", DEFAULT_KEY_NAME), None);
+        assert_eq!(store.get("gemini", "work").as_deref(), Some("g-second"));
+    }
+
+    #[test]
+    fn a_lost_key_leaves_the_vault_alone() {
+        let temp = TempStore::new();
+        let store = &temp.store;
+        store.set("openai", DEFAULT_KEY_NAME, "sk-first").unwrap();
+        let vault = fs::read(&store.vault_path).unwrap();
+
+        fs::remove_file(&store.vault_key_path).unwrap();
+        store.cache.write().clear();
+        assert_eq!(store.get("openai", DEFAULT_KEY_NAME), None);
+        let error = store.set("anthropic", DEFAULT_KEY_NAME, "sk-ant").unwrap_err();
+        assert!(error.contains("missing"), "{}", error);
+        assert!(!store.vault_key_path.exists());
+        assert_eq!(fs::read(&store.vault_path).unwrap(), vault);
+
+        fs::write(&store.vault_key_path, b"too short").unwrap();
+        let error = store.set("anthropic", DEFAULT_KEY_NAME, "sk-ant").unwrap_err();
+        assert!(error.contains("damaged"), "{}", error);
+        assert_eq!(fs::read(&store.vault_path).unwrap(), vault);
+    }
+
+    #[test]
+    fn concurrent_writes_keep_every_key() {
+        let temp = TempStore::new();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let store = temp.store.clone();
+                std::thread::spawn(move || store.write_vault_entry(&account("provider", &i.to_string()), Some("value")))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+
+        let vault = temp.store.read_vault_locked().unwrap();
+        assert_eq!(vault.len(), 8);
+    }
+}
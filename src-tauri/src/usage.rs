@@ -0,0 +1,483 @@
+// بسم الله الرحمن الرحيم
+// Token usage ledger for paid AI requests, with daily and monthly totals and budget limits
+
+use chrono::{DateTime, Datelike, Local, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::ai::{AiProvider, ChatRequest, ChatResponse, DeltaSink, ProviderFuture, TokenUsage};
+use crate::conversation::estimate_tokens;
+use crate::http::AiError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub provider: String,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    // Set when the provider didn't report usage and the counts were guessed from text length
+    #[serde(default)]
+    pub estimated: bool,
+    // In USD, when a price is configured for the model
+    #[serde(default)]
+    pub cost: Option<f64>,
+}
+
+// USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageLimits {
+    pub daily_tokens: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+    pub daily_cost: Option<f64>,
+    pub monthly_cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageSettings {
+    pub limits: UsageLimits,
+    // Keyed by model name, e.g. "gemini-2.0-flash" or "sonar"
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.input_tokens += record.input_tokens;
+        self.output_tokens += record.output_tokens;
+        self.cost += record.cost.unwrap_or(0.0);
+    }
+
+    pub fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub today: UsageTotals,
+    pub month: UsageTotals,
+    // This month's totals per "provider/model"
+    pub month_by_model: BTreeMap<String, UsageTotals>,
+    pub limits: UsageLimits,
+}
+
+pub struct UsageLedger {
+    records: RwLock<Vec<UsageRecord>>,
+    settings: RwLock<UsageSettings>,
+    ledger_path: PathBuf,
+    settings_path: PathBuf,
+}
+
+impl UsageLedger {
+    // An empty ledger under `dir`, for tests
+    #[cfg(test)]
+    pub(crate) fn in_dir(dir: &std::path::Path) -> Self {
+        Self {
+            records: RwLock::new(Vec::new()),
+            settings: RwLock::new(UsageSettings::default()),
+            ledger_path: dir.join("usage_ledger.jsonl"),
+            settings_path: dir.join("usage_settings.json"),
+        }
+    }
+
+    pub fn new(app_handle: &tauri::AppHandle) -> Self {
+        let app_dir = if cfg!(feature = "portable") {
+            app_handle
+                .path_resolver()
+                .resolve_resource(".")
+                .expect("Failed to get executable directory")
+        } else {
+            app_handle
+                .path_resolver()
+                .app_config_dir()
+                .expect("Failed to get app config directory")
+        };
+
+        fs::create_dir_all(&app_dir).expect("Failed to create app config directory");
+
+        // One JSON record per line, so recording a request is a single append
+        let ledger_path = app_dir.join("usage_ledger.jsonl");
+        let records = fs::read_to_string(&ledger_path)
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let settings_path = app_dir.join("usage_settings.json");
+        let settings = fs::read_to_string(&settings_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            records: RwLock::new(records),
+            settings: RwLock::new(settings),
+            ledger_path,
+            settings_path,
+        }
+    }
+
+    // Refuse a request when any budget has already been used up
+    pub fn check_budget(&self) -> Result<(), AiError> {
+        let limits = self.settings.read().limits.clone();
+        let (today, month) = {
+            let records = self.records.read();
+            (totals_since(&records, start_of_day()), totals_since(&records, start_of_month()))
+        };
+
+        let exceeded = if limits.daily_tokens.is_some_and(|limit| today.tokens() >= limit) {
+            Some(format!("daily token budget of {} reached", limits.daily_tokens.unwrap_or(0)))
+        } else if limits.monthly_tokens.is_some_and(|limit| month.tokens() >= limit) {
+            Some(format!("monthly token budget of {} reached", limits.monthly_tokens.unwrap_or(0)))
+        } else if limits.daily_cost.is_some_and(|limit| today.cost >= limit) {
+            Some(format!("daily budget of ${:.2} reached", limits.daily_cost.unwrap_or(0.0)))
+        } else if limits.monthly_cost.is_some_and(|limit| month.cost >= limit) {
+            Some(format!("monthly budget of ${:.2} reached", limits.monthly_cost.unwrap_or(0.0)))
+        } else {
+            None
+        };
+
+        match exceeded {
+            Some(message) => Err(AiError::BudgetExceeded(message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record(&self, provider: &str, model: &str, usage: &TokenUsage, estimated: bool) {
+        let cost = self.settings.read().prices.get(model).map(|price| {
+            (usage.input_tokens as f64 * price.input + usage.output_tokens as f64 * price.output) / 1_000_000.0
+        });
+        let record = UsageRecord {
+            timestamp: Utc::now(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            estimated,
+            cost,
+        };
+
+        if let Err(e) = self.append(&record) {
+            log::warn!("Failed to write usage ledger: {}", e);
+        }
+        self.records.write().push(record);
+    }
+
+    fn append(&self, record: &UsageRecord) -> Result<(), String> {
+        let line = serde_json::to_string(record)
+            .map_err(|e| format!("Failed to serialize usage record: {}", e))?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.ledger_path)
+            .map_err(|e| format!("Failed to open usage ledger: {}", e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write usage ledger: {}", e))
+    }
+
+    pub fn summary(&self) -> UsageSummary {
+        let records = self.records.read();
+        let month_start = start_of_month();
+
+        let mut month_by_model: BTreeMap<String, UsageTotals> = BTreeMap::new();
+        for record in records.iter().filter(|r| r.timestamp >= month_start) {
+            month_by_model
+                .entry(format!("{}/{}", record.provider, record.model))
+                .or_default()
+                .add(record);
+        }
+
+        UsageSummary {
+            today: totals_since(&records, start_of_day()),
+            month: totals_since(&records, month_start),
+            month_by_model,
+            limits: self.settings.read().limits.clone(),
+        }
+    }
+
+    // Most recent first
+    pub fn records(&self, since: Option<DateTime<Utc>>, limit: usize) -> Vec<UsageRecord> {
+        self.records
+            .read()
+            .iter()
+            .rev()
+            .filter(|r| since.is_none() || since <= Some(r.timestamp))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_settings(&self) -> UsageSettings {
+        self.settings.read().clone()
+    }
+
+    pub fn set_settings(&self, settings: UsageSettings) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(&settings)
+            .map_err(|e| format!("Failed to serialize usage settings: {}", e))?;
+        fs::write(&self.settings_path, content)
+            .map_err(|e| format!("Failed to save usage settings: {}", e))?;
+        *self.settings.write() = settings;
+        Ok(())
+    }
+}
+
+fn totals_since(records: &[UsageRecord], since: DateTime<Utc>) -> UsageTotals {
+    let mut totals = UsageTotals::default();
+    for record in records.iter().filter(|r| r.timestamp >= since) {
+        totals.add(record);
+    }
+    totals
+}
+
+// Days and months follow the user's local calendar
+fn start_of_day() -> DateTime<Utc> {
+    Local::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map(|midnight| midnight.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+fn start_of_month() -> DateTime<Utc> {
+    Local::now()
+        .date_naive()
+        .with_day(1)
+        .and_then(|first| first.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map(|midnight| midnight.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+// Wraps a paid provider so every request is checked against the budget and recorded
+pub struct MeteredProvider {
+    inner: Box<dyn AiProvider>,
+    ledger: Arc<UsageLedger>,
+}
+
+impl MeteredProvider {
+    pub fn new(inner: Box<dyn AiProvider>, ledger: Arc<UsageLedger>) -> Self {
+        Self { inner, ledger }
+    }
+
+    fn record(&self, request: &ChatRequest, response: &ChatResponse) {
+        match &response.usage {
+            Some(usage) => self.ledger.record(self.inner.name(), self.inner.model(), usage, false),
+            None => self.record_estimate(request, &response.text),
+        }
+    }
+
+    fn record_estimate(&self, request: &ChatRequest, reply: &str) {
+        let usage = TokenUsage {
+            input_tokens: request.messages.iter().map(|m| estimate_tokens(&m.content) as u64).sum(),
+            output_tokens: estimate_tokens(reply) as u64,
+        };
+        self.ledger.record(self.inner.name(), self.inner.model(), &usage, true);
+    }
+}
+
+// The text a stream has delivered, recorded as estimated usage if the stream fails or is dropped
+// on cancel before it finishes: providers only report usage at the end, but the tokens are billed
+struct PartialStream<'a> {
+    metered: &'a MeteredProvider,
+    request: &'a ChatRequest,
+    // None once the stream has finished and been recorded normally
+    received: Mutex<Option<String>>,
+}
+
+impl PartialStream<'_> {
+    fn push(&self, delta: &str) {
+        if let Some(received) = self.received.lock().as_mut() {
+            received.push_str(delta);
+        }
+    }
+
+    fn finish(&self) {
+        self.received.lock().take();
+    }
+}
+
+impl Drop for PartialStream<'_> {
+    fn drop(&mut self) {
+        // Nothing received means the request was refused or never answered
+        if let Some(received) = self.received.lock().take().filter(|text| !text.is_empty()) {
+            self.metered.record_estimate(self.request, &received);
+        }
+    }
+}
+
+impl AiProvider for MeteredProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            self.ledger.check_budget()?;
+            let response = self.inner.chat(request).await?;
+            self.record(request, &response);
+            Ok(response)
+        })
+    }
+
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: DeltaSink<'a>,
+    ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+        Box::pin(async move {
+            self.ledger.check_budget()?;
+            let partial = PartialStream {
+                metered: self,
+                request,
+                received: Mutex::new(Some(String::new())),
+            };
+            let on_partial = |delta: &str| {
+                partial.push(delta);
+                on_delta(delta);
+            };
+            let response = self.inner.stream_chat(request, &on_partial).await?;
+            partial.finish();
+            self.record(request, &response);
+            Ok(response)
+        })
+    }
+}
+
+#[tauri::command]
+pub fn get_usage_summary(ledger: tauri::State<'_, Arc<UsageLedger>>) -> UsageSummary {
+    ledger.summary()
+}
+
+#[tauri::command]
+pub fn get_usage_records(
+    days: Option<i64>,
+    limit: Option<usize>,
+    ledger: tauri::State<'_, Arc<UsageLedger>>,
+) -> Vec<UsageRecord> {
+    let since = days.map(|days| Utc::now() - chrono::Duration::days(days));
+    ledger.records(since, limit.unwrap_or(500))
+}
+
+#[tauri::command]
+pub fn get_usage_settings(ledger: tauri::State<'_, Arc<UsageLedger>>) -> UsageSettings {
+    ledger.get_settings()
+}
+
+#[tauri::command]
+pub fn set_usage_settings(settings: UsageSettings, ledger: tauri::State<'_, Arc<UsageLedger>>) -> Result<(), String> {
+    ledger.set_settings(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::ChatMessage;
+
+    // Streams two deltas, then fails or never finishes
+    struct BrokenStream {
+        fail: bool,
+    }
+
+    impl AiProvider for BrokenStream {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn model(&self) -> &str {
+            "test-model"
+        }
+
+        fn chat<'a>(&'a self, _request: &'a ChatRequest) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+            Box::pin(async { Err(AiError::Network("unused".to_string())) })
+        }
+
+        fn stream_chat<'a>(
+            &'a self,
+            _request: &'a ChatRequest,
+            on_delta: DeltaSink<'a>,
+        ) -> ProviderFuture<'a, Result<ChatResponse, AiError>> {
+            Box::pin(async move {
+                on_delta("The answer ");
+                on_delta("is forty-two, give or take.");
+                if self.fail {
+                    return Err(AiError::Network("connection reset".to_string()));
+                }
+                std::future::pending().await
+            })
+        }
+    }
+
+    fn metered(fail: bool) -> (MeteredProvider, Arc<UsageLedger>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("vuno-usage-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let ledger = Arc::new(UsageLedger::in_dir(&dir));
+        (MeteredProvider::new(Box::new(BrokenStream { fail }), ledger.clone()), ledger, dir)
+    }
+
+    fn request() -> ChatRequest {
+        ChatRequest {
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: "What is the answer to everything?".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_stream_records_what_it_delivered() {
+        let (provider, ledger, dir) = metered(true);
+        let request = request();
+        assert!(provider.stream_chat(&request, &|_| {}).await.is_err());
+
+        let records = ledger.records(None, 10);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].estimated);
+        assert!(records[0].input_tokens > 0);
+        assert_eq!(records[0].output_tokens, estimate_tokens("The answer is forty-two, give or take.") as u64);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_stream_records_what_it_delivered() {
+        let (provider, ledger, dir) = metered(false);
+        let request = request();
+        let stream = provider.stream_chat(&request, &|_| {});
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(20), stream).await.is_err());
+
+        let records = ledger.records(None, 10);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].output_tokens > 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
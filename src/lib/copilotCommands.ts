@@ -15,6 +15,7 @@ export interface CopilotRange {
 }
 
 export interface InlineCompletionItem {
+  id?: string;
   insertText: string;
   range?: CopilotRange;
   command?: any;
@@ -24,15 +25,47 @@ export interface InlineCompletionList {
   items: InlineCompletionItem[];
 }
 
+export interface PanelCompletionBatch {
+  token: string;
+  items: InlineCompletionItem[];
+  done: boolean;
+}
+
+export interface InlineCompletionSelection {
+  item: InlineCompletionItem;
+  index: number;
+  total: number;
+}
+
+export interface PartialAcceptance {
+  text: string;
+  remaining: string;
+  complete: boolean;
+}
+
 export interface CopilotStatus {
   status: 'Normal' | 'Error' | 'Warning' | 'Inactive';
   message: string;
   signedIn: boolean;
+  user?: string;
+}
+
+export interface CopilotConfig {
+  enabled: boolean;
+  telemetry: boolean;
+  auto_trigger: boolean;
+  debounce_ms: number;
+  disabled_languages: string[];
+  disabled_paths: string[];
+  server_path?: string | null;
 }
 
 export interface SignInResponse {
   userCode: string;
   verificationUri: string;
+  status: 'PromptUserDeviceFlow' | 'AlreadySignedIn';
+  user?: string;
+  expiresIn?: number;
 }
 
 // Commands
@@ -59,6 +92,29 @@ export async function copilotGetStatus(): Promise<CopilotStatus> {
   return await invoke('copilot_get_status');
 }
 
+/**
+ * Get the saved Copilot settings
+ */
+export async function copilotGetConfig(): Promise<CopilotConfig> {
+  return await invoke('copilot_get_config');
+}
+
+/**
+ * Save Copilot settings and push them to the running server
+ * A changed server_path takes effect on the next start
+ * @param config The full settings object
+ */
+export async function copilotSetConfig(config: CopilotConfig): Promise<void> {
+  return await invoke('copilot_set_config', { config });
+}
+
+/**
+ * Get recent Copilot server stderr output and restart messages
+ */
+export async function copilotGetServerLog(): Promise<string[]> {
+  return await invoke('copilot_get_server_log');
+}
+
 /**
  * Initiate the Copilot sign-in flow
  * Returns a user code that should be entered at the verification URI
@@ -74,6 +130,14 @@ export async function copilotSignOut(): Promise<void> {
   return await invoke('copilot_sign_out');
 }
 
+/**
+ * Ask the server whether a stored GitHub sign-in is still valid
+ * Updates the cached status and emits `copilot_status`
+ */
+export async function copilotCheckAuth(): Promise<boolean> {
+  return await invoke('copilot_check_auth');
+}
+
 /**
  * Get inline code completions from Copilot
  * @param fileUri The file URI (e.g., "file:///path/to/file.ts")
@@ -81,13 +145,15 @@ export async function copilotSignOut(): Promise<void> {
  * @param line The cursor line (0-indexed)
  * @param character The cursor character position (0-indexed)
  * @param version The document version number
+ * @param invoked True when the user asked for a suggestion explicitly; skips debounce and works with auto-trigger off
  */
 export async function copilotGetCompletions(
   fileUri: string,
   content: string,
   line: number,
   character: number,
-  version: number
+  version: number,
+  invoked = false
 ): Promise<InlineCompletionList> {
   return await invoke('copilot_get_completions', {
     fileUri,
@@ -95,9 +161,77 @@ export async function copilotGetCompletions(
     line,
     character,
     version,
+    invoked,
   });
 }
 
+/**
+ * Get inline completions for an open buffer at its stored cursor position
+ * @param bufferId The buffer to complete in
+ * @param invoked True when the user asked for a suggestion explicitly
+ */
+export async function copilotGetBufferCompletions(bufferId: number, invoked = false): Promise<InlineCompletionList> {
+  return await invoke('copilot_get_buffer_completions', { bufferId, invoked });
+}
+
+/**
+ * Request a panel of alternative suggestions for the cursor location
+ * Items stream in as `copilot_panel_completions` events tagged with the returned token;
+ * the resolved batch holds anything not already streamed and has `done` set
+ * @param fileUri The file URI
+ * @param content The current file content
+ * @param line The cursor line (0-indexed)
+ * @param character The cursor character position (0-indexed)
+ * @param version The document version number
+ */
+export async function copilotGetPanelCompletions(
+  fileUri: string,
+  content: string,
+  line: number,
+  character: number,
+  version: number
+): Promise<PanelCompletionBatch> {
+  return await invoke('copilot_get_panel_completions', {
+    fileUri,
+    content,
+    line,
+    character,
+    version,
+  });
+}
+
+/**
+ * Cancel the panel request in flight, if any
+ */
+export async function copilotCancelPanelCompletions(): Promise<void> {
+  return await invoke('copilot_cancel_panel_completions');
+}
+
+/**
+ * Cycle to the next or previous suggestion for the last completion request
+ * @param direction 1 for next, -1 for previous
+ */
+export async function copilotCycleCompletion(direction: number): Promise<InlineCompletionSelection> {
+  return await invoke('copilot_cycle_completion', { direction });
+}
+
+/**
+ * Accept the next word or line of the current suggestion
+ * @param granularity "word" or "line"
+ */
+export async function copilotAcceptPartialCompletion(granularity: 'word' | 'line'): Promise<PartialAcceptance> {
+  return await invoke('copilot_accept_partial_completion', { granularity });
+}
+
+/**
+ * Notify Copilot that a completion was displayed (for telemetry)
+ * Nothing is sent when telemetry is disabled
+ * @param completionId The ID of the displayed completion
+ */
+export async function copilotShowCompletion(completionId: string): Promise<void> {
+  return await invoke('copilot_show_completion', { completionId });
+}
+
 /**
  * Notify Copilot that a completion was accepted (for telemetry)
  * @param completionId The ID of the accepted completion